@@ -0,0 +1,248 @@
+use parking_lot::Mutex;
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+use crate::db::DB;
+use crate::errors::Error;
+use crate::tx::Tx;
+
+use super::oneshot::{channel, Completion, Sender};
+
+type Job = Box<dyn FnOnce(&DB) + Send>;
+
+/// Call queued by AsyncDB.batch
+trait BatchCall: Send {
+    /// Runs handler within batch transaction, returns whether it succeeded
+    fn call(&mut self, tx: &mut Tx) -> bool;
+
+    /// Completes the call once batch transaction is finished
+    fn finish(self: Box<Self>, err: Option<Error>);
+}
+
+struct TypedBatchCall<T, E, F> {
+    handler: F,
+    result: Option<Result<T, E>>,
+    sender: Sender<Result<T, E>>,
+}
+
+impl<T, E, F> BatchCall for TypedBatchCall<T, E, F>
+where
+    F: Fn(&mut Tx) -> Result<T, E> + Send,
+    T: Send,
+    E: From<Error> + Send,
+{
+    fn call(&mut self, tx: &mut Tx) -> bool {
+        let result = (self.handler)(tx);
+        let ok = result.is_ok();
+        self.result = Some(result);
+        ok
+    }
+
+    fn finish(self: Box<Self>, err: Option<Error>) {
+        let result = match (self.result, err) {
+            (Some(Err(e)), _) => Err(e),
+            (_, Some(e)) => Err(e.into()),
+            (Some(Ok(value)), None) => Ok(value),
+            (None, None) => Err(Error::from("batch call result is missing").into()),
+        };
+        self.sender.send(result);
+    }
+}
+
+enum WriteJob {
+    Update(Job),
+    Batch(Box<dyn BatchCall>),
+}
+
+struct Queues {
+    writer: mpsc::Sender<WriteJob>,
+    reader: mpsc::Sender<Job>,
+}
+
+/// Asynchronous handle to the database
+///
+/// Available with `async` feature. Futures returned by its methods don't block
+/// executor thread: write transactions are queued to a dedicated writer thread
+/// which serializes them, read transactions run on a pool of reader threads.
+/// Futures don't depend on any particular runtime.
+///
+/// Batch calls queued while writer thread is busy are combined into a single
+/// transaction.
+///
+/// # Example
+///
+/// ```no_run
+/// use nut::{AsyncDB, DBBuilder, Error};
+///
+/// # async fn run() -> Result<(), Error> {
+/// let db = AsyncDB::new(DBBuilder::new("./test.db").build()?);
+/// let id = db
+///     .update(|tx| -> Result<u64, Error> {
+///         tx.create_bucket_if_not_exists(b"users")?.next_sequence()
+///     })
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct AsyncDB {
+    db: DB,
+    queues: Arc<Mutex<Queues>>,
+}
+
+impl AsyncDB {
+    /// Creates async handle with reader pool sized to available parallelism.
+    pub fn new(db: DB) -> Self {
+        let readers = thread::available_parallelism().map_or(4, |n| n.get());
+        Self::with_readers(db, readers)
+    }
+
+    /// Creates async handle with given count of reader threads.
+    pub fn with_readers(db: DB, readers: usize) -> Self {
+        let (writer_tx, writer_rx) = mpsc::channel();
+        {
+            let db = db.clone();
+            thread::spawn(move || write_loop(db, writer_rx));
+        }
+
+        let (reader_tx, reader_rx) = mpsc::channel::<Job>();
+        let reader_rx = Arc::new(Mutex::new(reader_rx));
+        for _ in 0..readers.max(1) {
+            let db = db.clone();
+            let reader_rx = reader_rx.clone();
+            thread::spawn(move || loop {
+                let job = match reader_rx.lock().recv() {
+                    Ok(job) => job,
+                    Err(_) => break,
+                };
+                job(&db);
+            });
+        }
+
+        Self {
+            db,
+            queues: Arc::new(Mutex::new(Queues {
+                writer: writer_tx,
+                reader: reader_tx,
+            })),
+        }
+    }
+
+    /// Returns underlying blocking database handle
+    pub fn db(&self) -> &DB {
+        &self.db
+    }
+
+    /// Async version of DB.update
+    ///
+    /// Handler runs on writer thread within writable transaction,
+    /// which is committed if handler returns Ok.
+    pub fn update<T, E, F>(&self, handler: F) -> Completion<Result<T, E>>
+    where
+        F: FnOnce(&mut Tx) -> Result<T, E> + Send + 'static,
+        T: Send + 'static,
+        E: From<Error> + Send + 'static,
+    {
+        let (sender, receiver) = channel();
+        let job: Job = Box::new(move |db: &DB| sender.send(db.update(handler)));
+        let _ = self.queues.lock().writer.send(WriteJob::Update(job));
+        receiver
+    }
+
+    /// Async version of DB.view
+    ///
+    /// Handler runs on one of reader threads within read-only transaction.
+    pub fn view<T, E, F>(&self, handler: F) -> Completion<Result<T, E>>
+    where
+        F: FnOnce(&Tx) -> Result<T, E> + Send + 'static,
+        T: Send + 'static,
+        E: From<Error> + Send + 'static,
+    {
+        let (sender, receiver) = channel();
+        let job: Job = Box::new(move |db: &DB| sender.send(db.view(handler)));
+        let _ = self.queues.lock().reader.send(job);
+        receiver
+    }
+
+    /// Async version of DB.batch
+    ///
+    /// Calls waiting in writer queue are run in a single transaction.
+    /// As with DB.batch, handler may be called multiple times if some
+    /// other call of the batch fails, so it must be idempotent.
+    pub fn batch<T, E, F>(&self, handler: F) -> Completion<Result<T, E>>
+    where
+        F: Fn(&mut Tx) -> Result<T, E> + Send + 'static,
+        T: Send + 'static,
+        E: From<Error> + Send + 'static,
+    {
+        let (sender, receiver) = channel();
+        let call = TypedBatchCall {
+            handler,
+            result: None,
+            sender,
+        };
+        let _ = self
+            .queues
+            .lock()
+            .writer
+            .send(WriteJob::Batch(Box::new(call)));
+        receiver
+    }
+}
+
+fn write_loop(db: DB, jobs: mpsc::Receiver<WriteJob>) {
+    // job received while collecting batch calls
+    let mut deferred = None;
+    loop {
+        let job = match deferred.take() {
+            Some(job) => job,
+            None => match jobs.recv() {
+                Ok(job) => job,
+                Err(_) => break,
+            },
+        };
+        match job {
+            WriteJob::Update(job) => job(&db),
+            WriteJob::Batch(call) => {
+                let max_size = db.0.max_batch_size;
+                let mut calls = vec![call];
+                while max_size == 0 || calls.len() < max_size {
+                    match jobs.try_recv() {
+                        Ok(WriteJob::Batch(call)) => calls.push(call),
+                        Ok(job) => {
+                            deferred = Some(job);
+                            break;
+                        }
+                        Err(_) => break,
+                    }
+                }
+                run_batch(&db, calls);
+            }
+        }
+    }
+}
+
+/// Runs calls in a single transaction. Failed call is removed
+/// and completed with its error, the rest is retried.
+fn run_batch(db: &DB, mut calls: Vec<Box<dyn BatchCall>>) {
+    while !calls.is_empty() {
+        let mut last_call_id = 0;
+        let result = db.update(|tx| -> Result<(), Error> {
+            for (index, call) in calls.iter_mut().enumerate() {
+                last_call_id = index;
+                if !call.call(tx) {
+                    return Err("batch call failed".into());
+                }
+            }
+            Ok(())
+        });
+        match result {
+            Ok(()) => {
+                for call in calls.drain(..) {
+                    call.finish(None);
+                }
+            }
+            Err(e) => calls.remove(last_call_id).finish(Some(e)),
+        }
+    }
+}
@@ -0,0 +1,8 @@
+#[cfg(test)]
+mod tests;
+
+mod asyncdb;
+mod oneshot;
+
+pub use asyncdb::AsyncDB;
+pub use oneshot::Completion;
@@ -0,0 +1,65 @@
+use parking_lot::Mutex;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+
+use crate::errors::Error;
+
+struct Slot<T> {
+    value: Option<T>,
+    waker: Option<Waker>,
+    closed: bool,
+}
+
+/// Sending half, owned by worker thread
+pub(super) struct Sender<T>(Arc<Mutex<Slot<T>>>);
+
+/// Future resolving once worker thread completes the job
+///
+/// Resolves to Error::DatabaseGone if worker dropped the job without result.
+pub struct Completion<T>(Arc<Mutex<Slot<T>>>);
+
+pub(super) fn channel<T>() -> (Sender<T>, Completion<T>) {
+    let slot = Arc::new(Mutex::new(Slot {
+        value: None,
+        waker: None,
+        closed: false,
+    }));
+    (Sender(slot.clone()), Completion(slot))
+}
+
+impl<T> Sender<T> {
+    pub(super) fn send(self, value: T) {
+        self.0.lock().value = Some(value);
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let waker = {
+            let mut slot = self.0.lock();
+            slot.closed = true;
+            slot.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T, E: From<Error>> Future for Completion<Result<T, E>> {
+    type Output = Result<T, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut slot = self.0.lock();
+        if let Some(value) = slot.value.take() {
+            return Poll::Ready(value);
+        }
+        if slot.closed {
+            return Poll::Ready(Err(Error::DatabaseGone.into()));
+        }
+        slot.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
@@ -0,0 +1,153 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake};
+use std::thread::{self, Thread};
+
+use super::AsyncDB;
+use crate::db::tests::db_mock;
+use crate::db::CheckMode;
+use crate::errors::Error;
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = Box::pin(future);
+    let waker = Arc::new(ThreadWaker(thread::current())).into();
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(v) => return v,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+#[test]
+fn update_and_view() {
+    let db = AsyncDB::with_readers(db_mock().build().unwrap(), 2);
+
+    let seq = block_on(db.update(|tx| -> Result<u64, Error> {
+        let mut bucket = tx.create_bucket(b"bucket")?;
+        bucket.put(b"key", b"value".to_vec())?;
+        bucket.next_sequence()
+    }))
+    .unwrap();
+    assert_eq!(seq, 1);
+
+    let value = block_on(db.view(|tx| -> Result<Vec<u8>, Error> {
+        Ok(tx.bucket(b"bucket")?.get(b"key").unwrap().to_vec())
+    }))
+    .unwrap();
+    assert_eq!(value, b"value");
+
+    let result = block_on(db.update(|tx| -> Result<(), Error> {
+        tx.create_bucket(b"bucket")?.put(b"other", vec![1])?;
+        Err(Error::Invalid)
+    }));
+    assert!(result.is_err());
+    let found =
+        block_on(db.view(|tx| -> Result<bool, Error> {
+            Ok(tx.bucket(b"bucket")?.get(b"other").is_some())
+        }))
+        .unwrap();
+    assert!(!found);
+}
+
+#[test]
+fn panic_in_handler() {
+    let db = AsyncDB::with_readers(db_mock().build().unwrap(), 1);
+    let result = block_on(db.update(|_| -> Result<(), Error> { panic!("boom") }));
+    assert!(result.is_err());
+
+    // writer thread survives the panic
+    block_on(db.update(|tx| -> Result<(), Error> {
+        let _ = tx.create_bucket(b"bucket")?;
+        Ok(())
+    }))
+    .unwrap();
+}
+
+#[test]
+fn batch() {
+    let db = AsyncDB::with_readers(db_mock().build().unwrap(), 1);
+    block_on(db.update(|tx| -> Result<(), Error> {
+        let _ = tx.create_bucket(b"bucket")?;
+        Ok(())
+    }))
+    .unwrap();
+
+    let futures: Vec<_> = (0..20u32)
+        .map(|i| {
+            db.batch(move |tx| -> Result<u32, Error> {
+                if i == 7 {
+                    return Err(Error::Invalid);
+                }
+                tx.bucket_mut(b"bucket")?
+                    .put(&i.to_be_bytes(), b"value".to_vec())?;
+                Ok(i)
+            })
+        })
+        .collect();
+
+    for (i, future) in futures.into_iter().enumerate() {
+        let result = block_on(future);
+        if i == 7 {
+            assert!(matches!(result, Err(Error::Invalid)));
+        } else {
+            assert_eq!(result.unwrap(), i as u32);
+        }
+    }
+
+    let count = block_on(db.view(|tx| -> Result<usize, Error> {
+        let mut count = 0;
+        tx.bucket(b"bucket")?
+            .for_each(Box::new(|_, _| -> Result<(), Error> {
+                count += 1;
+                Ok(())
+            }))?;
+        Ok(count)
+    }))
+    .unwrap();
+    assert_eq!(count, 19);
+}
+
+#[test]
+fn concurrent_tasks() {
+    // read check may fail while parallel write transaction frees pages
+    let db = db_mock().checkmode(CheckMode::STRONG).build().unwrap();
+    let db = AsyncDB::new(db);
+    block_on(db.update(|tx| -> Result<(), Error> {
+        let _ = tx.create_bucket(b"bucket")?;
+        Ok(())
+    }))
+    .unwrap();
+
+    let handles: Vec<_> = (0..8u32)
+        .map(|t| {
+            let db = db.clone();
+            thread::spawn(move || {
+                for i in 0..20u32 {
+                    let key = (t * 100 + i).to_be_bytes();
+                    block_on(db.update(move |tx| -> Result<(), Error> {
+                        tx.bucket_mut(b"bucket")?.put(&key, vec![0; 10])
+                    }))
+                    .unwrap();
+                    let found = block_on(db.view(move |tx| -> Result<bool, Error> {
+                        Ok(tx.bucket(b"bucket")?.get(&key).is_some())
+                    }))
+                    .unwrap();
+                    assert!(found);
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
@@ -2,7 +2,7 @@
 
 use clap::{App, Arg, SubCommand};
 use hexdump::{hexdump_iter, sanitize_byte};
-use nut::{Bucket, BucketStats, DBBuilder, DB};
+use nut::{compact, Bucket, BucketStats, CompactOptions, DBBuilder, DB};
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::PathBuf;
 
@@ -109,6 +109,60 @@ a single page to take up multiple blocks."#,
 				"Runs an exhaustive check to verify that all pages are accessible or are marked as freed.",
 			)
 			.args(&[path_arg.clone()]),
+		SubCommand::with_name("compact")
+			.about("Copies database into a new densely packed file")
+			.long_about(
+				r#"Copies all buckets of the database into a new file, packing pages densely.
+Reclaims space of freed pages, which database file keeps after deletions.
+Bucket nesting, sequences and comparators are preserved.
+Buckets with custom comparators can't be compacted with this command."#,
+			)
+			.args(&[
+				Arg::with_name("output")
+					.value_name("FILE")
+					.short("o")
+					.long("output")
+					.help("path to new database")
+					.required(true)
+					.takes_value(true),
+				Arg::with_name("tx-max-size")
+					.value_name("BYTES")
+					.long("tx-max-size")
+					.takes_value(true)
+					.default_value("65536")
+					.validator(is_numeric)
+					.help("size of data copied per transaction, 0 copies everything at once"),
+				path_arg.clone(),
+			]),
+		SubCommand::with_name("backup")
+			.about("Copies database into a new file")
+			.long_about(
+				r#"Copies database into a new file from a read transaction.
+Database can be used by other processes while backup is in progress.
+Progress is printed to stderr."#,
+			)
+			.args(&[
+				Arg::with_name("output")
+					.value_name("FILE")
+					.short("o")
+					.long("output")
+					.help("path to backup file, replaced if exists")
+					.required(true)
+					.takes_value(true),
+				Arg::with_name("rate-limit")
+					.value_name("BYTES")
+					.long("rate-limit")
+					.takes_value(true)
+					.default_value("0")
+					.validator(is_numeric)
+					.help("bytes copied per second, 0 disables the limit"),
+				Arg::with_name("sync")
+					.long("sync")
+					.possible_values(&["true", "false"])
+					.default_value("true")
+					.help("sync backup file to disk"),
+				path_arg.clone(),
+			]),
 	]);
 
     let matches = app.clone().get_matches();
@@ -142,6 +196,25 @@ a single page to take up multiple blocks."#,
         ("check", Some(args)) => check(CheckOptions {
             path: PathBuf::from(args.value_of("path").unwrap()),
         }),
+        ("compact", Some(args)) => compact_db(CompactDBOptions {
+            path: PathBuf::from(args.value_of("path").unwrap()),
+            output: PathBuf::from(args.value_of("output").unwrap()),
+            tx_max_size: args
+                .value_of("tx-max-size")
+                .map(str::parse::<u64>)
+                .unwrap()
+                .unwrap(),
+        }),
+        ("backup", Some(args)) => backup(BackupOptions {
+            path: PathBuf::from(args.value_of("path").unwrap()),
+            output: PathBuf::from(args.value_of("output").unwrap()),
+            rate_limit: args
+                .value_of("rate-limit")
+                .map(str::parse::<u64>)
+                .unwrap()
+                .unwrap(),
+            sync: args.value_of("sync").unwrap() == "true",
+        }),
         _ => {
             app.print_long_help().unwrap();
             Ok(())
@@ -246,6 +319,65 @@ fn check(o: CheckOptions) -> Result<(), String> {
     Ok(())
 }
 
+struct CompactDBOptions {
+    path: PathBuf,
+    output: PathBuf,
+    tx_max_size: u64,
+}
+
+fn compact_db(o: CompactDBOptions) -> Result<(), String> {
+    let db = DBBuilder::new(&o.path).read_only(true).build()?;
+    let stats = compact(
+        &db,
+        &o.output,
+        CompactOptions::new().tx_max_size(o.tx_max_size),
+    )?;
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    let ratio = if stats.src_size == 0 {
+        0.0
+    } else {
+        stats.dst_size as f64 / stats.src_size as f64 * 100.0
+    };
+    writeln!(
+        &mut stdout,
+        "{} -> {} bytes ({:.1}%)",
+        stats.src_size, stats.dst_size, ratio
+    )
+    .map_err(|_| "Can't write output")?;
+    Ok(())
+}
+
+struct BackupOptions {
+    path: PathBuf,
+    output: PathBuf,
+    rate_limit: u64,
+    sync: bool,
+}
+
+fn backup(o: BackupOptions) -> Result<(), String> {
+    let db = DBBuilder::new(&o.path).read_only(true).build()?;
+    let tx = db.begin_tx()?;
+    let written = tx
+        .backup()
+        .rate_limit(o.rate_limit)
+        .sync(o.sync)
+        .progress(|copied, total| {
+            eprint!(
+                "\r{}/{} pages ({:.1}%)",
+                copied,
+                total,
+                copied as f64 / total as f64 * 100.0
+            );
+        })
+        .copy_to(&o.output)?;
+    eprintln!();
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    writeln!(&mut stdout, "{} bytes written", written).map_err(|_| "Can't write output")?;
+    Ok(())
+}
+
 struct InfoOptions {
     path: PathBuf,
     check: bool,
@@ -257,8 +389,13 @@ fn info(o: InfoOptions) -> Result<(), String> {
         let stdout = std::io::stdout();
         let mut stdout = stdout.lock();
         writeln!(&mut stdout, "Page size: {}", db.page_size()).map_err(|_| "Can't write output")?;
-        writeln!(&mut stdout, "Freelist id: {}", db.meta()?.freelist)
-            .map_err(|_| "Can't write output")?;
+        let meta = db.meta()?;
+        if meta.has_freelist() {
+            writeln!(&mut stdout, "Freelist id: {}", meta.freelist)
+        } else {
+            writeln!(&mut stdout, "Freelist id: none, rebuilt on open")
+        }
+        .map_err(|_| "Can't write output")?;
 
         {
             let tx = db.begin_tx()?;
@@ -377,7 +514,12 @@ fn tree_writer<'a>(
         let buckets = ubucket.buckets();
 
         for b_name in buckets {
-            tree_writer(indent_level + 1, &mut out, &b_name, ubucket.bucket(&b_name))?;
+            tree_writer(
+                indent_level + 1,
+                &mut out,
+                &b_name,
+                ubucket.bucket(&b_name).ok(),
+            )?;
         }
     };
 
@@ -1,22 +1,36 @@
 use either::Either;
+use std::borrow::Cow;
 use std::cell::RefCell;
+use std::cmp::Ordering;
 use std::collections::{hash_map::Entry, HashMap};
+use std::convert::TryFrom;
 use std::fmt;
+use std::ops::{Bound, RangeBounds};
 use std::{isize, usize};
 
-use crate::consts::{Flags, PGID};
+use crate::consts::{Flags, COMPARATOR_VERSION, PGID};
 use crate::db::DB;
 use crate::errors::Error;
 use crate::node::{Node, NodeBuilder, WeakNode};
 use crate::page::{BranchPageElement, LeafPageElement, OwnedPage, Page};
 use crate::tx::{Tx, WeakTx};
+use crate::utils::{clamp, prefix_end};
 
 use super::consts::*;
 use super::BucketStats;
+use super::Comparator;
 use super::Cursor;
 use super::IBucket;
+use super::Iter;
 use super::PageNode;
 
+/// Change of a value made by Bucket.modify
+enum Modify {
+    Keep,
+    Put(Vec<u8>),
+    Delete,
+}
+
 /// Bucket represents a collection of key/value pairs inside the database.
 pub struct Bucket {
     /// ref to on-file representation of a bucket
@@ -43,6 +57,18 @@ pub struct Bucket {
     ///
     /// This is non-persisted across transactions so it must be set in every Tx.
     pub(crate) fill_percent: f64,
+
+    /// id of key comparator, 0 for bytewise order
+    comparator_id: u32,
+
+    /// key comparator, None for bytewise order or if comparator is not registered
+    comparator: Option<Comparator>,
+
+    /// names of secondary indexes, None until looked up
+    pub(super) indexes: RefCell<Option<Vec<Vec<u8>>>>,
+
+    /// true for nested bucket holding secondary indexes of its parent
+    pub(crate) holds_indexes: bool,
 }
 
 impl fmt::Debug for Bucket {
@@ -57,6 +83,8 @@ impl fmt::Debug for Bucket {
             .field("root_node", &self.root_node)
             .field("nodes", &*self.nodes.borrow())
             .field("fill_percent", &self.fill_percent)
+            .field("comparator_id", &self.comparator_id)
+            .field("holds_indexes", &self.holds_indexes)
             .finish()
     }
 }
@@ -67,8 +95,17 @@ impl Bucket {
 
     pub(crate) const DEFAULT_FILL_PERCENT: f64 = 0.5;
 
+    /// count of pairs bulk_load buffers before appending them to the leaf
+    const LOAD_BATCH_SIZE: usize = 1024;
+
     pub(crate) const FLAG: u32 = 0x01;
 
+    /// marks bucket element which header is followed by comparator id
+    pub(crate) const COMPARATOR_FLAG: u32 = 0x02;
+
+    /// marks bucket element holding secondary indexes of the parent bucket
+    pub(crate) const INDEXES_FLAG: u32 = 0x04;
+
     pub(crate) fn new(tx: WeakTx) -> Self {
         Self {
             bucket: IBucket::new(),
@@ -78,6 +115,10 @@ impl Bucket {
             root_node: None,
             nodes: RefCell::new(HashMap::new()),
             fill_percent: Self::DEFAULT_FILL_PERCENT,
+            comparator_id: 0,
+            comparator: None,
+            indexes: RefCell::new(None),
+            holds_indexes: false,
         }
     }
 
@@ -100,6 +141,57 @@ impl Bucket {
         self.root_node.clone()
     }
 
+    /// Returns id of the bucket's key comparator, 0 for bytewise order
+    pub fn comparator_id(&self) -> u32 {
+        self.comparator_id
+    }
+
+    /// Compares keys in the bucket's order
+    #[inline]
+    pub(crate) fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        match self.comparator {
+            Some(cmp) => cmp(a, b),
+            None => a.cmp(b),
+        }
+    }
+
+    /// Returns true if key found by cursor is equal to given key
+    #[inline]
+    pub(super) fn is_key(&self, found: Option<&[u8]>, key: &[u8]) -> bool {
+        matches!(found, Some(found) if self.compare(found, key) == Ordering::Equal)
+    }
+
+    /// Returns flags of the bucket's element in parent bucket
+    fn element_flags(&self) -> u32 {
+        let mut flags = Self::FLAG;
+        if self.comparator_id != 0 {
+            flags |= Self::COMPARATOR_FLAG;
+        }
+        if self.holds_indexes {
+            flags |= Self::INDEXES_FLAG;
+        }
+        flags
+    }
+
+    /// Returns size of the bucket's header in parent bucket
+    fn header_size(&self) -> usize {
+        if self.comparator_id == 0 {
+            IBucket::SIZE
+        } else {
+            IBucket::SIZE + IBucket::COMPARATOR_SIZE
+        }
+    }
+
+    /// Serializes the bucket's header
+    fn write_header(&self, value: &mut [u8]) {
+        let bucket_ptr = value.as_mut_ptr() as *mut IBucket;
+        unsafe { std::ptr::copy_nonoverlapping(&self.bucket, bucket_ptr, 1) };
+        if self.comparator_id != 0 {
+            value[IBucket::SIZE..self.header_size()]
+                .copy_from_slice(&u64::from(self.comparator_id).to_le_bytes());
+        }
+    }
+
     /// Creates a cursor associated with the bucket.
     pub fn cursor(&self) -> Result<Cursor<&Bucket>, Error> {
         self.tx()?.0.stats.lock().cursor_count += 1;
@@ -107,27 +199,35 @@ impl Bucket {
         Ok(Cursor::new(self))
     }
 
-    fn __bucket(&self, name: &[u8]) -> Option<*mut Bucket> {
+    fn __bucket(&self, name: &[u8]) -> Result<Option<*mut Bucket>, Error> {
         if let Some(b) = self.buckets.borrow_mut().get_mut(name) {
-            return Some(b);
+            return Ok(Some(b));
         };
-        let (key, value) = {
-            let c = self.cursor().unwrap();
-            let (key, value, flags) = c.seek_to_item(name).unwrap().unwrap();
+        let (key, value, flags) = {
+            let c = self.cursor()?;
+            let (key, value, flags) = c.seek_to_item(name)?.unwrap();
 
             // Return None if the key doesn't exist or it is not a bucket.
-            if key != Some(name) || (flags & Self::FLAG) == 0 {
-                return None;
+            if !self.is_key(key, name) || (flags & Self::FLAG) == 0 {
+                return Ok(None);
             };
 
-            (key.map(|k| k.to_vec()), value.map(|v| v.to_vec()))
+            (key.unwrap().to_vec(), value.unwrap().to_vec(), flags)
+        };
+
+        // Name may differ from the stored key under custom comparator.
+        if let Some(b) = self.buckets.borrow_mut().get_mut(&key) {
+            return Ok(Some(b));
         };
 
         // Otherwise create a bucket and cache it.
-        let child = self.open_bucket(value.unwrap());
+        let child = self.open_bucket(value, flags)?;
+        if child.comparator_id != 0 && child.comparator.is_none() {
+            return Err(Error::ComparatorNotFound(child.comparator_id));
+        }
 
         let mut buckets = self.buckets.borrow_mut();
-        let bucket = match buckets.entry(key.unwrap()) {
+        let bucket = match buckets.entry(key) {
             Entry::Vacant(e) => e.insert(child),
             Entry::Occupied(e) => {
                 let c = e.into_mut();
@@ -135,29 +235,49 @@ impl Bucket {
                 c
             }
         };
-        Some(bucket)
+        Ok(Some(bucket))
     }
 
     /// Retrieves a nested bucket by name.
-    /// Returns None if the bucket does not exist or found item is not bucket.
-    pub fn bucket(&self, key: &[u8]) -> Option<&Bucket> {
-        self.__bucket(key).map(|b| unsafe { &*b })
+    /// Returns BucketNotFound error if the bucket does not exist or found item is not bucket,
+    /// and ComparatorNotFound error if the bucket's key comparator is not registered.
+    pub fn bucket(&self, key: &[u8]) -> Result<&Bucket, Error> {
+        match self.try_bucket(key)? {
+            Some(child) if !child.holds_indexes => Ok(child),
+            _ => Err(Error::BucketNotFound),
+        }
+    }
+
+    /// Retrieves a nested bucket by name, or None if it does not exist.
+    /// Returns ComparatorNotFound error if the bucket's key comparator is not registered.
+    pub(crate) fn try_bucket(&self, key: &[u8]) -> Result<Option<&Bucket>, Error> {
+        Ok(self.__bucket(key)?.map(|b| unsafe { &*b }))
     }
 
     /// Retrieves a nested mutable bucket by name.
-    /// Returns None if the bucket does not exist or found item is not bucket.
-    pub fn bucket_mut(&mut self, key: &[u8]) -> Option<&mut Bucket> {
-        if !self.tx().unwrap().writable() {
-            return None;
+    /// Returns same errors as bucket and TxReadonly error if the transaction is read-only.
+    pub fn bucket_mut(&mut self, key: &[u8]) -> Result<&mut Bucket, Error> {
+        match self.try_bucket_mut(key)? {
+            Some(child) if !child.holds_indexes => Ok(child),
+            _ => Err(Error::BucketNotFound),
+        }
+    }
+
+    /// Retrieves a nested mutable bucket by name, or None if it does not exist.
+    /// Returns ComparatorNotFound error if the bucket's key comparator is not registered.
+    pub(crate) fn try_bucket_mut(&mut self, key: &[u8]) -> Result<Option<&mut Bucket>, Error> {
+        if !self.tx()?.writable() {
+            return Err(Error::TxReadonly);
         };
-        self.__bucket(key).map(|b| unsafe { &mut *b })
+        Ok(self.__bucket(key)?.map(|b| unsafe { &mut *b }))
     }
 
     /// Helper method that re-interprets a sub-bucket value
     /// from a parent into a Bucket
     ///
-    /// value is bytes serialized bucket
-    pub(crate) fn open_bucket(&self, value: Vec<u8>) -> Bucket {
+    /// value is bytes serialized bucket, flags are flags of its element.
+    /// Key comparator is left unset if it's not registered.
+    pub(crate) fn open_bucket(&self, value: Vec<u8>, flags: u32) -> Result<Bucket, Error> {
         let mut child = Bucket::new(self.tx.clone());
         // let value = unsafe { value.as_ref().unwrap() };
 
@@ -180,10 +300,25 @@ impl Bucket {
             child.bucket = b;
         }
 
+        if flags & Self::COMPARATOR_FLAG != 0 {
+            let mut id = [0u8; IBucket::COMPARATOR_SIZE];
+            id.copy_from_slice(&value[IBucket::SIZE..IBucket::SIZE + IBucket::COMPARATOR_SIZE]);
+            let id = u64::from_le_bytes(id);
+            child.comparator_id = u32::try_from(id).map_err(|_| Error::Corruption {
+                pgid: self.bucket.root,
+                reason: format!("comparator id {} out of range", id),
+            })?;
+            child.comparator = self
+                .db()
+                .ok()
+                .and_then(|db| db.comparator(child.comparator_id));
+        }
+        child.holds_indexes = flags & Self::INDEXES_FLAG != 0;
+
         // Save a reference to the inline page if the bucket is inline.
         if child.bucket.root == 0 {
             let data = unsafe {
-                let slice = &value[IBucket::SIZE..];
+                let slice = &value[child.header_size()..];
                 let mut vec = vec![0u8; slice.len()];
                 std::ptr::copy_nonoverlapping(slice.as_ptr(), vec.as_mut_ptr(), slice.len());
                 vec
@@ -193,7 +328,7 @@ impl Bucket {
             child.page = Some(p);
         }
 
-        child
+        Ok(child)
     }
 
     pub(crate) fn clear(&mut self) {
@@ -205,6 +340,30 @@ impl Bucket {
 
     /// Creates bucket
     pub fn create_bucket(&mut self, key: &[u8]) -> Result<&mut Bucket, Error> {
+        self.create_bucket_with_comparator(key, 0)
+    }
+
+    /// Creates bucket which keeps keys in order of comparator
+    /// registered under given id with DBBuilder.comparator.
+    /// Id 0 stands for default bytewise order.
+    /// Database with such buckets gets format version 3, which older versions refuse to open.
+    ///
+    /// Returns ComparatorNotFound error if comparator is not registered.
+    pub fn create_bucket_with_comparator(
+        &mut self,
+        key: &[u8],
+        comparator_id: u32,
+    ) -> Result<&mut Bucket, Error> {
+        self.create_nested(key, comparator_id, false)
+    }
+
+    /// Creates nested bucket, which holds secondary indexes of the bucket if holds_indexes is set.
+    pub(crate) fn create_nested(
+        &mut self,
+        key: &[u8],
+        comparator_id: u32,
+        holds_indexes: bool,
+    ) -> Result<&mut Bucket, Error> {
         {
             let tx = self.tx()?;
             if !tx.opened() {
@@ -218,6 +377,14 @@ impl Bucket {
             }
         }
 
+        let comparator = match comparator_id {
+            0 => None,
+            id => Some(
+                self.db()?
+                    .comparator(id)
+                    .ok_or(Error::ComparatorNotFound(id))?,
+            ),
+        };
         let tx_clone = self.tx.clone();
 
         {
@@ -227,7 +394,7 @@ impl Bucket {
                 (key, flags)
             };
 
-            if ckey == Some(key) {
+            if self.is_key(ckey, key) {
                 if (flags & Self::FLAG) != 0 {
                     return Err(Error::BucketExists);
                 };
@@ -237,23 +404,28 @@ impl Bucket {
             let mut bucket = Bucket::new(tx_clone);
             bucket.root_node = Some(NodeBuilder::new(&bucket).is_leaf(true).build());
             bucket.fill_percent = Self::DEFAULT_FILL_PERCENT;
+            bucket.comparator_id = comparator_id;
+            bucket.comparator = comparator;
+            bucket.holds_indexes = holds_indexes;
+            if comparator_id != 0 {
+                let tx = self.tx()?;
+                tx.0.meta.try_write().ok_or("meta locked")?.version = COMPARATOR_VERSION;
+            }
 
             let value = bucket.write();
-            cursor.node().unwrap().put(key, key, value, 0, Self::FLAG);
+            let flags = bucket.element_flags();
+            cursor.node().unwrap().put(key, key, value, 0, flags);
             self.page = None;
         }
 
-        self.bucket_mut(key)
-            .ok_or_else(|| Error::Unexpected("cannot find bucket".to_string()))
+        self.try_bucket_mut(key)?.ok_or(Error::BucketNotFound)
     }
 
     /// Creates bucket if it not exists
     pub fn create_bucket_if_not_exists(&mut self, key: &[u8]) -> Result<&mut Bucket, Error> {
         match unsafe { &mut *(self as *mut Self) }.create_bucket(key) {
             Ok(b) => Ok(b),
-            Err(Error::BucketExists) => self
-                .bucket_mut(key)
-                .ok_or_else(|| Error::Unexpected("can't find bucket".to_string())),
+            Err(Error::BucketExists) => self.bucket_mut(key),
             v => v,
         }
     }
@@ -262,6 +434,12 @@ impl Bucket {
     ///
     /// Returns error if bucket not found or value is not bucket
     pub fn delete_bucket(&mut self, key: &[u8]) -> Result<(), Error> {
+        self.delete_nested(key, false)
+    }
+
+    /// Removes nested bucket, which must hold secondary indexes of the bucket
+    /// if holds_indexes is set and must not otherwise.
+    pub(super) fn delete_nested(&mut self, key: &[u8], holds_indexes: bool) -> Result<(), Error> {
         {
             let tx = self.tx()?;
             if !tx.opened() {
@@ -278,20 +456,34 @@ impl Bucket {
         let mut c = self.cursor()?;
         {
             let item = c.seek(key)?;
-            if item.key.as_deref().map(|v| &*v).unwrap() != key {
+            if !self.is_key(item.key, key) {
                 return Err(Error::BucketNotFound);
             }
             if !item.is_bucket() {
                 return Err(Error::IncompatibleValue);
             }
+            if (item.flags & Self::INDEXES_FLAG != 0) != holds_indexes {
+                return Err(Error::BucketNotFound);
+            }
         }
         let mut node = c.node()?;
         {
-            let child = self.bucket_mut(key).ok_or("Can't get bucket")?;
-            let child_buckets = child.buckets();
+            let child = self.try_bucket_mut(key)?.ok_or("Can't get bucket")?;
+            let child_buckets: Vec<(Vec<u8>, bool)> = {
+                let c = child.cursor()?;
+                let mut item = c.first()?;
+                let mut buckets = vec![];
+                while let Some(key) = item.key {
+                    if item.is_bucket() {
+                        buckets.push((key.to_vec(), item.flags & Self::INDEXES_FLAG != 0));
+                    }
+                    item = c.next()?;
+                }
+                buckets
+            };
 
-            for bucket in &child_buckets {
-                child.delete_bucket(bucket)?;
+            for (bucket, holds_indexes) in &child_buckets {
+                child.delete_nested(bucket, *holds_indexes)?;
             }
 
             // Release all bucket pages to freelist.
@@ -300,7 +492,10 @@ impl Bucket {
             child.free();
         }
 
-        self.buckets.borrow_mut().remove(key);
+        // Name may differ from the stored key under custom comparator.
+        self.buckets
+            .borrow_mut()
+            .retain(|name, _| self.compare(name, key) != Ordering::Equal);
         node.del(key);
 
         Ok(())
@@ -343,7 +538,7 @@ impl Bucket {
         }
 
         // If our target node isn't the same key as what's passed in then return nil.
-        if ckey != Some(key) {
+        if !self.is_key(ckey, key) {
             return None;
         }
 
@@ -352,6 +547,7 @@ impl Bucket {
 
     /// Sets the value for a key in the bucket.
     /// If the key exist then its previous value will be overwritten.
+    /// Value can be either owned or borrowed, owned value is stored without copying.
     /// Returns an error if the bucket was created from a read-only transaction, if the key is blank, if the key is too large, or if the value is too large.
     ///
     /// # Example
@@ -359,42 +555,371 @@ impl Bucket {
     /// ```no_run
     /// use nut::DBBuilder;
     ///
-    /// let mut db = DBBuilder::new("./test.db").build().unwrap();
+    /// let db = DBBuilder::new("./test.db").build().unwrap();
     /// let mut tx = db.begin_rw_tx().unwrap();
     /// let mut flowers = tx.create_bucket(b"flowers").unwrap();
     ///
-    /// flowers.put(b"irises", b"Iris is a genus of species of flowering plants".to_vec()).unwrap()
+    /// flowers.put(b"irises", b"Iris is a genus of species of flowering plants").unwrap();
+    /// flowers.put(b"roses", b"Rosa".to_vec()).unwrap();
+    /// ```
+    pub fn put<'v>(&mut self, key: &[u8], value: impl Into<Cow<'v, [u8]>>) -> Result<(), Error> {
+        let value = value.into();
+        self.check_put(key, value.len())?;
+        let changes = self.index_write(key, Some(&value))?;
+
+        // Insert into node.
+        self.put_node(key)?.put(key, key, value, 0, 0);
+
+        self.apply_index_changes(changes)
+    }
+
+    /// Sets value of given length for a key and returns it to be filled in place,
+    /// which saves serializing value into separate buffer.
+    /// Value is zeroed, previous value of existing key is overwritten.
+    /// Returns same errors as put and IndexedBucket error if the bucket has secondary indexes,
+    /// which can't be updated before value is filled.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use nut::DBBuilder;
+    ///
+    /// let db = DBBuilder::new("./test.db").build().unwrap();
+    /// let mut tx = db.begin_rw_tx().unwrap();
+    /// let mut counters = tx.create_bucket(b"counters").unwrap();
+    ///
+    /// counters.put_reserve(b"visits", 8).unwrap().copy_from_slice(&42u64.to_be_bytes());
+    /// ```
+    pub fn put_reserve(&mut self, key: &[u8], len: usize) -> Result<&mut [u8], Error> {
+        self.check_put(key, len)?;
+        if !self.index_names().is_empty() {
+            return Err(Error::IndexedBucket);
+        }
+
+        let mut node = self.put_node(key)?;
+        let value: *mut [u8] = &mut *node.put_reserve(key, len);
+        // Node is cached by bucket, so its value lives as long as bucket is borrowed.
+        Ok(unsafe { &mut *value })
+    }
+
+    /// Replaces value of a key only if its current value equals expected one,
+    /// None stands for missing key in both expected and new value, so None new value deletes the key.
+    /// Returns true if value was swapped.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use nut::DBBuilder;
+    ///
+    /// let db = DBBuilder::new("./test.db").build().unwrap();
+    /// let mut tx = db.begin_rw_tx().unwrap();
+    /// let mut locks = tx.create_bucket(b"locks").unwrap();
+    ///
+    /// assert!(locks.compare_and_swap(b"job", None, Some(b"worker-1".to_vec())).unwrap());
+    /// assert!(!locks.compare_and_swap(b"job", None, Some(b"worker-2".to_vec())).unwrap());
+    /// ```
+    pub fn compare_and_swap(
+        &mut self,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        new: Option<Vec<u8>>,
+    ) -> Result<bool, Error> {
+        let mut swapped = false;
+        self.modify(key, |current| {
+            if current != expected {
+                return Modify::Keep;
+            }
+            swapped = true;
+            match new {
+                Some(value) => Modify::Put(value),
+                None => Modify::Delete,
+            }
+        })?;
+        Ok(swapped)
+    }
+
+    /// Sets value of a key to the result of a function called with its current value,
+    /// None stands for missing key in both, so returning None deletes the key.
+    /// Returns new value.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use nut::DBBuilder;
+    /// use std::convert::TryInto;
+    ///
+    /// let db = DBBuilder::new("./test.db").build().unwrap();
+    /// let mut tx = db.begin_rw_tx().unwrap();
+    /// let mut counters = tx.create_bucket(b"counters").unwrap();
+    ///
+    /// counters.update(b"visits", |v| {
+    ///     let n = v.map_or(0, |v| u64::from_be_bytes(v.try_into().unwrap()));
+    ///     Some((n + 1).to_be_bytes().to_vec())
+    /// }).unwrap();
+    /// ```
+    pub fn update<F>(&mut self, key: &[u8], f: F) -> Result<Option<&[u8]>, Error>
+    where
+        F: FnOnce(Option<&[u8]>) -> Option<Vec<u8>>,
+    {
+        self.modify(key, |current| match f(current) {
+            Some(value) => Modify::Put(value),
+            None => Modify::Delete,
+        })
+    }
+
+    /// Returns value of a key, inserting the result of a function if the key is missing.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use nut::DBBuilder;
+    ///
+    /// let db = DBBuilder::new("./test.db").build().unwrap();
+    /// let mut tx = db.begin_rw_tx().unwrap();
+    /// let mut settings = tx.create_bucket(b"settings").unwrap();
+    ///
+    /// let theme = settings.get_or_insert_with(b"theme", || b"dark".to_vec()).unwrap();
+    /// ```
+    pub fn get_or_insert_with<F>(&mut self, key: &[u8], f: F) -> Result<&[u8], Error>
+    where
+        F: FnOnce() -> Vec<u8>,
+    {
+        let value = self.modify(key, |current| match current {
+            Some(_) => Modify::Keep,
+            None => Modify::Put(f()),
+        })?;
+        Ok(value.unwrap_or(&[]))
+    }
+
+    /// Seeks a key once and changes its value according to a function
+    /// called with current value. Returns resulting value.
+    fn modify<F>(&mut self, key: &[u8], f: F) -> Result<Option<&[u8]>, Error>
+    where
+        F: FnOnce(Option<&[u8]>) -> Modify,
+    {
+        self.check_put(key, 0)?;
+
+        let mut c = self.cursor()?;
+        let item = c.seek(key)?;
+        let old = match (item.key, item.value) {
+            _ if self.is_key(item.key, key) && item.is_bucket() => {
+                // Return an error if there is an existing key with a bucket value.
+                return Err(Error::IncompatibleValue);
+            }
+            (Some(k), Some(v)) if self.is_key(item.key, key) => Some((k, v)),
+            _ => None,
+        };
+        let current = old.map(|(_, v)| v);
+
+        match f(current) {
+            // Value lives in page or cached node as long as bucket is borrowed.
+            Modify::Keep => Ok(current.map(|v| unsafe { &*(v as *const [u8]) })),
+            Modify::Delete if current.is_none() => Ok(None),
+            Modify::Delete => {
+                let changes = self.index_changes(old, key, None)?;
+                c.node()?.del(key);
+                drop(c);
+                self.apply_index_changes(changes)?;
+                Ok(None)
+            }
+            Modify::Put(value) => {
+                Self::check_key_value(key, value.len())?;
+                let changes = self.index_changes(old, key, Some(&value))?;
+                let mut node = c.node()?;
+                let value: *const [u8] = &*node.put(key, key, value, 0, 0);
+                drop(c);
+                self.apply_index_changes(changes)?;
+                // Node is cached by bucket, so its value lives as long as bucket is borrowed.
+                Ok(Some(unsafe { &*value }))
+            }
+        }
+    }
+
+    /// Appends key/value pairs sorted by key to the bucket.
+    /// This is much faster than putting them one by one: keys aren't searched,
+    /// and leaf and branch pages are packed sequentially up to given fill percent on commit.
+    ///
+    /// Keys must be strictly ascending and greater than any key already in the bucket,
+    /// otherwise KeyOutOfOrder error is returned and the bucket is left unchanged.
+    /// Names of nested buckets count as keys too, including the one holding
+    /// secondary indexes, which sorts first with the default comparator.
+    /// Fill percent applies to pages of the loaded pairs only.
+    /// Returns count of loaded pairs.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use nut::DBBuilder;
+    ///
+    /// let db = DBBuilder::new("./test.db").build().unwrap();
+    /// let mut tx = db.begin_rw_tx().unwrap();
+    /// let mut events = tx.create_bucket(b"events").unwrap();
+    ///
+    /// let items = (0..1_000_000u64).map(|id| (id.to_be_bytes(), b"event"));
+    /// events.bulk_load(items, 1.0).unwrap();
     /// ```
-    pub fn put(&mut self, key: &[u8], value: Vec<u8>) -> Result<(), Error> {
+    pub fn bulk_load<'v, I, K, V>(&mut self, items: I, fill_percent: f64) -> Result<usize, Error>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<[u8]>,
+        V: Into<Cow<'v, [u8]>>,
+    {
+        self.load(items, fill_percent, true)
+    }
+
+    /// Same as bulk_load, but leaves secondary indexes untouched unless update_indexes is set.
+    /// Used by compaction, which copies index buckets as is.
+    pub(crate) fn load<'v, I, K, V>(
+        &mut self,
+        items: I,
+        fill_percent: f64,
+        update_indexes: bool,
+    ) -> Result<usize, Error>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<[u8]>,
+        V: Into<Cow<'v, [u8]>>,
+    {
+        self.check_writable()?;
+
+        let mut items = items.into_iter();
+        let mut last_key = self.cursor()?.last()?.key.map(<[u8]>::to_vec);
+        // loaded leaf and its length before the load
+        let mut loaded: Option<(Node, usize)> = None;
+        let mut count = 0;
+        loop {
+            let batch = self.load_batch(&mut items, &mut last_key);
+            let batch = match batch {
+                Ok(batch) if batch.is_empty() => break,
+                Ok(batch) => batch,
+                Err(e) => return self.unload(loaded, update_indexes).and(Err(e)),
+            };
+            let changes = if update_indexes {
+                let changes: Result<Vec<_>, Error> = batch
+                    .iter()
+                    .map(|(key, value)| self.index_changes(None, key, Some(value)))
+                    .collect();
+                match changes {
+                    Ok(changes) => changes.into_iter().flatten().collect(),
+                    Err(e) => return self.unload(loaded, update_indexes).and(Err(e)),
+                }
+            } else {
+                vec![]
+            };
+
+            count += batch.len();
+            let (node, _) = match &mut loaded {
+                Some(loaded) => loaded,
+                None => {
+                    // All keys go to the leaf the first one is routed to. It isn't always
+                    // the leaf of the last key, as leaves emptied by deletes stay until commit.
+                    let mut c = self.cursor()?;
+                    c.seek_to_item(&batch[0].0)?;
+                    let node = c.node()?;
+                    let len = node.0.inodes.borrow().len();
+                    loaded.get_or_insert((node, len))
+                }
+            };
+            node.append(batch);
+            if let Err(e) = self.apply_index_changes(changes) {
+                return self.unload(loaded, update_indexes).and(Err(e));
+            }
+        }
+
+        if let Some((node, _)) = loaded {
+            node.0.fill_percent.set(Some(fill_percent));
+        }
+        Ok(count)
+    }
+
+    /// Takes next batch of pairs to load, checking that keys are valid and ascending.
+    fn load_batch<'v, I, K, V>(
+        &self,
+        items: &mut I,
+        last_key: &mut Option<Vec<u8>>,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Error>
+    where
+        I: Iterator<Item = (K, V)>,
+        K: AsRef<[u8]>,
+        V: Into<Cow<'v, [u8]>>,
+    {
+        let mut batch = Vec::with_capacity(Self::LOAD_BATCH_SIZE);
+        for (key, value) in items.take(Self::LOAD_BATCH_SIZE) {
+            let key = key.as_ref();
+            let value = value.into();
+            Self::check_key_value(key, value.len())?;
+            let prev = batch
+                .last()
+                .map(|(k, _): &(Vec<u8>, _)| k)
+                .or(last_key.as_ref());
+            if matches!(prev, Some(prev) if self.compare(prev, key) != Ordering::Less) {
+                return Err(Error::KeyOutOfOrder);
+            }
+            batch.push((key.to_vec(), value.into_owned()));
+        }
+        if let Some((key, _)) = batch.last() {
+            *last_key = Some(key.clone());
+        }
+        Ok(batch)
+    }
+
+    /// Removes pairs appended by failed load along with their index entries.
+    fn unload(&mut self, loaded: Option<(Node, usize)>, update_indexes: bool) -> Result<(), Error> {
+        let (node, len) = match loaded {
+            Some(loaded) => loaded,
+            None => return Ok(()),
+        };
+        let removed = node.0.inodes.borrow_mut().split_off(len);
+        if update_indexes {
+            for inode in removed {
+                let changes =
+                    self.index_changes(Some((&inode.key, &inode.value)), &inode.key, None)?;
+                self.apply_index_changes(changes)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn check_put(&self, key: &[u8], value_len: usize) -> Result<(), Error> {
+        self.check_writable()?;
+        Self::check_key_value(key, value_len)
+    }
+
+    pub(super) fn check_writable(&self) -> Result<(), Error> {
         if !self.tx()?.opened() {
             return Err(Error::TxClosed);
         }
         if !self.tx()?.writable() {
             return Err(Error::TxReadonly);
         }
+        Ok(())
+    }
+
+    fn check_key_value(key: &[u8], value_len: usize) -> Result<(), Error> {
         if key.is_empty() {
             return Err(Error::KeyRequired);
         }
         if key.len() > MAX_KEY_SIZE {
             return Err(Error::KeyTooLarge);
         }
-        if value.len() > MAX_VALUE_SIZE {
+        if value_len > MAX_VALUE_SIZE {
             return Err(Error::ValueTooLarge);
         }
+        Ok(())
+    }
 
+    /// Returns leaf node to put a key into.
+    fn put_node(&mut self, key: &[u8]) -> Result<Node, Error> {
         // Move cursor to correct position.
         let mut c = self.cursor()?;
         let item = c.seek(key)?;
 
         // Return an error if there is an existing key with a bucket value.
-        if (Some(key) == item.key) && item.is_bucket() {
+        if self.is_key(item.key, key) && item.is_bucket() {
             return Err(Error::IncompatibleValue);
         }
 
-        // Insert into node.
-        c.node().unwrap().put(key, key, value, 0, 0);
-
-        Ok(())
+        c.node()
     }
 
     /// Removes a key from the bucket.
@@ -406,7 +931,7 @@ impl Bucket {
     /// ```no_run
     /// use nut::DBBuilder;
     ///
-    /// let mut db = DBBuilder::new("./test.db").build().unwrap();
+    /// let db = DBBuilder::new("./test.db").build().unwrap();
     /// let mut tx = db.begin_rw_tx().unwrap();
     /// let mut flowers = tx.bucket_mut(b"flowers").unwrap();
     ///
@@ -428,11 +953,27 @@ impl Bucket {
         if item.is_bucket() {
             return Err(Error::IncompatibleValue);
         };
+        let changes = self.index_write(key, None)?;
 
         // Delete the node if we have a matching key.
         c.node().unwrap().del(key);
+        drop(c);
 
-        Ok(())
+        self.apply_index_changes(changes)
+    }
+
+    /// Returns the threshold for filling nodes when they split.
+    pub fn fill_percent(&self) -> f64 {
+        self.fill_percent
+    }
+
+    /// Sets the threshold for filling nodes when they split, clamped to 0.1..=1.0.
+    /// By default the bucket fills pages to 50%, increasing it saves space
+    /// for mostly append-only workloads.
+    ///
+    /// This is non-persisted across transactions so it must be set in every Tx.
+    pub fn set_fill_percent(&mut self, fill_percent: f64) {
+        self.fill_percent = clamp(fill_percent, Self::MIN_FILL_PERCENT, Self::MAX_FILL_PERCENT);
     }
 
     /// Returns the current integer for the bucket without incrementing it.
@@ -470,7 +1011,8 @@ impl Bucket {
         Ok(())
     }
 
-    /// Executes a function for each key/value pair in a bucket.
+    /// Executes a function for each key/value pair in a bucket,
+    /// skipping nested bucket holding secondary indexes.
     /// If the provided function returns an error then the iteration is stopped and
     /// the error is returned to the caller.
     pub fn for_each<'a, E: Into<Error>>(
@@ -486,12 +1028,98 @@ impl Bucket {
             if item.is_none() {
                 break;
             };
-            handler(item.key.unwrap(), item.value).map_err(|e| e.into())?;
+            if item.flags & Self::INDEXES_FLAG == 0 {
+                handler(item.key.unwrap(), item.value).map_err(|e| e.into())?;
+            }
             item = c.next()?;
         }
         Ok(())
     }
 
+    /// Returns iterator over all key/value pairs in the bucket in sorted order.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use nut::DBBuilder;
+    ///
+    /// let db = DBBuilder::new("./test.db").build().unwrap();
+    /// let tx = db.begin_tx().unwrap();
+    /// let flowers = tx.bucket(b"flowers").unwrap();
+    ///
+    /// for (key, entry) in flowers.iter().unwrap().rev() {
+    ///     println!("{:?}: {:?}", key, entry.value());
+    /// }
+    /// ```
+    pub fn iter(&self) -> Result<Iter<'_>, Error> {
+        self.range::<[u8], _>(..)
+    }
+
+    /// Returns iterator over key/value pairs within given key range in sorted order.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use nut::DBBuilder;
+    ///
+    /// let db = DBBuilder::new("./test.db").build().unwrap();
+    /// let tx = db.begin_tx().unwrap();
+    /// let flowers = tx.bucket(b"flowers").unwrap();
+    ///
+    /// let keys: Vec<&[u8]> = flowers.range(&b"a"[..]..&b"c"[..]).unwrap().map(|(k, _)| k).collect();
+    /// ```
+    pub fn range<K, R>(&self, range: R) -> Result<Iter<'_>, Error>
+    where
+        K: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<K>,
+    {
+        if !self.tx()?.opened() {
+            return Err(Error::TxClosed);
+        }
+        let bound = |b: Bound<&K>| match b {
+            Bound::Included(k) => Bound::Included(k.as_ref().to_vec()),
+            Bound::Excluded(k) => Bound::Excluded(k.as_ref().to_vec()),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        Ok(Iter::new(
+            self.cursor()?,
+            Cursor::new(self),
+            bound(range.start_bound()),
+            bound(range.end_bound()),
+        ))
+    }
+
+    /// Returns iterator over key/value pairs with keys starting with given prefix.
+    /// Iteration stops at the first key not matching the prefix, in either direction.
+    ///
+    /// Keys with a prefix are contiguous only in bytewise order, so for buckets
+    /// with custom comparator all keys are scanned and the rest are skipped.
+    /// Iteration then costs O(n) in the number of keys in the bucket, not in
+    /// the number of matching ones; prefer range() with bounds that suit the
+    /// comparator for large buckets.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use nut::DBBuilder;
+    ///
+    /// let db = DBBuilder::new("./test.db").build().unwrap();
+    /// let tx = db.begin_tx().unwrap();
+    /// let users = tx.bucket(b"users").unwrap();
+    ///
+    /// let last_post = users.scan_prefix(b"user/42/").unwrap().next_back();
+    /// ```
+    pub fn scan_prefix(&self, prefix: &[u8]) -> Result<Iter<'_>, Error> {
+        if self.comparator_id != 0 {
+            return Ok(self.iter()?.filter_prefix(prefix.to_vec()));
+        }
+        let end = match prefix_end(prefix) {
+            Some(end) => Bound::Excluded(end),
+            None => Bound::Unbounded,
+        };
+        self.range((Bound::Included(prefix.to_vec()), end))
+    }
+
     /// Returns stats on a bucket.
     pub fn stats(&self) -> BucketStats {
         let mut stats = BucketStats::default();
@@ -534,7 +1162,9 @@ impl Bucket {
                             if (e.flags & Self::FLAG) != 0 {
                                 // For any bucket element, open the element value
                                 // and recursively call Stats on the contained bucket.
-                                sub_stats += self.open_bucket(e.value().to_vec()).stats();
+                                if let Ok(child) = self.open_bucket(e.value().to_vec(), e.flags) {
+                                    sub_stats += child.stats();
+                                }
                             };
                         }
                     }
@@ -574,6 +1204,25 @@ impl Bucket {
         stats
     }
 
+    /// Executes a function for each nested bucket stored in the bucket's pages.
+    /// Key comparators of nested buckets aren't required, so they can only be used to walk pages.
+    pub(crate) fn for_each_stored_bucket<F>(&self, mut handler: F)
+    where
+        F: FnMut(&[u8], Result<Bucket, Error>),
+    {
+        self.for_each_page(Box::new(|p, _| {
+            if p.flags != Flags::LEAVES {
+                return;
+            }
+            for i in 0..p.count as usize {
+                let e = p.leaf_page_element(i);
+                if (e.flags & Self::FLAG) != 0 {
+                    handler(e.key(), self.open_bucket(e.value().to_vec(), e.flags));
+                }
+            }
+        }));
+    }
+
     /// Iterates over every page in a bucket, including inline pages.
     fn for_each_page<'a>(&self, mut handler: Box<dyn FnMut(&Page, usize) + 'a>) {
         // If we have an inline page then just use that.
@@ -648,9 +1297,8 @@ impl Bucket {
                 child.spill()?;
 
                 // Update the child bucket header in this bucket.
-                let mut vec = vec![0u8; IBucket::SIZE];
-                let bucket_ptr = vec.as_mut_ptr() as *mut IBucket;
-                unsafe { std::ptr::copy_nonoverlapping(&child.bucket, bucket_ptr, 1) };
+                let mut vec = vec![0u8; child.header_size()];
+                child.write_header(&mut vec);
                 vec
             };
 
@@ -673,7 +1321,7 @@ impl Bucket {
             if !item.is_bucket() {
                 return Err(format!("unexpected bucket header flag: {}", item.flags).into());
             }
-            c.node()?.put(&name, &name, value, 0, Self::FLAG);
+            c.node()?.put(name, name, value, 0, child.element_flags());
         }
 
         // Ignore if there's not a materialized root node.
@@ -736,15 +1384,15 @@ impl Bucket {
         // Allocate the appropriate size.
         let n = self.root_node.as_ref().unwrap();
         let node_size = n.size();
-        let mut value = vec![0u8; IBucket::SIZE + node_size];
+        let header_size = self.header_size();
+        let mut value = vec![0u8; header_size + node_size];
 
         // Write a bucket header.
-        let bucket_ptr = value.as_mut_ptr() as *mut IBucket;
-        unsafe { std::ptr::copy_nonoverlapping(&self.bucket, bucket_ptr, 1) };
+        self.write_header(&mut value);
 
         // Convert byte slice to a fake page and write the root node.
         {
-            let mut page_buf = &mut value[IBucket::SIZE..];
+            let mut page_buf = &mut value[header_size..];
             let mut page = Page::from_buf_mut(&mut page_buf);
             n.write(&mut page);
         }
@@ -754,8 +1402,14 @@ impl Bucket {
 
     /// Attempts to balance all nodes.
     pub(crate) fn rebalance(&mut self) {
-        for node in self.nodes.borrow_mut().values_mut() {
-            node.rebalance()
+        // Rebalancing adds and removes cached nodes, so iterate over a snapshot
+        // and skip nodes which were removed meanwhile.
+        let pgids: Vec<PGID> = self.nodes.borrow().keys().cloned().collect();
+        for pgid in pgids {
+            let node = self.nodes.borrow().get(&pgid).cloned();
+            if let Some(mut node) = node {
+                node.rebalance()
+            }
         }
         for child in self.buckets.borrow_mut().values_mut() {
             child.rebalance()
@@ -823,7 +1477,10 @@ impl Bucket {
         // differently. We'll return the rootNode (if available) or the fake page.
         if self.bucket.root == 0 {
             if id != 0 {
-                return Err(format!("inline bucket non-zero page access: {} != 0", id).into());
+                return Err(Error::Corruption {
+                    pgid: id,
+                    reason: "inline bucket non-zero page access".to_string(),
+                });
             }
             if let Some(ref node) = self.root_node {
                 return Ok(PageNode::from(node.clone()));
@@ -0,0 +1,28 @@
+use std::cmp::Ordering;
+
+/// Function defining order of keys in a bucket
+///
+/// Comparators are registered on DB open with DBBuilder.comparator
+/// and chosen by id when creating a bucket. Id is persisted with the bucket,
+/// so same comparator must be registered under same id every time database is opened.
+///
+/// Id 0 stands for default bytewise order.
+///
+/// # Example
+///
+/// ```
+/// use nut::DBBuilder;
+///
+/// fn reverse(a: &[u8], b: &[u8]) -> std::cmp::Ordering {
+///     b.cmp(a)
+/// }
+///
+/// let db = DBBuilder::in_memory().comparator(1, reverse).build().unwrap();
+/// let mut tx = db.begin_rw_tx().unwrap();
+/// let mut bucket = tx.create_bucket_with_comparator(b"latest", 1).unwrap();
+/// bucket.put(b"2020", b"old").unwrap();
+/// bucket.put(b"2024", b"new").unwrap();
+///
+/// assert_eq!(bucket.cursor().unwrap().first().unwrap().key, Some(&b"2024"[..]));
+/// ```
+pub type Comparator = fn(&[u8], &[u8]) -> Ordering;
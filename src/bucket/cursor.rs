@@ -1,5 +1,6 @@
 use either::Either;
 use std::cell::RefCell;
+use std::cmp::Ordering;
 use std::ops::Deref;
 
 use crate::bucket::Bucket;
@@ -82,19 +83,20 @@ impl<'a, B: Deref<Target = Bucket> + 'a> Cursor<'a, B> {
     }
 
     fn search_node(&self, key: &[u8], n: &Node) -> Result<(), Error> {
+        let bucket = self.bucket();
         let (exact, mut index) = match n
             .0
             .inodes
             .borrow()
-            .binary_search_by(|inode| inode.key.as_slice().cmp(key))
+            .binary_search_by(|inode| bucket.compare(&inode.key, key))
         {
             Ok(mut v) => {
                 let inodes = n.0.inodes.borrow();
                 for (i, inode) in inodes.iter().enumerate().skip(v) {
-                    match inode.key.as_slice().cmp(key) {
-                        std::cmp::Ordering::Greater => break,
-                        std::cmp::Ordering::Less => break,
-                        std::cmp::Ordering::Equal => v = i,
+                    match bucket.compare(&inode.key, key) {
+                        Ordering::Greater => break,
+                        Ordering::Less => break,
+                        Ordering::Equal => v = i,
                     };
                 }
                 (true, v)
@@ -119,20 +121,22 @@ impl<'a, B: Deref<Target = Bucket> + 'a> Cursor<'a, B> {
     }
 
     fn search_page(&self, key: &[u8], p: &Page) -> Result<(), Error> {
+        let bucket = self.bucket();
         let inodes = p.branch_page_elements();
-        let (exact, mut index) = match inodes.binary_search_by(|inode| inode.key().cmp(key)) {
-            Ok(mut v) => {
-                for (i, inode) in inodes.iter().enumerate().skip(v) {
-                    match inode.key().cmp(key) {
-                        std::cmp::Ordering::Greater => break,
-                        std::cmp::Ordering::Less => break,
-                        std::cmp::Ordering::Equal => v = i,
-                    };
+        let (exact, mut index) =
+            match inodes.binary_search_by(|inode| bucket.compare(inode.key(), key)) {
+                Ok(mut v) => {
+                    for (i, inode) in inodes.iter().enumerate().skip(v) {
+                        match bucket.compare(inode.key(), key) {
+                            Ordering::Greater => break,
+                            Ordering::Less => break,
+                            Ordering::Equal => v = i,
+                        };
+                    }
+                    (true, v)
                 }
-                (true, v)
-            }
-            Err(v) => (false, v),
-        };
+                Err(v) => (false, v),
+            };
         if !exact && index > 0 {
             index -= 1;
         }
@@ -150,6 +154,7 @@ impl<'a, B: Deref<Target = Bucket> + 'a> Cursor<'a, B> {
 
     /// Searches the leaf node on the top of the stack for a key.
     fn nsearch(&self, key: &[u8]) -> Result<(), Error> {
+        let bucket = self.bucket();
         let mut stack = self.stack.borrow_mut();
         let el_ref = stack.last_mut().unwrap();
         if let Either::Right(ref n) = el_ref.upgrade() {
@@ -157,7 +162,7 @@ impl<'a, B: Deref<Target = Bucket> + 'a> Cursor<'a, B> {
                 .0
                 .inodes
                 .borrow()
-                .binary_search_by(|inode| inode.key.as_slice().cmp(key))
+                .binary_search_by(|inode| bucket.compare(&inode.key, key))
             {
                 Ok(v) => v,
                 Err(v) => v,
@@ -169,7 +174,7 @@ impl<'a, B: Deref<Target = Bucket> + 'a> Cursor<'a, B> {
         // If we have a page then search its leaf elements.
         let page = el_ref.el.upgrade().left().ok_or("left empty")?;
         let inodes = page.leaf_page_elements();
-        let index = match inodes.binary_search_by(|inode| inode.key().cmp(key)) {
+        let index = match inodes.binary_search_by(|inode| bucket.compare(inode.key(), key)) {
             Ok(v) => v,
             Err(v) => v,
         };
@@ -290,12 +295,17 @@ impl<'a, B: Deref<Target = Bucket> + 'a> Cursor<'a, B> {
                 el: el_ref,
                 index: 0,
             };
-            el_ref.index = el_ref.count() - 1;
+            el_ref.index = el_ref.count().saturating_sub(1);
             stack.push(el_ref);
         }
 
         self.last_leaf()?;
 
+        // Deletes may leave empty leaf nodes, step back to the last non-empty one.
+        if self.stack.borrow().last().ok_or("stack empty")?.count() == 0 {
+            return self.prev();
+        }
+
         let mut item = self.key_value()?;
         if (item.flags & Bucket::FLAG) != 0 {
             item.value = None;
@@ -322,7 +332,7 @@ impl<'a, B: Deref<Target = Bucket> + 'a> Cursor<'a, B> {
                 el: page_node,
                 index: 0,
             };
-            next_ref.index = next_ref.count() - 1;
+            next_ref.index = next_ref.count().saturating_sub(1);
             stack.push(next_ref)
         }
 
@@ -385,33 +395,34 @@ impl<'a, B: Deref<Target = Bucket> + 'a> Cursor<'a, B> {
         if !self.bucket.tx()?.opened() {
             return Err(Error::TxClosed);
         };
-        {
-            let mut stack = self.stack.borrow_mut();
+        loop {
+            {
+                let mut stack = self.stack.borrow_mut();
 
-            // If we've hit the end then return nil.
-            if stack.len() == 0 {
-                return Ok(CursorItem::new_null(None, None));
-            }
+                // Attempt to move back one element until we're successful.
+                // Move up the stack as we hit the beginning of each page in our stack.
+                while let Some(elem) = stack.last_mut() {
+                    if elem.index > 0 {
+                        elem.index -= 1;
+                        break;
+                    }
+                    stack.pop();
+                }
 
-            // Attempt to move back one element until we're successful.
-            // Move up the stack as we hit the beginning of each page in our stack.
-            for i in stack.len() - 1..=0 {
-                let elem = &mut stack[i];
-                if elem.index > 0 {
-                    elem.index -= 1;
-                    break;
+                // If we've hit the end then return nil.
+                if stack.is_empty() {
+                    return Ok(CursorItem::new_null(None, None));
                 }
-                stack.truncate(i);
             }
 
-            // If we've hit the end then return nil.
-            if stack.len() == 0 {
-                return Ok(CursorItem::new_null(None, None));
+            // Move down the stack to find the last element of the last leaf under this branch.
+            self.last_leaf()?;
+
+            if self.stack.borrow().last().ok_or("stack empty")?.count() == 0 {
+                continue;
             }
+            break;
         }
-
-        // Move down the stack to find the last element of the last leaf under this branch.
-        self.last_leaf()?;
         let mut item = self.key_value()?;
         if (item.flags & Bucket::FLAG) != 0 {
             item.value = None;
@@ -446,6 +457,59 @@ impl<'a, B: Deref<Target = Bucket> + 'a> Cursor<'a, B> {
         Ok(item)
     }
 
+    /// Moves the cursor to the last key less than or equal to a given key and returns it.
+    /// If no such key exists, a nil key is returned.
+    ///
+    /// The returned key and value are only valid for the life of the transaction.
+    pub fn seek_le(&self, seek: &[u8]) -> Result<CursorItem<'a>, Error> {
+        let item = self.seek(seek)?;
+        match item.key {
+            None => self.last(),
+            Some(key) if self.bucket().compare(key, seek) != Ordering::Equal => self.prev(),
+            Some(_) => Ok(item),
+        }
+    }
+
+    /// Moves the cursor to the last key less than a given key and returns it.
+    /// If no such key exists, a nil key is returned.
+    ///
+    /// The returned key and value are only valid for the life of the transaction.
+    pub fn seek_lt(&self, seek: &[u8]) -> Result<CursorItem<'a>, Error> {
+        let item = self.seek(seek)?;
+        match item.key {
+            None => self.last(),
+            Some(_) => self.prev(),
+        }
+    }
+
+    /// Moves the cursor to the first key starting with a given prefix and returns it.
+    /// If no such key exists, a nil key is returned.
+    ///
+    /// Keys with the prefix follow it, so next() can be called until a key without
+    /// the prefix is met. With custom comparator they can be interleaved with
+    /// other keys, so the bucket is scanned from the start to find the first one,
+    /// which costs O(n) in the number of keys in the bucket on every call.
+    ///
+    /// The returned key and value are only valid for the life of the transaction.
+    pub fn seek_prefix(&self, prefix: &[u8]) -> Result<CursorItem<'a>, Error> {
+        let bytewise = self.bucket().comparator_id() == 0;
+        let mut item = if bytewise {
+            self.seek(prefix)?
+        } else {
+            self.first()?
+        };
+        while let Some(key) = item.key {
+            if key.starts_with(prefix) {
+                return Ok(item);
+            }
+            if bytewise {
+                break;
+            }
+            item = self.next()?;
+        }
+        Ok(CursorItem::new_null(None, None))
+    }
+
     /// Moves the cursor to a given key and returns it.
     /// If the key does not exist then the next key is used.
     pub(crate) fn seek_to_item(&self, seek: &[u8]) -> Result<CursorItem<'a>, Error> {
@@ -467,6 +531,7 @@ impl<'a, B: Deref<Target = Bucket> + 'a> Cursor<'a, B> {
 
     /// Removes the current key/value under the cursor from the bucket.
     /// Delete fails if current key/value is a bucket or if the transaction is not writable.
+    /// Secondary indexes of the bucket are updated as by Bucket.delete.
     pub fn delete(&mut self) -> Result<(), Error> {
         if !self.bucket.tx()?.opened() {
             return Err(Error::TxClosed);
@@ -475,17 +540,19 @@ impl<'a, B: Deref<Target = Bucket> + 'a> Cursor<'a, B> {
             return Err(Error::TxReadonly);
         };
 
-        let key = {
+        let (key, changes) = {
             let item = self.key_value()?;
             // Return an error if current value is a bucket.
             if (item.flags & Bucket::FLAG) != 0 {
                 return Err(Error::IncompatibleValue);
             }
-            item.key.ok_or("key empty")?.to_vec()
+            let key = item.key.ok_or("key empty")?;
+            let old = item.value.map(|value| (key, value));
+            (key.to_vec(), self.bucket().index_changes(old, key, None)?)
         };
 
         self.node()?.del(&key);
 
-        Ok(())
+        self.bucket_mut().apply_index_changes(changes)
     }
 }
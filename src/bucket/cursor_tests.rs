@@ -1,8 +1,12 @@
+use std::ops::Bound;
+
 use crate::db::tests::db_mock;
 
+use super::Entry;
+
 #[test]
 fn seek_none() {
-    let mut db = db_mock().build().unwrap();
+    let db = db_mock().build().unwrap();
     let mut tx = db.begin_rw_tx().unwrap();
     drop(tx.create_bucket(b"blub").unwrap());
     let c = tx.cursor();
@@ -13,7 +17,7 @@ fn seek_none() {
 
 #[test]
 fn seek_some() {
-    let mut db = db_mock().build().unwrap();
+    let db = db_mock().build().unwrap();
     let mut tx = db.begin_rw_tx().unwrap();
     drop(tx.create_bucket(b"foo").unwrap());
     let c = tx.cursor();
@@ -24,7 +28,7 @@ fn seek_some() {
 
 #[test]
 fn values_cursor() {
-    let mut db = db_mock().build().unwrap();
+    let db = db_mock().build().unwrap();
     let mut tx = db.begin_rw_tx().unwrap();
     {
         let mut bucket = tx.create_bucket(b"bucket").unwrap();
@@ -118,7 +122,7 @@ fn values_cursor() {
 
 #[test]
 fn bucket_cursor() {
-    let mut db = db_mock().build().unwrap();
+    let db = db_mock().build().unwrap();
     let mut tx = db.begin_rw_tx().unwrap();
     {
         let mut bucket = tx.create_bucket(b"bucket").unwrap();
@@ -149,3 +153,285 @@ fn bucket_cursor() {
         assert!(bucket_names.contains(&b"another bucket".to_vec()));
     }
 }
+
+#[test]
+fn iter() {
+    let db = db_mock().build().unwrap();
+    {
+        let mut tx = db.begin_rw_tx().unwrap();
+        let mut bucket = tx.create_bucket(b"bucket").unwrap();
+        for i in 0..1000u32 {
+            bucket
+                .put(&i.to_be_bytes(), i.to_string().into_bytes())
+                .unwrap();
+        }
+        bucket.create_bucket(b"nested").unwrap();
+    }
+
+    let tx = db.begin_tx().unwrap();
+    let bucket = tx.bucket(b"bucket").unwrap();
+
+    let keys: Vec<&[u8]> = bucket.iter().unwrap().map(|(k, _)| k).collect();
+    assert_eq!(keys.len(), 1001);
+    assert!(keys.windows(2).all(|w| w[0] < w[1]));
+
+    let (key, entry) = bucket.iter().unwrap().next_back().unwrap();
+    assert_eq!(key, b"nested");
+    assert_eq!(entry, Entry::Bucket);
+    assert_eq!(entry.value(), None);
+
+    let mut rev: Vec<&[u8]> = bucket.iter().unwrap().rev().map(|(k, _)| k).collect();
+    rev.reverse();
+    assert_eq!(keys, rev);
+
+    let (_, entry) = bucket.iter().unwrap().next().unwrap();
+    assert_eq!(entry, Entry::Value(b"0"));
+
+    // both ends meet without yielding any key twice
+    let mut it = bucket.iter().unwrap();
+    let mut count = 0;
+    while it.next().is_some() {
+        count += 1;
+        if it.next_back().is_some() {
+            count += 1;
+        }
+    }
+    assert_eq!(count, 1001);
+    assert_eq!(it.next_back(), None);
+}
+
+fn keys<'a>(it: impl Iterator<Item = (&'a [u8], Entry<'a>)>) -> Vec<u32> {
+    it.map(|(k, _)| u32::from_be_bytes([k[0], k[1], k[2], k[3]]))
+        .collect()
+}
+
+#[test]
+fn iter_range() {
+    let db = db_mock().build().unwrap();
+    let mut tx = db.begin_rw_tx().unwrap();
+    let mut bucket = tx.create_bucket(b"bucket").unwrap();
+    for i in (0..500u32).map(|i| i * 2) {
+        bucket.put(&i.to_be_bytes(), vec![0; 100]).unwrap();
+    }
+    let key = |i: u32| i.to_be_bytes().to_vec();
+
+    assert_eq!(
+        keys(bucket.range(key(10)..key(16)).unwrap()),
+        vec![10, 12, 14]
+    );
+    assert_eq!(
+        keys(bucket.range(key(9)..=key(16)).unwrap()),
+        vec![10, 12, 14, 16]
+    );
+    assert_eq!(
+        keys(bucket.range(key(9)..=key(15)).unwrap().rev()),
+        vec![14, 12, 10]
+    );
+    assert_eq!(
+        keys(
+            bucket
+                .range((Bound::Excluded(key(10)), Bound::Excluded(key(16))))
+                .unwrap()
+                .rev()
+        ),
+        vec![14, 12]
+    );
+    assert_eq!(keys(bucket.range(key(995)..).unwrap()), vec![996, 998]);
+    assert_eq!(keys(bucket.range(..key(3)).unwrap().rev()), vec![2, 0]);
+    assert_eq!(
+        keys(bucket.range(key(2000)..).unwrap().rev()),
+        Vec::<u32>::new()
+    );
+    assert_eq!(
+        keys(bucket.range(key(16)..key(10)).unwrap()),
+        Vec::<u32>::new()
+    );
+    assert_eq!(bucket.range::<[u8], _>(..).unwrap().count(), 500);
+
+    // deletes leave empty nodes until commit
+    for i in 100..900u32 {
+        bucket.delete(&key(i)).unwrap();
+    }
+    assert_eq!(
+        keys(bucket.range(key(90)..key(910)).unwrap().rev()),
+        vec![908, 906, 904, 902, 900, 98, 96, 94, 92, 90]
+    );
+    assert_eq!(bucket.iter().unwrap().count(), 100);
+}
+
+#[test]
+fn iter_empty() {
+    let db = db_mock().build().unwrap();
+    let mut tx = db.begin_rw_tx().unwrap();
+    let bucket = tx.create_bucket(b"bucket").unwrap();
+    assert_eq!(bucket.iter().unwrap().next(), None);
+    assert_eq!(bucket.iter().unwrap().next_back(), None);
+}
+
+#[test]
+fn scan_prefix() {
+    let db = db_mock().build().unwrap();
+    let mut tx = db.begin_rw_tx().unwrap();
+    let mut bucket = tx.create_bucket(b"bucket").unwrap();
+    for user in &[b"user/1/", b"user/2/", b"user/3/"] {
+        for post in 0..200u32 {
+            let mut key = user.to_vec();
+            key.extend_from_slice(&post.to_be_bytes());
+            bucket.put(&key, vec![0; 50]).unwrap();
+        }
+    }
+    bucket.put(b"user/2", b"profile".to_vec()).unwrap();
+    bucket.put(&[0xff, 0xff], b"max".to_vec()).unwrap();
+    bucket.put(&[0xff, 0xff, 0x01], b"max".to_vec()).unwrap();
+
+    let posts: Vec<&[u8]> = bucket
+        .scan_prefix(b"user/2/")
+        .unwrap()
+        .map(|(k, _)| k)
+        .collect();
+    assert_eq!(posts.len(), 200);
+    assert!(posts.iter().all(|k| k.starts_with(b"user/2/")));
+    assert_eq!(&posts[199][7..], &199u32.to_be_bytes());
+
+    let (last, _) = bucket.scan_prefix(b"user/2/").unwrap().next_back().unwrap();
+    assert_eq!(last, posts[199]);
+    assert_eq!(bucket.scan_prefix(b"user/2/").unwrap().rev().count(), 200);
+
+    assert_eq!(bucket.scan_prefix(b"user/2").unwrap().count(), 201);
+    assert_eq!(bucket.scan_prefix(b"user/").unwrap().rev().count(), 601);
+    assert_eq!(bucket.scan_prefix(b"user/4/").unwrap().next(), None);
+    assert_eq!(bucket.scan_prefix(&[0xff]).unwrap().rev().count(), 2);
+    assert_eq!(bucket.scan_prefix(b"").unwrap().count(), 603);
+}
+
+#[test]
+fn scan_prefix_comparator() {
+    let db = db_mock()
+        .comparator(1, |a, b| b.cmp(a))
+        .comparator(2, |a, b| {
+            a.to_ascii_lowercase().cmp(&b.to_ascii_lowercase())
+        })
+        .build()
+        .unwrap();
+    let mut tx = db.begin_rw_tx().unwrap();
+    fn keys<'a>(iter: impl Iterator<Item = (&'a [u8], Entry<'a>)>) -> Vec<Vec<u8>> {
+        iter.map(|(k, _)| k.to_vec()).collect()
+    }
+
+    let mut reverse = tx.create_bucket_with_comparator(b"reverse", 1).unwrap();
+    for key in &[b"aa", b"ab", b"ba", b"bb"] {
+        reverse.put(*key, vec![]).unwrap();
+    }
+    assert_eq!(
+        keys(reverse.scan_prefix(b"a").unwrap()),
+        vec![b"ab".to_vec(), b"aa".to_vec()]
+    );
+    assert_eq!(
+        keys(reverse.scan_prefix(b"b").unwrap().rev()),
+        vec![b"ba".to_vec(), b"bb".to_vec()]
+    );
+    assert_eq!(reverse.scan_prefix(b"c").unwrap().next(), None);
+    drop(reverse);
+
+    // keys with a prefix are interleaved with others
+    let mut names = tx.create_bucket_with_comparator(b"names", 2).unwrap();
+    for key in ["Ab", "aC", "AD", "ae", "b"] {
+        names.put(key.as_bytes(), vec![]).unwrap();
+    }
+    assert_eq!(
+        keys(names.scan_prefix(b"a").unwrap()),
+        vec![b"aC".to_vec(), b"ae".to_vec()]
+    );
+    let mut iter = names.scan_prefix(b"A").unwrap();
+    assert_eq!(iter.next().unwrap().0, b"Ab");
+    assert_eq!(iter.next_back().unwrap().0, b"AD");
+    assert_eq!(iter.next(), None);
+    assert_eq!(iter.next_back(), None);
+}
+
+#[test]
+fn seek_prefix() {
+    let db = db_mock()
+        .comparator(1, |a, b| {
+            a.to_ascii_lowercase().cmp(&b.to_ascii_lowercase())
+        })
+        .build()
+        .unwrap();
+    let mut tx = db.begin_rw_tx().unwrap();
+    let mut bucket = tx.create_bucket(b"bucket").unwrap();
+    for i in 0..1000u32 {
+        bucket
+            .put(format!("key{:04}", i).as_bytes(), vec![0; 50])
+            .unwrap();
+    }
+    bucket.put(b"keys", b"s".to_vec()).unwrap();
+    let c = bucket.cursor().unwrap();
+    assert_eq!(c.seek_prefix(b"key05").unwrap().key, Some(&b"key0500"[..]));
+    assert_eq!(c.next().unwrap().key, Some(&b"key0501"[..]));
+    assert_eq!(c.seek_prefix(b"keys").unwrap().key, Some(&b"keys"[..]));
+    assert_eq!(c.seek_prefix(b"").unwrap().key, Some(&b"key0000"[..]));
+    assert!(c.seek_prefix(b"key1").unwrap().is_none());
+    assert!(c.seek_prefix(b"l").unwrap().is_none());
+    drop(bucket);
+
+    let mut names = tx.create_bucket_with_comparator(b"names", 1).unwrap();
+    for key in ["Ab", "aC", "AD", "ae", "b"] {
+        names.put(key.as_bytes(), vec![]).unwrap();
+    }
+    let c = names.cursor().unwrap();
+    assert_eq!(c.seek_prefix(b"a").unwrap().key, Some(&b"aC"[..]));
+    assert_eq!(c.seek_prefix(b"AD").unwrap().key, Some(&b"AD"[..]));
+    assert!(c.seek_prefix(b"B").unwrap().is_none());
+}
+
+#[test]
+fn seek_le_lt() {
+    let db = db_mock().build().unwrap();
+    {
+        let mut tx = db.begin_rw_tx().unwrap();
+        let mut bucket = tx.create_bucket(b"bucket").unwrap();
+        for i in (1..=1000u32).map(|i| i * 10) {
+            bucket.put(&i.to_be_bytes(), vec![0; 100]).unwrap();
+        }
+        drop(bucket);
+        let mut inline = tx.create_bucket(b"inline").unwrap();
+        inline.put(b"b", b"2".to_vec()).unwrap();
+        inline.put(b"d", b"4".to_vec()).unwrap();
+    }
+
+    let tx = db.begin_tx().unwrap();
+    let bucket = tx.bucket(b"bucket").unwrap();
+    assert!(bucket.root() != 0);
+    let c = bucket.cursor().unwrap();
+    let key = |item: crate::CursorItem| {
+        item.key
+            .map(|k| u32::from_be_bytes([k[0], k[1], k[2], k[3]]))
+    };
+
+    assert_eq!(key(c.seek_le(&5000u32.to_be_bytes()).unwrap()), Some(5000));
+    assert_eq!(key(c.seek_le(&5005u32.to_be_bytes()).unwrap()), Some(5000));
+    assert_eq!(key(c.seek_lt(&5000u32.to_be_bytes()).unwrap()), Some(4990));
+    assert_eq!(
+        key(c.seek_le(&20000u32.to_be_bytes()).unwrap()),
+        Some(10000)
+    );
+    assert_eq!(key(c.seek_lt(&10000u32.to_be_bytes()).unwrap()), Some(9990));
+    assert_eq!(key(c.seek_le(&5u32.to_be_bytes()).unwrap()), None);
+    assert_eq!(key(c.seek_lt(&10u32.to_be_bytes()).unwrap()), None);
+
+    // every key across leaf page boundaries
+    for i in (1..1000u32).map(|i| i * 10) {
+        assert_eq!(key(c.seek_le(&(i + 9).to_be_bytes()).unwrap()), Some(i));
+        assert_eq!(key(c.seek_lt(&(i + 10).to_be_bytes()).unwrap()), Some(i));
+        assert_eq!(key(c.next().unwrap()), Some(i + 10));
+    }
+
+    let inline = tx.bucket(b"inline").unwrap();
+    assert_eq!(inline.root(), 0);
+    let c = inline.cursor().unwrap();
+    assert_eq!(c.seek_le(b"c").unwrap().key, Some(&b"b"[..]));
+    assert_eq!(c.seek_le(b"d").unwrap().value, Some(&b"4"[..]));
+    assert_eq!(c.seek_lt(b"d").unwrap().key, Some(&b"b"[..]));
+    assert_eq!(c.seek_lt(b"z").unwrap().key, Some(&b"d"[..]));
+    assert!(c.seek_lt(b"b").unwrap().is_none());
+}
@@ -4,6 +4,11 @@ use crate::consts::PGID;
 /// This is stored as the "value" of a bucket key. If the bucket is small enough,
 /// then its root page can be stored inline in the "value", after the bucket
 /// header. In the case of inline buckets, the "root" will be 0.
+///
+/// Header of a bucket with custom key comparator is followed by comparator id,
+/// stored as little-endian u64 to keep inline page aligned.
+/// Such buckets are marked with Bucket::COMPARATOR_FLAG in the parent's leaf element.
+/// Database with such buckets has meta version 3, so older readers refuse it.
 #[derive(Clone, Debug)]
 #[repr(C)]
 pub struct IBucket {
@@ -17,6 +22,9 @@ pub struct IBucket {
 impl IBucket {
     pub(crate) const SIZE: usize = std::mem::size_of::<Self>();
 
+    /// size of comparator id following the header
+    pub(crate) const COMPARATOR_SIZE: usize = std::mem::size_of::<u64>();
+
     pub(crate) fn new() -> IBucket {
         IBucket {
             root: 0,
@@ -0,0 +1,327 @@
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use crate::errors::Error;
+
+use super::consts::MAX_KEY_SIZE;
+use super::{Bucket, Cursor, CursorItem, Entry};
+
+/// Function returning secondary index keys for a value
+pub(crate) type IndexFn = Arc<dyn Fn(&[u8]) -> Vec<Vec<u8>> + Send + Sync>;
+
+/// Key of nested bucket holding a bucket per index.
+/// The bucket is told apart from user data by Bucket::INDEXES_FLAG of its element.
+pub(crate) const INDEXES_BUCKET: &[u8] = b"\x00indexes";
+
+/// Index entries to remove and to add on write of a key
+pub(super) struct IndexChange {
+    name: Vec<u8>,
+    removed: Vec<Vec<u8>>,
+    added: Vec<Vec<u8>>,
+}
+
+/// Index entry is a key with empty value, made of index key, primary key and length of index key.
+/// Length makes entries unambiguous and lets lookup skip entries of longer index keys sharing the prefix.
+fn entry(index_key: &[u8], key: &[u8]) -> Vec<u8> {
+    let mut entry = Vec::with_capacity(index_key.len() + key.len() + 4);
+    entry.extend_from_slice(index_key);
+    entry.extend_from_slice(key);
+    entry.extend_from_slice(&(index_key.len() as u32).to_be_bytes());
+    entry
+}
+
+/// Returns index entries of a key/value
+fn entries(extractor: &IndexFn, key: &[u8], value: &[u8]) -> BTreeSet<Vec<u8>> {
+    extractor(value)
+        .iter()
+        .map(|index_key| entry(index_key, key))
+        .collect()
+}
+
+impl Bucket {
+    /// Creates secondary index with extractor registered under given name with DBBuilder.index
+    /// and indexes existing values.
+    /// Index is updated by every write to the bucket in the same transaction.
+    ///
+    /// Indexes are kept in nested bucket with "\0indexes" key, marked as internal.
+    /// Iter, for_each, buckets() and bucket() skip it, but cursors see it
+    /// and bucket stats include its pages.
+    ///
+    /// Returns IndexNotRegistered error if extractor is not registered,
+    /// BucketExists error if the index already exists
+    /// and IncompatibleValue error if "\0indexes" key holds user data.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use nut::DBBuilder;
+    ///
+    /// let db = DBBuilder::in_memory()
+    ///     .index("city", |user| user.split(|b| *b == b':').skip(1).map(|c| c.to_vec()).collect())
+    ///     .build()
+    ///     .unwrap();
+    /// let mut tx = db.begin_rw_tx().unwrap();
+    /// let mut users = tx.create_bucket(b"users").unwrap();
+    /// users.create_index("city").unwrap();
+    ///
+    /// users.put(b"1", b"alice:paris").unwrap();
+    /// users.put(b"2", b"bob:berlin").unwrap();
+    ///
+    /// assert_eq!(users.index_keys("city", b"paris").unwrap(), vec![&b"1"[..]]);
+    /// ```
+    pub fn create_index(&mut self, name: &str) -> Result<(), Error> {
+        let extractor = self.index_fn(name.as_bytes())?;
+        self.check_writable()?;
+        if self.index_names().iter().any(|n| n == name.as_bytes()) {
+            return Err(Error::BucketExists);
+        }
+        let entries = self.index_entries(&extractor)?;
+        self.load_index(name.as_bytes(), entries)
+    }
+
+    /// Removes secondary index.
+    /// Returns IndexNotFound error if the index doesn't exist.
+    pub fn drop_index(&mut self, name: &str) -> Result<(), Error> {
+        self.check_writable()?;
+        let indexes = self
+            .indexes_bucket_mut()?
+            .ok_or_else(|| Error::IndexNotFound(name.to_string()))?;
+        match indexes.delete_bucket(name.as_bytes()) {
+            Err(Error::BucketNotFound) => return Err(Error::IndexNotFound(name.to_string())),
+            r => r?,
+        }
+        if indexes.buckets().is_empty() {
+            self.delete_nested(INDEXES_BUCKET, true)?;
+        }
+        self.indexes.borrow_mut().take();
+        Ok(())
+    }
+
+    /// Drops all entries of secondary index and indexes values again.
+    /// Returns IndexNotFound error if the index doesn't exist.
+    pub fn rebuild_index(&mut self, name: &str) -> Result<(), Error> {
+        let extractor = self.index_fn(name.as_bytes())?;
+        self.check_writable()?;
+        let entries = self.index_entries(&extractor)?;
+        self.drop_index(name)?;
+        self.load_index(name.as_bytes(), entries)
+    }
+
+    /// Returns names of the bucket's secondary indexes
+    pub fn indexes(&self) -> Vec<String> {
+        self.index_names()
+            .iter()
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+            .collect()
+    }
+
+    /// Returns keys which values have given index key, sorted by key bytes.
+    /// Returns IndexNotFound error if the index doesn't exist.
+    pub fn index_keys(&self, name: &str, index_key: &[u8]) -> Result<Vec<&[u8]>, Error> {
+        let index = match self.indexes_bucket()? {
+            Some(indexes) => indexes.try_bucket(name.as_bytes())?,
+            None => None,
+        }
+        .ok_or_else(|| Error::IndexNotFound(name.to_string()))?;
+        let len = (index_key.len() as u32).to_be_bytes();
+
+        let mut keys = vec![];
+        for (entry, _) in index.scan_prefix(index_key)? {
+            if entry.len() > index_key.len() + len.len() && entry.ends_with(&len) {
+                keys.push(&entry[index_key.len()..entry.len() - len.len()]);
+            }
+        }
+        // Length suffix puts a key after its extensions with low bytes, like "a" after "a\0".
+        keys.sort_unstable();
+        Ok(keys)
+    }
+
+    /// Returns key/value pairs which values have given index key, sorted by key bytes.
+    /// Returns IndexNotFound error if the index doesn't exist.
+    pub fn index_get(&self, name: &str, index_key: &[u8]) -> Result<Vec<(&[u8], &[u8])>, Error> {
+        let keys = self.index_keys(name, index_key)?;
+        keys.into_iter()
+            .map(|key| {
+                let value = self
+                    .get(key)
+                    .ok_or_else(|| format!("index {} refers missing key {:?}", name, key))?;
+                Ok((key, value))
+            })
+            .collect()
+    }
+
+    /// Returns nested bucket holding secondary indexes, if the bucket has any
+    pub(super) fn indexes_bucket(&self) -> Result<Option<&Bucket>, Error> {
+        Ok(self
+            .try_bucket(INDEXES_BUCKET)?
+            .filter(|indexes| indexes.holds_indexes))
+    }
+
+    pub(super) fn indexes_bucket_mut(&mut self) -> Result<Option<&mut Bucket>, Error> {
+        Ok(self
+            .try_bucket_mut(INDEXES_BUCKET)?
+            .filter(|indexes| indexes.holds_indexes))
+    }
+
+    /// Returns index extractor registered in database
+    fn index_fn(&self, name: &[u8]) -> Result<IndexFn, Error> {
+        self.db()?
+            .index(name)
+            .ok_or_else(|| Error::IndexNotRegistered(String::from_utf8_lossy(name).into_owned()))
+    }
+
+    /// Returns names of index buckets, cached until indexes are created or dropped
+    pub(super) fn index_names(&self) -> Vec<Vec<u8>> {
+        if let Some(ref names) = *self.indexes.borrow() {
+            return names.clone();
+        }
+        let names = match self.indexes_bucket() {
+            Ok(Some(indexes)) => indexes.buckets(),
+            _ => vec![],
+        };
+        self.indexes.borrow_mut().replace(names.clone());
+        names
+    }
+
+    /// Returns index entries of all values of the bucket
+    fn index_entries(&self, extractor: &IndexFn) -> Result<BTreeSet<Vec<u8>>, Error> {
+        let mut loaded = BTreeSet::new();
+        for (key, entry) in self.iter()? {
+            if let Entry::Value(value) = entry {
+                loaded.append(&mut entries(extractor, key, value));
+            }
+        }
+        if loaded.iter().any(|entry| entry.len() > MAX_KEY_SIZE) {
+            return Err(Error::KeyTooLarge);
+        }
+        Ok(loaded)
+    }
+
+    /// Creates index bucket filled with given entries
+    fn load_index(&mut self, name: &[u8], entries: BTreeSet<Vec<u8>>) -> Result<(), Error> {
+        self.indexes.borrow_mut().take();
+        if self.indexes_bucket()?.is_none() {
+            match self.create_nested(INDEXES_BUCKET, 0, true) {
+                Err(Error::BucketExists) => return Err(Error::IncompatibleValue),
+                r => r?,
+            };
+        }
+        let index = self
+            .indexes_bucket_mut()?
+            .ok_or("indexes bucket not found")?
+            .create_bucket(name)?;
+        index.bulk_load(
+            entries.iter().map(|entry| (entry, &[][..])),
+            Self::DEFAULT_FILL_PERCENT,
+        )?;
+        Ok(())
+    }
+
+    /// Computes index changes for setting a key to a value, or deleting it if value is None.
+    /// Old is the stored key/value, which is replaced or deleted.
+    ///
+    /// Called before the write, so missing extractor or too large index key fail it cleanly.
+    pub(super) fn index_changes(
+        &self,
+        old: Option<(&[u8], &[u8])>,
+        key: &[u8],
+        value: Option<&[u8]>,
+    ) -> Result<Vec<IndexChange>, Error> {
+        let mut changes = vec![];
+        for name in self.index_names() {
+            let extractor = self.index_fn(&name)?;
+            let old = old.map_or_else(BTreeSet::new, |(k, v)| entries(&extractor, k, v));
+            let new = value.map_or_else(BTreeSet::new, |v| entries(&extractor, key, v));
+            if new.iter().any(|entry| entry.len() > MAX_KEY_SIZE) {
+                return Err(Error::KeyTooLarge);
+            }
+            changes.push(IndexChange {
+                name,
+                removed: old.difference(&new).cloned().collect(),
+                added: new.difference(&old).cloned().collect(),
+            });
+        }
+        Ok(changes)
+    }
+
+    /// Computes index changes for setting a key to a value, or deleting it if value is None,
+    /// looking up the stored value only if the bucket has indexes.
+    pub(super) fn index_write(
+        &self,
+        key: &[u8],
+        value: Option<&[u8]>,
+    ) -> Result<Vec<IndexChange>, Error> {
+        if self.index_names().is_empty() {
+            return Ok(vec![]);
+        }
+        let item = self.cursor()?.seek(key)?;
+        let old = match (item.key, item.value) {
+            (Some(k), Some(v)) if self.is_key(item.key, key) && !item.is_bucket() => Some((k, v)),
+            _ => None,
+        };
+        self.index_changes(old, key, value)
+    }
+
+    /// Writes index changes made by index_changes
+    pub(super) fn apply_index_changes(&mut self, changes: Vec<IndexChange>) -> Result<(), Error> {
+        if changes.is_empty() {
+            return Ok(());
+        }
+        let indexes = self
+            .indexes_bucket_mut()?
+            .ok_or("indexes bucket not found")?;
+        for change in changes {
+            let index = indexes
+                .try_bucket_mut(&change.name)?
+                .ok_or("index bucket not found")?;
+            for entry in change.removed {
+                index.delete(&entry)?;
+            }
+            for entry in change.added {
+                index.put(&entry, &[][..])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Verifies that index bucket contains exactly entries of the bucket's values,
+    /// returns description of every mismatch.
+    ///
+    /// Index is skipped if its extractor is not registered.
+    pub(crate) fn check_index(&self, name: &[u8], index: &Bucket) -> Vec<String> {
+        let extractor = match self.index_fn(name) {
+            Ok(extractor) => extractor,
+            Err(_) => return vec![],
+        };
+        let name = String::from_utf8_lossy(name);
+
+        let mut expected = BTreeSet::new();
+        let c = Cursor::new(self);
+        let mut item = c.first();
+        while let Ok(CursorItem {
+            key: Some(key),
+            value,
+            ..
+        }) = item
+        {
+            if let Some(value) = value {
+                expected.append(&mut entries(&extractor, key, value));
+            }
+            item = c.next();
+        }
+
+        let mut errors = vec![];
+        let c = Cursor::new(index);
+        let mut item = c.first();
+        while let Ok(CursorItem { key: Some(key), .. }) = item {
+            if !expected.remove(key) {
+                errors.push(format!("index {}: unexpected entry {:?}", name, key));
+            }
+            item = c.next();
+        }
+        for entry in expected {
+            errors.push(format!("index {}: missing entry {:?}", name, entry));
+        }
+        errors
+    }
+}
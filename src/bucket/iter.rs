@@ -0,0 +1,216 @@
+use std::cmp::Ordering;
+use std::ops::Bound;
+
+use super::{Bucket, Cursor, CursorItem};
+
+/// Bucket item yielded by Iter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Entry<'a> {
+    /// plain value
+    Value(&'a [u8]),
+
+    /// nested bucket, can be opened with Bucket.bucket(key)
+    Bucket,
+}
+
+impl<'a> Entry<'a> {
+    /// Returns value, or None if entry is a nested bucket
+    pub fn value(&self) -> Option<&'a [u8]> {
+        match *self {
+            Entry::Value(v) => Some(v),
+            Entry::Bucket => None,
+        }
+    }
+
+    #[inline]
+    pub fn is_bucket(&self) -> bool {
+        *self == Entry::Bucket
+    }
+}
+
+/// Iterator over bucket's key/value pairs in sorted order
+///
+/// Created by Bucket.iter() and Bucket.range().
+/// Can be iterated from both ends, so .rev() walks keys backwards.
+/// Nested bucket holding secondary indexes is skipped.
+///
+/// Keys and values are only valid for the life of the transaction.
+pub struct Iter<'a> {
+    /// cursor walking from the start of the range
+    front: Cursor<'a, &'a Bucket>,
+
+    /// cursor walking from the end of the range
+    back: Cursor<'a, &'a Bucket>,
+
+    start: Bound<Vec<u8>>,
+    end: Bound<Vec<u8>>,
+
+    /// skips keys without this prefix
+    prefix: Option<Vec<u8>>,
+
+    /// yields nested bucket holding secondary indexes
+    indexes: bool,
+
+    /// last keys yielded from each end, None until the end is positioned
+    front_key: Option<&'a [u8]>,
+    back_key: Option<&'a [u8]>,
+
+    done: bool,
+}
+
+impl<'a> Iter<'a> {
+    pub(crate) fn new(
+        front: Cursor<'a, &'a Bucket>,
+        back: Cursor<'a, &'a Bucket>,
+        start: Bound<Vec<u8>>,
+        end: Bound<Vec<u8>>,
+    ) -> Self {
+        Self {
+            front,
+            back,
+            start,
+            end,
+            prefix: None,
+            indexes: false,
+            front_key: None,
+            back_key: None,
+            done: false,
+        }
+    }
+
+    /// Skips keys without given prefix instead of stopping at them,
+    /// for buckets where such keys aren't contiguous.
+    pub(crate) fn filter_prefix(mut self, prefix: Vec<u8>) -> Self {
+        self.prefix = Some(prefix);
+        self
+    }
+
+    /// Yields nested bucket holding secondary indexes too
+    pub(crate) fn with_indexes(mut self) -> Self {
+        self.indexes = true;
+        self
+    }
+
+    fn skipped(&self, key: &[u8], flags: u32) -> bool {
+        if !self.indexes && flags & Bucket::INDEXES_FLAG != 0 {
+            return true;
+        }
+        matches!(self.prefix, Some(ref prefix) if !key.starts_with(prefix))
+    }
+
+    /// Positions front cursor on the first key of the range
+    fn seek_front(&self) -> Option<CursorItem<'a>> {
+        match self.start {
+            Bound::Unbounded => self.front.first().ok(),
+            Bound::Included(ref key) => self.front.seek(key).ok(),
+            Bound::Excluded(ref key) => {
+                let item = self.front.seek(key).ok()?;
+                if matches!(item.key, Some(k) if self.compare(k, key) == Ordering::Equal) {
+                    return self.front.next().ok();
+                }
+                Some(item)
+            }
+        }
+    }
+
+    /// Positions back cursor on the last key of the range
+    fn seek_back(&self) -> Option<CursorItem<'a>> {
+        match self.end {
+            Bound::Unbounded => self.back.last().ok(),
+            Bound::Included(ref key) => self.back.seek_le(key).ok(),
+            Bound::Excluded(ref key) => self.back.seek_lt(key).ok(),
+        }
+    }
+
+    /// Compares keys in the bucket's order
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        self.front.bucket().compare(a, b)
+    }
+
+    fn before_end(&self, key: &[u8]) -> bool {
+        match self.end {
+            Bound::Unbounded => true,
+            Bound::Included(ref end) => self.compare(key, end) != Ordering::Greater,
+            Bound::Excluded(ref end) => self.compare(key, end) == Ordering::Less,
+        }
+    }
+
+    fn after_start(&self, key: &[u8]) -> bool {
+        match self.start {
+            Bound::Unbounded => true,
+            Bound::Included(ref start) => self.compare(key, start) != Ordering::Less,
+            Bound::Excluded(ref start) => self.compare(key, start) == Ordering::Greater,
+        }
+    }
+
+    /// Stops iteration on missing item, otherwise converts it into iterator's item
+    fn finish(&mut self, item: Option<CursorItem<'a>>) -> Option<(&'a [u8], Entry<'a>)> {
+        let item = match item {
+            Some(item) if item.key.is_some() => item,
+            _ => {
+                self.done = true;
+                return None;
+            }
+        };
+        let entry = if item.is_bucket() {
+            Entry::Bucket
+        } else {
+            Entry::Value(item.value.unwrap_or(&[]))
+        };
+        Some((item.key.unwrap(), entry))
+    }
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a [u8], Entry<'a>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        loop {
+            let item = match self.front_key {
+                None => self.seek_front(),
+                Some(_) => self.front.next().ok(),
+            };
+            let item = item.filter(|item| match item.key {
+                Some(key) => {
+                    self.before_end(key) && !matches!(self.back_key, Some(back) if self.compare(key, back) != Ordering::Less)
+                }
+                None => false,
+            });
+            let flags = item.as_ref().map_or(0, |item| item.flags);
+            let (key, entry) = self.finish(item)?;
+            self.front_key = Some(key);
+            if !self.skipped(key, flags) {
+                return Some((key, entry));
+            }
+        }
+    }
+}
+
+impl<'a> DoubleEndedIterator for Iter<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        loop {
+            let item = match self.back_key {
+                None => self.seek_back(),
+                Some(_) => self.back.prev().ok(),
+            };
+            let item = item.filter(|item| match item.key {
+                Some(key) => {
+                    self.after_start(key) && !matches!(self.front_key, Some(front) if self.compare(key, front) != Ordering::Greater)
+                }
+                None => false,
+            });
+            let flags = item.as_ref().map_or(0, |item| item.flags);
+            let (key, entry) = self.finish(item)?;
+            self.back_key = Some(key);
+            if !self.skipped(key, flags) {
+                return Some((key, entry));
+            }
+        }
+    }
+}
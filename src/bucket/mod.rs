@@ -5,16 +5,24 @@ pub(crate) mod tests;
 pub(crate) mod cursor_tests;
 
 mod bucket;
+mod comparator;
 mod consts;
 mod cursor;
 mod cursor_item;
 mod elemref;
 mod ibucket;
+mod index;
+mod iter;
 mod stats;
+mod typed;
 
 pub use bucket::Bucket;
+pub use comparator::Comparator;
 pub use cursor::Cursor;
 pub use cursor_item::CursorItem;
 pub(crate) use elemref::{ElemRef, PageNode};
 pub(crate) use ibucket::IBucket;
+pub(crate) use index::IndexFn;
+pub use iter::{Entry, Iter};
 pub use stats::BucketStats;
+pub use typed::{TypedBucket, TypedIter};
@@ -1,8 +1,12 @@
+use std::cmp::Ordering;
+use std::convert::TryInto;
+
 use crate::db::tests::db_mock;
+use crate::db::CheckMode;
 use crate::errors::Error;
 use crate::tx::WeakTx;
 
-use super::Bucket;
+use super::{Bucket, IBucket, TypedBucket};
 
 pub(crate) fn bucket_mock(tx: WeakTx) -> Bucket {
     Bucket::new(tx)
@@ -10,7 +14,7 @@ pub(crate) fn bucket_mock(tx: WeakTx) -> Bucket {
 
 #[test]
 fn create() {
-    let mut db = db_mock().build().unwrap();
+    let db = db_mock().build().unwrap();
     let mut tx = db.begin_rw_tx().unwrap();
     assert!(tx.bucket(b"foo").is_err());
 
@@ -23,13 +27,13 @@ fn create() {
 
     bucket.create_bucket(b"subbucket").unwrap();
     assert!(bucket.get(b"subbucket").is_none());
-    assert!(bucket.bucket(b"subbucket").is_some());
+    assert!(bucket.bucket(b"subbucket").is_ok());
 }
 
 #[test]
 fn create_nested_buckets() {
     let path = {
-        let mut db = db_mock().autoremove(false).build().unwrap();
+        let db = db_mock().autoremove(false).build().unwrap();
         let mut tx = db.begin_rw_tx().unwrap();
         assert!(tx.bucket(b"foo").is_err());
 
@@ -54,7 +58,7 @@ fn create_nested_buckets() {
             .bucket(b"food")
             .unwrap()
             .bucket(b"fooe")
-            .is_some());
+            .is_ok());
 
         tx.commit().unwrap();
         db.path().unwrap()
@@ -72,12 +76,12 @@ fn create_nested_buckets() {
         .bucket(b"food")
         .unwrap()
         .bucket(b"fooe")
-        .is_some());
+        .is_ok());
 }
 
 #[test]
 fn delete_value() {
-    let mut db = db_mock().build().unwrap();
+    let db = db_mock().build().unwrap();
     let mut tx = db.begin_rw_tx().unwrap();
     {
         let mut bucket = tx.create_bucket(b"bucket").unwrap();
@@ -91,13 +95,748 @@ fn delete_value() {
 
 #[test]
 fn delete_bucket_err() {
-    let mut db = db_mock().build().unwrap();
+    let db = db_mock().build().unwrap();
     let mut tx = db.begin_rw_tx().unwrap();
     {
         let mut bucket = tx.create_bucket(b"bucket").unwrap();
         bucket.create_bucket(b"stan").unwrap();
         bucket.bucket(b"stan").unwrap();
-        assert_eq!(bucket.delete(b"stan"), Err(Error::IncompatibleValue));
+        assert!(matches!(
+            bucket.delete(b"stan"),
+            Err(Error::IncompatibleValue)
+        ));
     }
     tx.commit().unwrap();
 }
+
+#[test]
+fn put_borrowed() {
+    let db = db_mock().build().unwrap();
+    db.update(|tx| -> Result<(), Error> {
+        let mut bucket = tx.create_bucket(b"foo")?;
+        let value = vec![1, 2, 3];
+        bucket.put(b"slice", &value[..])?;
+        bucket.put(b"array", b"jaja")?;
+        bucket.put(b"vec", value)?;
+        bucket.put(b"slice", &b"overwritten"[..])?;
+        assert!(matches!(bucket.put(b"", &b""[..]), Err(Error::KeyRequired)));
+        Ok(())
+    })
+    .unwrap();
+
+    let tx = db.begin_tx().unwrap();
+    let bucket = tx.bucket(b"foo").unwrap();
+    assert_eq!(bucket.get(b"slice").unwrap(), b"overwritten");
+    assert_eq!(bucket.get(b"array").unwrap(), b"jaja");
+    assert_eq!(bucket.get(b"vec").unwrap(), &[1, 2, 3]);
+}
+
+#[test]
+fn put_reserve() {
+    let db = db_mock().build().unwrap();
+    db.update(|tx| -> Result<(), Error> {
+        let mut bucket = tx.create_bucket(b"foo")?;
+        for i in 0..1000u64 {
+            let value = bucket.put_reserve(&i.to_be_bytes(), 16)?;
+            assert_eq!(value, &[0; 16]);
+            value[..8].copy_from_slice(&i.to_le_bytes());
+        }
+        bucket.put(b"a", b"jaja")?;
+        bucket.put_reserve(b"a", 2)?.copy_from_slice(b"ok");
+
+        bucket.create_bucket(b"sub")?;
+        assert!(matches!(
+            bucket.put_reserve(b"sub", 1),
+            Err(Error::IncompatibleValue)
+        ));
+        Ok(())
+    })
+    .unwrap();
+
+    let tx = db.begin_tx().unwrap();
+    let bucket = tx.bucket(b"foo").unwrap();
+    assert_eq!(bucket.get(b"a").unwrap(), b"ok");
+    for i in 0..1000u64 {
+        let value = bucket.get(&i.to_be_bytes()).unwrap();
+        assert_eq!(&value[..8], &i.to_le_bytes());
+        assert_eq!(&value[8..], &[0; 8]);
+    }
+}
+
+#[test]
+fn bulk_load() {
+    let db = db_mock().build().unwrap();
+    let value = [7u8; 32];
+    let count = db
+        .update(|tx| -> Result<usize, Error> {
+            let mut bucket = tx.create_bucket(b"full")?;
+            let count =
+                bucket.bulk_load((0..20_000u32).map(|i| (i.to_be_bytes(), &value[..])), 1.0)?;
+            // fill percent applies to loaded pages only
+            assert_eq!(bucket.fill_percent(), Bucket::DEFAULT_FILL_PERCENT);
+            Ok(count)
+        })
+        .unwrap();
+    assert_eq!(count, 20_000);
+
+    db.update(|tx| -> Result<(), Error> {
+        tx.create_bucket(b"half")?.bulk_load(
+            (0..20_000u32).map(|i| (i.to_be_bytes(), value.to_vec())),
+            0.5,
+        )?;
+
+        // appending to non-empty bucket
+        tx.bucket_mut(b"full")?.bulk_load(
+            (20_000..30_000u32).map(|i| (i.to_be_bytes(), &value[..])),
+            1.0,
+        )?;
+        Ok(())
+    })
+    .unwrap();
+
+    let tx = db.begin_tx().unwrap();
+    let full = tx.bucket(b"full").unwrap();
+    let half = tx.bucket(b"half").unwrap();
+    assert_eq!(full.iter().unwrap().count(), 30_000);
+    for (i, (key, entry)) in half.iter().unwrap().enumerate() {
+        assert_eq!(key, &(i as u32).to_be_bytes());
+        assert_eq!(entry.value(), Some(&value[..]));
+    }
+    // 30000 keys packed full take fewer pages than 20000 keys packed half
+    let (full, half) = (full.stats(), half.stats());
+    assert!(full.leaf_page_n < half.leaf_page_n);
+    assert!(half.depth > 2);
+}
+
+#[test]
+fn bulk_load_after_delete() {
+    let db = db_mock().build().unwrap();
+    db.update(|tx| -> Result<(), Error> {
+        let mut bucket = tx.create_bucket(b"foo")?;
+        for i in 0..2000u32 {
+            bucket.put(&i.to_be_bytes(), vec![0; 100])?;
+        }
+        Ok(())
+    })
+    .unwrap();
+
+    db.update(|tx| -> Result<(), Error> {
+        let mut bucket = tx.bucket_mut(b"foo")?;
+        // empties the last leaf, which stays in the tree until commit
+        for i in 1900..2000u32 {
+            bucket.delete(&i.to_be_bytes())?;
+        }
+        bucket.bulk_load((2000..2010u32).map(|i| (i.to_be_bytes(), b"new")), 1.0)?;
+        for i in 2000..2010u32 {
+            assert_eq!(bucket.get(&i.to_be_bytes()), Some(&b"new"[..]));
+        }
+        Ok(())
+    })
+    .unwrap();
+
+    let tx = db.begin_tx().unwrap();
+    let bucket = tx.bucket(b"foo").unwrap();
+    let keys: Vec<u32> = bucket
+        .iter()
+        .unwrap()
+        .map(|(k, _)| u32::from_be_bytes([k[0], k[1], k[2], k[3]]))
+        .collect();
+    assert_eq!(keys, (0..1900).chain(2000..2010).collect::<Vec<_>>());
+}
+
+#[test]
+fn bulk_load_out_of_order() {
+    let db = db_mock().build().unwrap();
+    let mut tx = db.begin_rw_tx().unwrap();
+    let mut bucket = tx.create_bucket(b"foo").unwrap();
+    bucket.put(b"b", b"1").unwrap();
+
+    let items = vec![(b"c", b"2"), (b"e", b"3"), (b"d", b"4")];
+    assert!(matches!(
+        bucket.bulk_load(items, 1.0),
+        Err(Error::KeyOutOfOrder)
+    ));
+    assert!(matches!(
+        bucket.bulk_load(vec![(b"a", b"1")], 1.0),
+        Err(Error::KeyOutOfOrder)
+    ));
+    assert!(matches!(
+        bucket.bulk_load(vec![(b"c", b"1"), (b"c", b"1")], 1.0),
+        Err(Error::KeyOutOfOrder)
+    ));
+    assert!(bucket.get(b"c").is_none());
+    assert_eq!(bucket.iter().unwrap().count(), 1);
+
+    assert_eq!(bucket.bulk_load(vec![(b"c", b"2")], 1.0).unwrap(), 1);
+    assert_eq!(bucket.get(b"c").unwrap(), b"2");
+
+    // pairs of batches appended before the bad key are removed
+    let items = (10..5000u32)
+        .chain(Some(0))
+        .map(|i| (i.to_be_bytes(), b"v"));
+    assert!(matches!(
+        bucket.bulk_load(items, 1.0),
+        Err(Error::KeyOutOfOrder)
+    ));
+    assert_eq!(bucket.iter().unwrap().count(), 2);
+}
+
+#[test]
+fn fill_percent() {
+    let db = db_mock().build().unwrap();
+    db.update(|tx| -> Result<(), Error> {
+        let mut bucket = tx.create_bucket(b"default")?;
+        assert_eq!(bucket.fill_percent(), Bucket::DEFAULT_FILL_PERCENT);
+        for i in 0..10_000u32 {
+            bucket.put(&i.to_be_bytes(), &[0; 32][..])?;
+        }
+        drop(bucket);
+
+        let mut bucket = tx.create_bucket(b"append")?;
+        bucket.set_fill_percent(2.0);
+        assert_eq!(bucket.fill_percent(), 1.0);
+        bucket.set_fill_percent(0.0);
+        assert_eq!(bucket.fill_percent(), 0.1);
+        bucket.set_fill_percent(0.95);
+        for i in 0..10_000u32 {
+            bucket.put(&i.to_be_bytes(), &[0; 32][..])?;
+        }
+        Ok(())
+    })
+    .unwrap();
+
+    let tx = db.begin_tx().unwrap();
+    let default = tx.bucket(b"default").unwrap().stats();
+    let append = tx.bucket(b"append").unwrap().stats();
+    assert!(default.leaf_in_use * 10 < default.leaf_alloc * 6);
+    assert!(append.leaf_in_use * 10 > append.leaf_alloc * 9);
+    assert!(append.leaf_page_n * 3 / 2 < default.leaf_page_n);
+}
+
+fn reverse(a: &[u8], b: &[u8]) -> Ordering {
+    b.cmp(a)
+}
+
+fn case_insensitive(a: &[u8], b: &[u8]) -> Ordering {
+    a.to_ascii_lowercase().cmp(&b.to_ascii_lowercase())
+}
+
+#[test]
+fn comparator() {
+    let path = {
+        let db = db_mock()
+            .comparator(1, reverse)
+            .comparator(2, case_insensitive)
+            .autoremove(false)
+            .build()
+            .unwrap();
+        db.update(|tx| -> Result<(), Error> {
+            let mut bucket = tx.create_bucket_with_comparator(b"reverse", 1)?;
+            assert_eq!(bucket.comparator_id(), 1);
+            for i in 0..10_000u32 {
+                bucket.put(&i.to_be_bytes(), &i.to_be_bytes()[..])?;
+            }
+            let names = bucket.create_bucket_with_comparator(b"names", 2)?;
+            names.put(b"Bob", b"1")?;
+            names.put(b"alice", b"2")?;
+            names.put(b"BOB", b"3")?;
+            Ok(())
+        })
+        .unwrap();
+        // older readers must refuse comparator headers
+        assert_eq!(db.meta().unwrap().version, 3);
+        db.path().unwrap()
+    };
+
+    let db = db_mock()
+        .path(path.clone())
+        .comparator(1, reverse)
+        .comparator(2, case_insensitive)
+        .autoremove(false)
+        .build()
+        .unwrap();
+    {
+        let tx = db.begin_tx().unwrap();
+        tx.check_sync().unwrap();
+        let bucket = tx.bucket(b"reverse").unwrap();
+        let keys: Vec<u32> = bucket
+            .iter()
+            .unwrap()
+            .filter(|(_, entry)| !entry.is_bucket())
+            .map(|(k, _)| u32::from_be_bytes([k[0], k[1], k[2], k[3]]))
+            .collect();
+        assert_eq!(keys, (0..10_000).rev().collect::<Vec<_>>());
+        assert_eq!(
+            bucket.get(&42u32.to_be_bytes()).unwrap(),
+            42u32.to_be_bytes()
+        );
+
+        let range: Vec<&[u8]> = bucket
+            .range(&9_999u32.to_be_bytes()[..]..&9_997u32.to_be_bytes()[..])
+            .unwrap()
+            .map(|(k, _)| k)
+            .collect();
+        assert_eq!(range, vec![9_999u32.to_be_bytes(), 9_998u32.to_be_bytes()]);
+
+        let names = bucket.bucket(b"names").unwrap();
+        assert_eq!(names.comparator_id(), 2);
+        assert_eq!(names.get(b"bob").unwrap(), b"3");
+        let keys: Vec<&[u8]> = names.iter().unwrap().map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![&b"alice"[..], b"BOB"]);
+    }
+    drop(db);
+
+    // nested bucket with unregistered comparator isn't taken for a missing one
+    let db = db_mock()
+        .path(path.clone())
+        .comparator(1, reverse)
+        .autoremove(false)
+        .build()
+        .unwrap();
+    {
+        let mut tx = db.begin_rw_tx().unwrap();
+        let mut bucket = tx.bucket_mut(b"reverse").unwrap();
+        assert!(matches!(
+            bucket.bucket(b"names"),
+            Err(Error::ComparatorNotFound(2))
+        ));
+        assert!(matches!(
+            bucket.bucket_mut(b"names"),
+            Err(Error::ComparatorNotFound(2))
+        ));
+        assert!(matches!(
+            bucket.bucket(b"missing"),
+            Err(Error::BucketNotFound)
+        ));
+    }
+    drop(db);
+
+    let db = db_mock().path(path).build().unwrap();
+    let tx = db.begin_tx().unwrap();
+    assert!(matches!(
+        tx.bucket(b"reverse"),
+        Err(Error::ComparatorNotFound(1))
+    ));
+}
+
+#[test]
+fn comparator_id_out_of_range() {
+    let db = db_mock().build().unwrap();
+    let mut tx = db.begin_rw_tx().unwrap();
+    let bucket = tx.create_bucket(b"foo").unwrap();
+    let mut value = vec![0; IBucket::SIZE];
+    value.extend_from_slice(&(1u64 << 32).to_le_bytes());
+    assert!(matches!(
+        bucket.open_bucket(value, Bucket::FLAG | Bucket::COMPARATOR_FLAG),
+        Err(Error::Corruption { .. })
+    ));
+}
+
+#[test]
+fn comparator_not_registered() {
+    let db = db_mock().comparator(1, reverse).build().unwrap();
+    let mut tx = db.begin_rw_tx().unwrap();
+    assert!(matches!(
+        tx.create_bucket_with_comparator(b"foo", 2),
+        Err(Error::ComparatorNotFound(2))
+    ));
+    assert!(tx.bucket(b"foo").is_err());
+}
+
+#[test]
+fn compare_and_swap() {
+    let db = db_mock().build().unwrap();
+    let mut tx = db.begin_rw_tx().unwrap();
+    let mut bucket = tx.create_bucket(b"foo").unwrap();
+
+    assert!(bucket
+        .compare_and_swap(b"a", None, Some(b"1".to_vec()))
+        .unwrap());
+    assert!(!bucket
+        .compare_and_swap(b"a", None, Some(b"2".to_vec()))
+        .unwrap());
+    assert!(!bucket
+        .compare_and_swap(b"a", Some(b"2"), Some(b"3".to_vec()))
+        .unwrap());
+    assert_eq!(bucket.get(b"a").unwrap(), b"1");
+
+    assert!(bucket
+        .compare_and_swap(b"a", Some(b"1"), Some(b"2".to_vec()))
+        .unwrap());
+    assert_eq!(bucket.get(b"a").unwrap(), b"2");
+
+    assert!(bucket.compare_and_swap(b"a", Some(b"2"), None).unwrap());
+    assert!(bucket.get(b"a").is_none());
+    assert!(bucket.compare_and_swap(b"a", None, None).unwrap());
+
+    bucket.create_bucket(b"sub").unwrap();
+    assert!(matches!(
+        bucket.compare_and_swap(b"sub", None, Some(vec![])),
+        Err(Error::IncompatibleValue)
+    ));
+}
+
+#[test]
+fn update() {
+    let db = db_mock().build().unwrap();
+    db.update(|tx| -> Result<(), Error> {
+        let mut bucket = tx.create_bucket(b"counters")?;
+        for _ in 0..3 {
+            let value = bucket.update(b"visits", |v| {
+                let n = v.map_or(0, |v| u64::from_be_bytes(v.try_into().unwrap()));
+                Some((n + 1).to_be_bytes().to_vec())
+            })?;
+            assert!(value.is_some());
+        }
+        Ok(())
+    })
+    .unwrap();
+
+    let mut tx = db.begin_rw_tx().unwrap();
+    let mut bucket = tx.bucket_mut(b"counters").unwrap();
+    assert_eq!(bucket.get(b"visits").unwrap(), 3u64.to_be_bytes());
+
+    assert_eq!(bucket.update(b"visits", |_| None).unwrap(), None);
+    assert!(bucket.get(b"visits").is_none());
+    assert!(matches!(
+        bucket.update(b"", |_| None),
+        Err(Error::KeyRequired)
+    ));
+}
+
+#[test]
+fn get_or_insert_with() {
+    let db = db_mock().build().unwrap();
+    let mut tx = db.begin_rw_tx().unwrap();
+    let mut bucket = tx.create_bucket(b"foo").unwrap();
+
+    let value = bucket.get_or_insert_with(b"a", || b"1".to_vec()).unwrap();
+    assert_eq!(value, b"1");
+    let value = bucket.get_or_insert_with(b"a", || unreachable!()).unwrap();
+    assert_eq!(value, b"1");
+    assert_eq!(bucket.get(b"a").unwrap(), b"1");
+    drop(bucket);
+    tx.commit().unwrap();
+
+    let tx = db.begin_tx().unwrap();
+    assert_eq!(tx.bucket(b"foo").unwrap().get(b"a").unwrap(), b"1");
+}
+
+#[test]
+fn typed() {
+    let db = db_mock().build().unwrap();
+    let mut tx = db.begin_rw_tx().unwrap();
+    let mut bucket = tx.create_bucket(b"temperatures").unwrap();
+    bucket.create_bucket(b"nested").unwrap();
+
+    {
+        let mut typed: TypedBucket<_, i32, String> = TypedBucket::new(&mut *bucket);
+        for t in &[15, -3, 0, -40, 7] {
+            typed.put(t, &format!("{}C", t)).unwrap();
+        }
+        assert_eq!(typed.get(&-3).unwrap(), Some("-3C".to_string()));
+        assert_eq!(typed.get(&100).unwrap(), None);
+
+        let keys: Vec<i32> = typed.iter().unwrap().map(|i| i.unwrap().0).collect();
+        assert_eq!(keys, vec![-40, -3, 0, 7, 15]);
+        let keys: Vec<i32> = typed
+            .range(-3..=7)
+            .unwrap()
+            .rev()
+            .map(|i| i.unwrap().0)
+            .collect();
+        assert_eq!(keys, vec![7, 0, -3]);
+
+        typed.delete(&0).unwrap();
+        assert_eq!(typed.get(&0).unwrap(), None);
+    }
+
+    bucket.put(b"bad", b"value").unwrap();
+    let typed: TypedBucket<_, i32, String> = TypedBucket::new(&*bucket);
+    assert!(matches!(
+        typed.iter().unwrap().next(),
+        Some(Err(Error::Codec(_)))
+    ));
+}
+
+fn city(user: &[u8]) -> Vec<Vec<u8>> {
+    user.split(|b| *b == b':')
+        .skip(1)
+        .map(|c| c.to_vec())
+        .collect()
+}
+
+#[test]
+fn index_create() {
+    let db = db_mock().index("city", city).build().unwrap();
+    let mut tx = db.begin_rw_tx().unwrap();
+    let mut users = tx.create_bucket(b"users").unwrap();
+    users.put(b"1", b"alice:paris").unwrap();
+    users.put(b"2", b"bob:berlin:paris").unwrap();
+    assert!(matches!(
+        users.create_index("name"),
+        Err(Error::IndexNotRegistered(_))
+    ));
+    users.create_index("city").unwrap();
+    assert_eq!(users.indexes(), vec!["city".to_string()]);
+    assert!(matches!(
+        users.create_index("city"),
+        Err(Error::BucketExists)
+    ));
+
+    assert_eq!(
+        users.index_keys("city", b"paris").unwrap(),
+        vec![&b"1"[..], b"2"]
+    );
+    assert_eq!(users.index_keys("city", b"berlin").unwrap(), vec![b"2"]);
+    assert!(matches!(
+        users.index_keys("name", b"alice"),
+        Err(Error::IndexNotFound(_))
+    ));
+
+    // internal bucket is hidden from lookup and iteration
+    assert!(users.indexes_bucket().unwrap().is_some());
+    assert!(users.bucket(b"\x00indexes").is_err());
+    assert_eq!(users.iter().unwrap().count(), 2);
+    assert_eq!(users.iter().unwrap().rev().count(), 2);
+    assert!(users.buckets().is_empty());
+}
+
+#[test]
+fn index_reserved_name() {
+    let db = db_mock().index("city", city).build().unwrap();
+    let mut tx = db.begin_rw_tx().unwrap();
+    let mut users = tx.create_bucket(b"users").unwrap();
+    users.put(b"1", b"alice:paris").unwrap();
+    users.create_bucket(b"\x00indexes").unwrap();
+
+    // user bucket with the same name is an ordinary bucket
+    assert!(users.bucket(b"\x00indexes").is_ok());
+    assert!(users.indexes_bucket().unwrap().is_none());
+    assert_eq!(users.iter().unwrap().count(), 2);
+    assert_eq!(users.buckets(), vec![b"\x00indexes".to_vec()]);
+    assert!(matches!(
+        users.create_index("city"),
+        Err(Error::IncompatibleValue)
+    ));
+
+    users.delete_bucket(b"\x00indexes").unwrap();
+    users.put(b"\x00indexes", b"value").unwrap();
+    assert_eq!(users.iter().unwrap().count(), 2);
+    assert!(matches!(
+        users.create_index("city"),
+        Err(Error::IncompatibleValue)
+    ));
+    assert!(users.indexes().is_empty());
+}
+
+#[test]
+fn index_put() {
+    let db = db_mock().index("city", city).build().unwrap();
+    db.update(|tx| -> Result<(), Error> {
+        let mut users = tx.create_bucket(b"users")?;
+        users.create_index("city")?;
+        users.put(b"1", b"alice:paris")?;
+        users.put(b"3", b"carol:par")?;
+        users.put(b"1", b"alice:rome")?;
+        users.put(b"4", b"dave:paris")?;
+        // sorted by key, although length suffix puts "a" after "a\0" in the index
+        users.put(b"a", b"x:oslo")?;
+        users.put(b"a\x00", b"y:oslo")?;
+        Ok(())
+    })
+    .unwrap();
+
+    let tx = db.begin_tx().unwrap();
+    tx.check_sync().unwrap();
+    let users = tx.bucket(b"users").unwrap();
+    assert_eq!(users.index_keys("city", b"paris").unwrap(), vec![b"4"]);
+    assert_eq!(users.index_keys("city", b"par").unwrap(), vec![b"3"]);
+    assert_eq!(
+        users.index_keys("city", b"oslo").unwrap(),
+        vec![&b"a"[..], b"a\x00"]
+    );
+    assert_eq!(
+        users.index_get("city", b"rome").unwrap(),
+        vec![(&b"1"[..], &b"alice:rome"[..])]
+    );
+}
+
+#[test]
+fn index_delete() {
+    let db = db_mock().index("city", city).build().unwrap();
+    db.update(|tx| -> Result<(), Error> {
+        let mut users = tx.create_bucket(b"users")?;
+        users.create_index("city")?;
+        for i in 0..10u8 {
+            users.put(&[i], b"user:paris")?;
+        }
+        users.delete(&[2])?;
+
+        let mut c = users.cursor()?;
+        c.seek(&[5])?;
+        c.delete()?;
+        Ok(())
+    })
+    .unwrap();
+
+    let tx = db.begin_tx().unwrap();
+    tx.check_sync().unwrap();
+    let users = tx.bucket(b"users").unwrap();
+    let keys = users.index_keys("city", b"paris").unwrap();
+    assert_eq!(keys, vec![[0], [1], [3], [4], [6], [7], [8], [9]]);
+}
+
+#[test]
+fn index_modify() {
+    let db = db_mock().index("city", city).build().unwrap();
+    db.update(|tx| -> Result<(), Error> {
+        let mut users = tx.create_bucket(b"users")?;
+        users.create_index("city")?;
+        users.put(b"1", b"alice:paris")?;
+        users.put(b"2", b"bob:paris")?;
+        users.compare_and_swap(b"3", None, Some(b"carol:paris".to_vec()))?;
+        users.compare_and_swap(b"1", Some(b"alice:paris"), None)?;
+        users.update(b"2", |_| Some(b"bob:rome".to_vec()))?;
+        users.get_or_insert_with(b"4", || b"dave:rome".to_vec())?;
+        Ok(())
+    })
+    .unwrap();
+
+    let tx = db.begin_tx().unwrap();
+    tx.check_sync().unwrap();
+    let users = tx.bucket(b"users").unwrap();
+    assert_eq!(users.index_keys("city", b"paris").unwrap(), vec![b"3"]);
+    assert_eq!(
+        users.index_keys("city", b"rome").unwrap(),
+        vec![&b"2"[..], b"4"]
+    );
+}
+
+#[test]
+fn index_bulk_load() {
+    let db = db_mock().index("city", city).build().unwrap();
+    db.update(|tx| -> Result<(), Error> {
+        let mut users = tx.create_bucket(b"users")?;
+        users.create_index("city")?;
+        users.put(b"1", b"alice:paris")?;
+        users.bulk_load(vec![(b"2", &b"bob:rome"[..]), (b"3", b"carol:paris")], 0.5)?;
+        assert!(matches!(
+            users.put_reserve(b"4", 1),
+            Err(Error::IndexedBucket)
+        ));
+
+        // failed load leaves no index entries
+        let items = (4..3000u32)
+            .map(|i| (format!("9{:05}", i), &b"dave:oslo"[..]))
+            .chain(Some(("0".to_string(), &b"eve:oslo"[..])));
+        assert!(matches!(
+            users.bulk_load(items, 1.0),
+            Err(Error::KeyOutOfOrder)
+        ));
+        assert!(users.index_keys("city", b"oslo")?.is_empty());
+        Ok(())
+    })
+    .unwrap();
+
+    let tx = db.begin_tx().unwrap();
+    tx.check_sync().unwrap();
+    let users = tx.bucket(b"users").unwrap();
+    assert_eq!(
+        users.index_keys("city", b"paris").unwrap(),
+        vec![&b"1"[..], b"3"]
+    );
+    assert_eq!(users.index_keys("city", b"rome").unwrap(), vec![b"2"]);
+}
+
+#[test]
+fn index_rebuild() {
+    let db = db_mock()
+        .index("city", city)
+        .checkmode(CheckMode::NO)
+        .build()
+        .unwrap();
+    db.update(|tx| -> Result<(), Error> {
+        let mut users = tx.create_bucket(b"users")?;
+        users.create_index("city")?;
+        users.put(b"4", b"dave:paris")?;
+
+        // break the index behind its back
+        let indexes = users.indexes_bucket_mut()?.unwrap();
+        let index = indexes.bucket_mut(b"city").unwrap();
+        index.delete(b"paris4\x00\x00\x00\x05")?;
+        Ok(())
+    })
+    .unwrap();
+    match db.begin_tx().unwrap().check_sync() {
+        Err(Error::CheckFail(errors)) => assert_eq!(errors.len(), 1),
+        r => panic!("unexpected check result {:?}", r),
+    }
+
+    db.update(|tx| tx.bucket_mut(b"users")?.rebuild_index("city"))
+        .unwrap();
+    let tx = db.begin_tx().unwrap();
+    tx.check_sync().unwrap();
+    let users = tx.bucket(b"users").unwrap();
+    assert_eq!(users.index_keys("city", b"paris").unwrap(), vec![b"4"]);
+}
+
+#[test]
+fn index_drop() {
+    let path = {
+        let db = db_mock()
+            .index("city", city)
+            .autoremove(false)
+            .build()
+            .unwrap();
+        db.update(|tx| -> Result<(), Error> {
+            let mut users = tx.create_bucket(b"users")?;
+            users.create_index("city")?;
+            users.put(b"4", b"dave:paris")?;
+            Ok(())
+        })
+        .unwrap();
+        db.path().unwrap()
+    };
+
+    // extractor isn't registered, so the index can't be updated
+    let db = db_mock().path(path).build().unwrap();
+    db.update(|tx| -> Result<(), Error> {
+        let mut users = tx.bucket_mut(b"users")?;
+        assert_eq!(users.index_keys("city", b"paris")?, vec![b"4"]);
+        assert!(matches!(
+            users.put(b"6", b"frank:oslo"),
+            Err(Error::IndexNotRegistered(_))
+        ));
+        assert!(users.get(b"6").is_none());
+        users.drop_index("city")?;
+        assert!(users.indexes().is_empty());
+        assert!(users.indexes_bucket()?.is_none());
+        assert!(matches!(
+            users.drop_index("city"),
+            Err(Error::IndexNotFound(_))
+        ));
+        users.put(b"6", b"frank:oslo")?;
+        Ok(())
+    })
+    .unwrap();
+    db.begin_tx().unwrap().check_sync().unwrap();
+}
+
+#[test]
+fn index_delete_bucket() {
+    let db = db_mock().index("city", city).build().unwrap();
+    db.update(|tx| -> Result<(), Error> {
+        let mut users = tx.create_bucket(b"users")?;
+        users.create_index("city")?;
+        for i in 0..1000u32 {
+            users.put(&i.to_be_bytes(), b"user:paris")?;
+        }
+        Ok(())
+    })
+    .unwrap();
+
+    // pages of indexes are freed with the bucket
+    db.update(|tx| tx.delete_bucket(b"users")).unwrap();
+    db.begin_tx().unwrap().check_sync().unwrap();
+}
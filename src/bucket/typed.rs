@@ -0,0 +1,152 @@
+use std::marker::PhantomData;
+use std::ops::{Bound, Deref, DerefMut, RangeBounds};
+
+use crate::codec::{Codec, NativeCodec};
+use crate::errors::Error;
+
+use super::{Bucket, Entry, Iter};
+
+/// Bucket wrapper which encodes keys and values of types K and V with codec C
+///
+/// Wraps anything dereferencing to Bucket, like &Bucket or bucket guard returned by Tx,
+/// mutating methods require mutable bucket.
+/// Nested buckets are skipped by iterators.
+///
+/// # Example
+///
+/// ```
+/// use nut::{DBBuilder, TypedBucket};
+///
+/// let db = DBBuilder::in_memory().build().unwrap();
+/// let mut tx = db.begin_rw_tx().unwrap();
+/// let mut users: TypedBucket<_, u64, String> = TypedBucket::new(tx.create_bucket(b"users").unwrap());
+///
+/// users.put(&2, &"bob".to_string()).unwrap();
+/// users.put(&1, &"alice".to_string()).unwrap();
+///
+/// assert_eq!(users.get(&1).unwrap(), Some("alice".to_string()));
+/// let ids: Vec<u64> = users.iter().unwrap().map(|item| item.unwrap().0).collect();
+/// assert_eq!(ids, vec![1, 2]);
+/// ```
+pub struct TypedBucket<B, K, V, C = NativeCodec> {
+    bucket: B,
+    _m: PhantomData<fn() -> (K, V, C)>,
+}
+
+impl<B, K, V, C> TypedBucket<B, K, V, C>
+where
+    B: Deref<Target = Bucket>,
+    C: Codec<K> + Codec<V>,
+{
+    pub fn new(bucket: B) -> Self {
+        Self {
+            bucket,
+            _m: PhantomData,
+        }
+    }
+
+    /// Returns underlying bucket
+    pub fn bucket(&self) -> &Bucket {
+        &self.bucket
+    }
+
+    /// Unwraps underlying bucket
+    pub fn into_inner(self) -> B {
+        self.bucket
+    }
+
+    /// Retrieves and decodes the value for a key.
+    /// Returns None if the key does not exist or if the key is a nested bucket.
+    pub fn get(&self, key: &K) -> Result<Option<V>, Error> {
+        let key = <C as Codec<K>>::encode(key)?;
+        self.bucket
+            .get(&key)
+            .map(<C as Codec<V>>::decode)
+            .transpose()
+    }
+
+    /// Returns iterator over all decoded key/value pairs in the order of encoded keys.
+    pub fn iter(&self) -> Result<TypedIter<'_, K, V, C>, Error> {
+        Ok(TypedIter::new(self.bucket.iter()?))
+    }
+
+    /// Returns iterator over decoded key/value pairs within given key range.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Result<TypedIter<'_, K, V, C>, Error> {
+        let bound = |b: Bound<&K>| -> Result<Bound<Vec<u8>>, Error> {
+            Ok(match b {
+                Bound::Included(k) => Bound::Included(<C as Codec<K>>::encode(k)?),
+                Bound::Excluded(k) => Bound::Excluded(<C as Codec<K>>::encode(k)?),
+                Bound::Unbounded => Bound::Unbounded,
+            })
+        };
+        let range = (bound(range.start_bound())?, bound(range.end_bound())?);
+        Ok(TypedIter::new(self.bucket.range(range)?))
+    }
+}
+
+impl<B, K, V, C> TypedBucket<B, K, V, C>
+where
+    B: DerefMut<Target = Bucket>,
+    C: Codec<K> + Codec<V>,
+{
+    /// Encodes and sets the value for a key.
+    /// Returns same errors as Bucket.put and codec errors.
+    pub fn put(&mut self, key: &K, value: &V) -> Result<(), Error> {
+        let key = <C as Codec<K>>::encode(key)?;
+        let value = <C as Codec<V>>::encode(value)?;
+        self.bucket.put(&key, value)
+    }
+
+    /// Removes a key from the bucket.
+    /// Returns same errors as Bucket.delete and codec errors.
+    pub fn delete(&mut self, key: &K) -> Result<(), Error> {
+        let key = <C as Codec<K>>::encode(key)?;
+        self.bucket.delete(&key)
+    }
+}
+
+/// Iterator over decoded key/value pairs of TypedBucket
+///
+/// Yields an error for pair which can't be decoded.
+pub struct TypedIter<'a, K, V, C> {
+    inner: Iter<'a>,
+    _m: PhantomData<fn() -> (K, V, C)>,
+}
+
+impl<'a, K, V, C: Codec<K> + Codec<V>> TypedIter<'a, K, V, C> {
+    fn new(inner: Iter<'a>) -> Self {
+        Self {
+            inner,
+            _m: PhantomData,
+        }
+    }
+
+    fn decode(key: &[u8], value: &[u8]) -> Result<(K, V), Error> {
+        Ok((
+            <C as Codec<K>>::decode(key)?,
+            <C as Codec<V>>::decode(value)?,
+        ))
+    }
+}
+
+impl<'a, K, V, C: Codec<K> + Codec<V>> Iterator for TypedIter<'a, K, V, C> {
+    type Item = Result<(K, V), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let (key, Entry::Value(value)) = self.inner.next()? {
+                return Some(Self::decode(key, value));
+            }
+        }
+    }
+}
+
+impl<'a, K, V, C: Codec<K> + Codec<V>> DoubleEndedIterator for TypedIter<'a, K, V, C> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        loop {
+            if let (key, Entry::Value(value)) = self.inner.next_back()? {
+                return Some(Self::decode(key, value));
+            }
+        }
+    }
+}
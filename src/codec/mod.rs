@@ -0,0 +1,113 @@
+#[cfg(test)]
+mod tests;
+
+use std::convert::TryInto;
+
+use crate::errors::Error;
+
+/// Converts values of type T to bytes stored in a bucket and back
+///
+/// One codec can implement Codec for several types,
+/// TypedBucket requires its codec to handle both key and value types.
+/// Keys are ordered by their encoded bytes, so key codec should preserve order
+/// to make ranges and iteration order meaningful.
+pub trait Codec<T> {
+    /// Encodes value into bytes
+    fn encode(value: &T) -> Result<Vec<u8>, Error>;
+
+    /// Decodes value from bytes
+    fn decode(bytes: &[u8]) -> Result<T, Error>;
+}
+
+/// Codec for integers, strings and raw bytes
+///
+/// Integers are encoded big-endian with flipped sign bit for signed ones,
+/// so bytewise order of encoded integers matches their numeric order.
+/// Strings are encoded as UTF-8 and raw bytes are stored as is.
+#[derive(Debug, Clone, Copy)]
+pub struct NativeCodec;
+
+macro_rules! native_int {
+    ($($t:ty => $u:ty),*) => {
+        $(
+            impl Codec<$t> for NativeCodec {
+                fn encode(value: &$t) -> Result<Vec<u8>, Error> {
+                    let flip = <$t>::MIN as $u;
+                    Ok(((*value as $u) ^ flip).to_be_bytes().to_vec())
+                }
+
+                fn decode(bytes: &[u8]) -> Result<$t, Error> {
+                    let bytes = bytes.try_into().map_err(|_| {
+                        Error::Codec(format!(
+                            "expected {} bytes for {}, got {}",
+                            std::mem::size_of::<$t>(),
+                            stringify!($t),
+                            bytes.len()
+                        ))
+                    })?;
+                    let flip = <$t>::MIN as $u;
+                    Ok((<$u>::from_be_bytes(bytes) ^ flip) as $t)
+                }
+            }
+        )*
+    };
+}
+
+native_int!(
+    u8 => u8, u16 => u16, u32 => u32, u64 => u64, u128 => u128,
+    i8 => u8, i16 => u16, i32 => u32, i64 => u64, i128 => u128
+);
+
+impl Codec<String> for NativeCodec {
+    fn encode(value: &String) -> Result<Vec<u8>, Error> {
+        Ok(value.as_bytes().to_vec())
+    }
+
+    fn decode(bytes: &[u8]) -> Result<String, Error> {
+        String::from_utf8(bytes.to_vec()).map_err(|e| Error::Codec(e.to_string()))
+    }
+}
+
+impl Codec<Vec<u8>> for NativeCodec {
+    fn encode(value: &Vec<u8>) -> Result<Vec<u8>, Error> {
+        Ok(value.clone())
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Vec<u8>, Error> {
+        Ok(bytes.to_vec())
+    }
+}
+
+/// Codec for any serde type using bincode
+///
+/// Bincode encodes integers little-endian, so it's not order preserving for keys.
+#[cfg(feature = "bincode")]
+#[derive(Debug, Clone, Copy)]
+pub struct BincodeCodec;
+
+#[cfg(feature = "bincode")]
+impl<T: serde::Serialize + serde::de::DeserializeOwned> Codec<T> for BincodeCodec {
+    fn encode(value: &T) -> Result<Vec<u8>, Error> {
+        bincode::serialize(value).map_err(|e| Error::Codec(e.to_string()))
+    }
+
+    fn decode(bytes: &[u8]) -> Result<T, Error> {
+        bincode::deserialize(bytes).map_err(|e| Error::Codec(e.to_string()))
+    }
+}
+
+/// Codec for any serde type using JSON
+#[cfg(feature = "json")]
+#[derive(Debug, Clone, Copy)]
+pub struct JsonCodec;
+
+#[cfg(feature = "json")]
+impl<T: serde::Serialize + serde::de::DeserializeOwned> Codec<T> for JsonCodec {
+    fn encode(value: &T) -> Result<Vec<u8>, Error> {
+        serde_json::to_vec(value).map_err(|e| Error::Codec(e.to_string()))
+    }
+
+    fn decode(bytes: &[u8]) -> Result<T, Error> {
+        serde_json::from_slice(bytes).map_err(|e| Error::Codec(e.to_string()))
+    }
+}
@@ -0,0 +1,74 @@
+use crate::errors::Error;
+
+use super::{Codec, NativeCodec};
+
+fn roundtrip<T: PartialEq + std::fmt::Debug>(value: T)
+where
+    NativeCodec: Codec<T>,
+{
+    let bytes = NativeCodec::encode(&value).unwrap();
+    assert_eq!(NativeCodec::decode(&bytes).unwrap(), value);
+}
+
+#[test]
+fn native_roundtrip() {
+    roundtrip(0u8);
+    roundtrip(u64::MAX);
+    roundtrip(i32::MIN);
+    roundtrip(-1i64);
+    roundtrip(i128::MAX);
+    roundtrip("hello".to_string());
+    roundtrip(vec![0u8, 255, 1]);
+}
+
+#[test]
+fn native_int_order() {
+    let values = [i64::MIN, -1000, -1, 0, 1, 1000, i64::MAX];
+    let encoded: Vec<Vec<u8>> = values
+        .iter()
+        .map(|v| NativeCodec::encode(v).unwrap())
+        .collect();
+    let mut sorted = encoded.clone();
+    sorted.sort();
+    assert_eq!(encoded, sorted);
+    assert_eq!(NativeCodec::encode(&1u16).unwrap(), vec![0, 1]);
+}
+
+#[test]
+fn native_decode_err() {
+    assert!(matches!(
+        <NativeCodec as Codec<u32>>::decode(&[1, 2]),
+        Err(Error::Codec(_))
+    ));
+    assert!(matches!(
+        <NativeCodec as Codec<String>>::decode(&[0xff]),
+        Err(Error::Codec(_))
+    ));
+}
+
+#[cfg(feature = "bincode")]
+#[test]
+fn bincode_roundtrip() {
+    use super::BincodeCodec;
+
+    let value = (42u32, "answer".to_string(), vec![1.5f64]);
+    let bytes = BincodeCodec::encode(&value).unwrap();
+    let decoded: (u32, String, Vec<f64>) = BincodeCodec::decode(&bytes).unwrap();
+    assert_eq!(decoded, value);
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn json_roundtrip() {
+    use super::JsonCodec;
+
+    let value = (42u32, "answer".to_string(), vec![1.5f64]);
+    let bytes = JsonCodec::encode(&value).unwrap();
+    assert_eq!(bytes, br#"[42,"answer",[1.5]]"#.to_vec());
+    let decoded: (u32, String, Vec<f64>) = JsonCodec::decode(&bytes).unwrap();
+    assert_eq!(decoded, value);
+    assert!(matches!(
+        <JsonCodec as Codec<u32>>::decode(b"x"),
+        Err(Error::Codec(_))
+    ));
+}
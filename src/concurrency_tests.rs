@@ -1,9 +1,10 @@
 use crate::db::tests::db_mock;
+use crate::errors::Error;
 use std::thread;
 use std::time::Duration;
 
 fn perform_read(number_of_threads: usize, number_of_values: usize) {
-    let mut db = db_mock().build().unwrap();
+    let db = db_mock().build().unwrap();
     {
         let mut tx = db.begin_rw_tx().unwrap();
         let mut bucket = tx.create_bucket(b"bucket").unwrap();
@@ -11,7 +12,7 @@ fn perform_read(number_of_threads: usize, number_of_values: usize) {
             bucket
                 .put(
                     format!("{}", i).as_bytes(),
-                    format!("{}", i * 100000).into(),
+                    format!("{}", i * 100000).into_bytes(),
                 )
                 .unwrap();
         }
@@ -21,7 +22,7 @@ fn perform_read(number_of_threads: usize, number_of_values: usize) {
     for _ in 0..number_of_threads {
         let db = db.clone();
         handles.push(thread::spawn(move || {
-            db.view(Box::new(|tx| {
+            db.view(|tx| -> Result<(), Error> {
                 let bucket = tx.bucket(b"bucket").unwrap();
                 for i in 10..10 + number_of_values {
                     let expected = format!("{}", i * 100000);
@@ -30,7 +31,7 @@ fn perform_read(number_of_threads: usize, number_of_values: usize) {
                     assert_eq!(expected.as_bytes(), value);
                 }
                 Ok(())
-            }))
+            })
             .unwrap();
         }));
     }
@@ -108,7 +109,7 @@ fn multiple_writers_multiple_readers() {
     let n_wvalues = 200;
     let n_wthreads = 5;
 
-    let mut db = db_mock()
+    let db = db_mock()
         // Strong because Paranoid check can fail in case
         // of parallel read/write transaction on after read check
         .checkmode(CheckMode::STRONG)
@@ -127,7 +128,7 @@ fn multiple_writers_multiple_readers() {
 
         for n in 0..n_wthreads {
             // writer
-            let mut db = db.clone();
+            let db = db.clone();
             handles.push(thread::spawn(move || {
                 let mut tx = db.begin_rw_tx().unwrap();
                 let mut bucket = tx.bucket_mut(b"bucket").unwrap();
@@ -135,7 +136,7 @@ fn multiple_writers_multiple_readers() {
                     bucket
                         .put(
                             format!("{}", i).as_bytes(),
-                            format!("{}{v}{v}{v}{v}{v}{v}{v}", n, v = i).into(),
+                            format!("{}{v}{v}{v}{v}{v}{v}{v}", n, v = i).into_bytes(),
                         )
                         .unwrap();
                     thread::sleep(Duration::from_nanos(10));
@@ -161,3 +162,115 @@ fn multiple_writers_multiple_readers() {
         }
     }
 }
+
+#[test]
+fn db_is_send_and_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<crate::DB>();
+}
+
+#[test]
+fn shared_handle_stress() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    let n_writers = 8;
+    let n_readers = 8;
+    let n_updates = 30;
+
+    use crate::CheckMode;
+    let db = db_mock()
+        // read check may fail while parallel write transaction frees pages
+        .checkmode(CheckMode::STRONG)
+        .no_sync(true)
+        .build()
+        .unwrap();
+    let db = Arc::new(db);
+    db.update(|tx| -> Result<(), Error> {
+        tx.create_bucket(b"counter")?
+            .put(b"n", 0u64.to_be_bytes().to_vec())?;
+        let _ = tx.create_bucket(b"items")?;
+        Ok(())
+    })
+    .unwrap();
+
+    // counter always equals number of items in committed state
+    fn check(tx: &crate::Tx) -> Result<u64, Error> {
+        let counter = tx.bucket(b"counter")?;
+        let mut n = [0u8; 8];
+        n.copy_from_slice(counter.get(b"n").unwrap());
+        let n = u64::from_be_bytes(n);
+
+        let items = tx.bucket(b"items")?;
+        let mut count = 0;
+        items.for_each(Box::new(|_, _| -> Result<(), Error> {
+            count += 1;
+            Ok(())
+        }))?;
+        assert_eq!(n, count);
+        Ok(n)
+    }
+
+    fn increment(tx: &mut crate::Tx, key: String) -> Result<(), Error> {
+        let n = check(tx)?;
+        tx.bucket_mut(b"counter")?
+            .put(b"n", (n + 1).to_be_bytes().to_vec())?;
+        tx.bucket_mut(b"items")?.put(key.as_bytes(), vec![0; 64])?;
+        Ok(())
+    }
+
+    let done = Arc::new(AtomicBool::new(false));
+    let mut readers = vec![];
+    for _ in 0..n_readers {
+        let db = db.clone();
+        let done = done.clone();
+        readers.push(thread::spawn(move || {
+            let mut last = 0;
+            while !done.load(Ordering::Acquire) {
+                let n = db.view(check).unwrap();
+                assert!(n >= last);
+                last = n;
+            }
+        }));
+    }
+
+    let mut writers = vec![];
+    for w in 0..n_writers {
+        let db = db.clone();
+        writers.push(thread::spawn(move || {
+            for i in 0..n_updates {
+                let key = format!("{}-{}", w, i);
+                match i % 3 {
+                    0 => db.update(|tx| increment(tx, key)).unwrap(),
+                    1 => {
+                        let mut tx = db.begin_rw_tx().unwrap();
+                        increment(&mut tx, key).unwrap();
+                        tx.commit().unwrap();
+                    }
+                    _ => loop {
+                        match db.try_begin_rw_tx() {
+                            Ok(mut tx) => {
+                                increment(&mut tx, key).unwrap();
+                                tx.commit().unwrap();
+                                break;
+                            }
+                            Err(Error::WriteInProgress) => thread::yield_now(),
+                            Err(e) => panic!("{}", e),
+                        }
+                    },
+                }
+            }
+        }));
+    }
+
+    for handle in writers {
+        handle.join().unwrap();
+    }
+    done.store(true, Ordering::Release);
+    for handle in readers {
+        handle.join().unwrap();
+    }
+
+    let n = db.view(check).unwrap();
+    assert_eq!(n, (n_writers * n_updates) as u64);
+}
@@ -17,18 +17,30 @@ pub(crate) type TXID = u64;
 
 pub(crate) const MAX_MMAP_STEP: u64 = 1 << 30;
 
+/// Meta.freelist of database which doesn't store freelist,
+/// freelist is rebuilt from reachable pages on open instead.
+pub(crate) const PGID_NO_FREELIST: PGID = 0xFFFF_FFFF_FFFF_FFFF;
+
 /// database mime header
 pub(crate) const MAGIC: u32 = 0xED0C_DAED;
 
 /// database version
 pub(crate) const VERSION: u32 = 2;
 
-// TODO: openbsd
-pub(crate) const IGNORE_NOSYNC: bool = true;
+/// version of database having buckets with custom key comparators,
+/// so readers which don't know comparator headers refuse to open it
+pub(crate) const COMPARATOR_VERSION: u32 = 3;
+
+/// OpenBSD has no unified buffer cache,
+/// so writes must be synced to be visible through the mmap.
+pub(crate) const IGNORE_NOSYNC: bool = cfg!(target_os = "openbsd");
 
 pub(crate) const DEFAULT_MAX_BATCH_SIZE: usize = 1000;
 
 pub(crate) const DEFAULT_MAX_BATCH_DELAY: Duration = Duration::from_millis(10000);
+
+/// Interval between attempts to obtain file lock when lock timeout is set
+pub(crate) const FLOCK_RETRY_TIMEOUT: Duration = Duration::from_millis(50);
 // pub(crate) const DEFAULT_ALLOC_SIZE: u64 = 16 * 1024 * 1024;
 
 bitflags! {
@@ -1,21 +1,34 @@
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
 
-use super::db::{CheckMode, DB};
+use super::db::{CheckMode, SyncMode, DB};
+use crate::bucket::{Comparator, IndexFn};
 use crate::consts::{DEFAULT_MAX_BATCH_DELAY, DEFAULT_MAX_BATCH_SIZE};
 use crate::errors::Error;
+use crate::freelist::FreelistType;
+use crate::storage::{MemoryStorage, Storage};
 
 /// Options that can be set when opening a database.
 pub(super) struct Options {
+    pub(super) no_sync: bool,
+    pub(super) sync_mode: SyncMode,
     pub(super) no_grow_sync: bool,
+    pub(super) no_freelist_sync: bool,
+    pub(super) auto_shrink: bool,
     pub(super) read_only: bool,
     pub(super) ignore_flock: bool,
+    pub(super) lock_timeout: Option<Duration>,
     pub(super) initial_mmap_size: usize,
     pub(super) autoremove: bool,
     pub(super) checkmode: CheckMode,
     pub(super) max_batch_delay: Duration,
     pub(super) max_batch_size: usize,
     pub(super) page_size: usize,
+    pub(super) freelist_type: FreelistType,
+    pub(super) comparators: HashMap<u32, Comparator>,
+    pub(super) indexes: HashMap<String, IndexFn>,
 }
 
 /// Struct to construct database
@@ -28,39 +41,116 @@ pub(super) struct Options {
 /// let db = DBBuilder::new("./test.db").read_only(true).build();
 /// ```
 pub struct DBBuilder {
-    path: PathBuf,
+    path: Option<PathBuf>,
+    storage: Option<Box<dyn Storage>>,
+    no_sync: bool,
+    sync_mode: SyncMode,
     no_grow_sync: bool,
+    no_freelist_sync: bool,
+    auto_shrink: bool,
     read_only: bool,
     ignore_flock: bool,
+    lock_timeout: Option<Duration>,
     initial_mmap_size: usize,
     autoremove: bool,
     checkmode: CheckMode,
     max_batch_delay: Duration,
     max_batch_size: usize,
     page_size: usize,
+    freelist_type: FreelistType,
+    comparators: HashMap<u32, Comparator>,
+    indexes: HashMap<String, IndexFn>,
 }
 
 impl DBBuilder {
     /// Creates new Builder,
     /// path required.
     pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self::with_path(Some(path.as_ref().to_owned()))
+    }
+
+    /// Creates new Builder for database which lives only in memory.
+    ///
+    /// Nothing touches the filesystem and data is gone once database is dropped,
+    /// use Tx.write_to or Tx.copy_to to save snapshot to file.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use nut::DBBuilder;
+    ///
+    /// let db = DBBuilder::in_memory().build().unwrap();
+    /// assert_eq!(db.path(), None);
+    /// ```
+    pub fn in_memory() -> Self {
+        let mut builder = Self::with_path(None);
+        builder.storage = Some(Box::new(MemoryStorage::new()));
+        builder
+    }
+
+    fn with_path(path: Option<PathBuf>) -> Self {
         Self {
-            path: path.as_ref().to_owned(),
+            path,
+            storage: None,
+            no_sync: false,
+            sync_mode: SyncMode::Full,
             no_grow_sync: false,
+            no_freelist_sync: false,
+            auto_shrink: false,
             read_only: false,
             ignore_flock: false,
+            lock_timeout: None,
             initial_mmap_size: 0,
             autoremove: false,
             checkmode: CheckMode::NO,
             max_batch_delay: DEFAULT_MAX_BATCH_DELAY,
             max_batch_size: DEFAULT_MAX_BATCH_SIZE,
             page_size: page_size::get(),
+            freelist_type: FreelistType::default(),
+            comparators: HashMap::new(),
+            indexes: HashMap::new(),
         }
     }
 
     /// Path to db file
     pub fn path<P: AsRef<Path>>(mut self, path: P) -> Self {
-        self.path = path.as_ref().to_owned();
+        self.path = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// Defines storage backend to open database on.
+    /// If not set, file at path is opened with MmapStorage.
+    ///
+    /// Path is still used as database path, e.g. for autoremove.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use nut::{CachedFileStorage, DBBuilder};
+    ///
+    /// let storage = CachedFileStorage::open("./test.db", false).unwrap();
+    /// let db = DBBuilder::new("./test.db").storage(storage).build();
+    /// ```
+    pub fn storage<S: Storage + 'static>(mut self, v: S) -> Self {
+        self.storage = Some(Box::new(v));
+        self
+    }
+
+    /// Skips fdatasync on commit, same as sync_mode(SyncMode::None).
+    ///
+    /// Ignored on platforms which require syncing to keep mmap consistent (OpenBSD).
+    ///
+    /// Default: false
+    pub fn no_sync(mut self, v: bool) -> Self {
+        self.no_sync = v;
+        self
+    }
+
+    /// Defines how commits are flushed to stable storage
+    ///
+    /// Default: SyncMode::Full
+    pub fn sync_mode(mut self, v: SyncMode) -> Self {
+        self.sync_mode = v;
         self
     }
 
@@ -72,6 +162,30 @@ impl DBBuilder {
         self
     }
 
+    /// Skips writing freelist on commit, it's rebuilt by walking
+    /// all reachable pages on open instead.
+    /// Speeds up commits of databases with big freelist at the cost of slower open.
+    ///
+    /// Files are compatible both ways, database written with this option is
+    /// given a freelist on the first open without it.
+    ///
+    /// Default: false
+    pub fn no_freelist_sync(mut self, v: bool) -> Self {
+        self.no_freelist_sync = v;
+        self
+    }
+
+    /// Releases free pages at the end of the file on every commit
+    /// and truncates the file, see DB::shrink().
+    ///
+    /// File isn't truncated while read transactions are open.
+    ///
+    /// Default: false
+    pub fn auto_shrink(mut self, v: bool) -> Self {
+        self.auto_shrink = v;
+        self
+    }
+
     /// Open database in read-only mode.
     ///
     /// If database opened in read only mode file will be locked shared
@@ -95,6 +209,17 @@ impl DBBuilder {
         self
     }
 
+    /// Amount of time to wait for the file lock held by another process.
+    ///
+    /// Lock is retried until deadline passes, then Error::Timeout is returned.
+    /// Without timeout opening fails right away with Error::FileLocked.
+    ///
+    /// Default: None
+    pub fn lock_timeout(mut self, v: Duration) -> Self {
+        self.lock_timeout = Some(v);
+        self
+    }
+
     /// Initial mmap size of the database
     ///
     /// in bytes. Read transactions won't block write transaction
@@ -155,19 +280,83 @@ impl DBBuilder {
         self
     }
 
+    /// Defines type of in-memory freelist.
+    /// FreelistType::HashMap is faster for databases with many free pages.
+    /// On-disk format doesn't depend on it, so it can be changed between opens.
+    ///
+    /// Default: FreelistType::Array
+    pub fn freelist_type(mut self, v: FreelistType) -> Self {
+        self.freelist_type = v;
+        self
+    }
+
+    /// Registers key comparator under given id,
+    /// buckets created with this id keep their keys in comparator's order.
+    ///
+    /// Comparator must be registered every time database is opened,
+    /// otherwise buckets using it can't be opened.
+    /// Id 0 is reserved for default bytewise order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if id is 0.
+    pub fn comparator(mut self, id: u32, comparator: Comparator) -> Self {
+        assert_ne!(id, 0, "comparator id 0 is reserved for bytewise order");
+        self.comparators.insert(id, comparator);
+        self
+    }
+
+    /// Registers secondary index extractor under given name,
+    /// which returns index keys for a value.
+    ///
+    /// Indexes are created on buckets with Bucket.create_index,
+    /// extractor must be registered every time database is opened,
+    /// otherwise writes to buckets using the index fail.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use nut::DBBuilder;
+    ///
+    /// // users are stored as "name:city"
+    /// let db = DBBuilder::new("./test.db")
+    ///     .index("city", |user| user.split(|b| *b == b':').skip(1).map(|c| c.to_vec()).collect())
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn index<F>(mut self, name: &str, extractor: F) -> Self
+    where
+        F: Fn(&[u8]) -> Vec<Vec<u8>> + Send + Sync + 'static,
+    {
+        self.indexes.insert(name.to_string(), Arc::new(extractor));
+        self
+    }
+
     /// Builds and returns DB instance
     pub fn build(self) -> Result<DB, Error> {
         let options = Options {
+            no_sync: self.no_sync,
+            sync_mode: self.sync_mode,
             no_grow_sync: self.no_grow_sync,
+            no_freelist_sync: self.no_freelist_sync,
+            auto_shrink: self.auto_shrink,
             read_only: self.read_only,
             ignore_flock: self.ignore_flock,
+            lock_timeout: self.lock_timeout,
             initial_mmap_size: self.initial_mmap_size,
             autoremove: self.autoremove,
             checkmode: self.checkmode,
             max_batch_delay: self.max_batch_delay,
             max_batch_size: self.max_batch_size,
             page_size: self.page_size,
+            freelist_type: self.freelist_type,
+            comparators: self.comparators,
+            indexes: self.indexes,
         };
-        DB::open(self.path, options)
+        match (self.storage, self.path) {
+            (Some(storage), path) => DB::open_storage(storage, path, options),
+            (None, Some(path)) => DB::open(path, options),
+            (None, None) => Err("Database path required".into()),
+        }
     }
 }
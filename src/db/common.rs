@@ -9,16 +9,15 @@ use crate::tx::Tx;
 
 use super::WeakDB;
 
+type Handler = Box<dyn Fn(&mut Tx) -> Result<(), Error> + Send>;
+
 pub(super) struct Call {
-    h: Arc<Box<dyn Fn(&mut Tx) -> Result<(), String> + Send>>,
-    err: mpsc::Sender<Result<(), String>>,
+    h: Handler,
+    err: mpsc::Sender<Result<(), Error>>,
 }
 
 impl Call {
-    pub(super) fn new(
-        func: Arc<Box<dyn Fn(&mut Tx) -> Result<(), String> + Send>>,
-        err_ch: mpsc::Sender<Result<(), String>>,
-    ) -> Self {
+    pub(super) fn new(func: Handler, err_ch: mpsc::Sender<Result<(), Error>>) -> Self {
         Self {
             h: func,
             err: err_ch,
@@ -90,22 +89,22 @@ impl Batch {
     /// run performs the transactions in the batch and communicates results
     /// back to DB.Batch.
     fn run(&mut self) {
-        let mut db = self.0.db.upgrade().unwrap();
+        let db = self.0.db.upgrade().unwrap();
         db.0.batch.lock().take();
 
         let mut calls = self.0.calls.try_lock().unwrap();
 
         while calls.len() > 0 {
             let mut last_call_id = 0;
-            if let Err(e) = db.update(Box::new(|tx| {
+            if let Err(e) = db.update(|tx| -> Result<(), Error> {
                 for (index, call) in calls.iter().enumerate() {
                     last_call_id = index;
                     (call.h)(tx)?
                 }
                 Ok(())
-            })) {
+            }) {
                 let failed_call = calls.remove(last_call_id);
-                failed_call.err.send(Err(format!("{}", e))).unwrap();
+                failed_call.err.send(Err(e)).unwrap();
                 continue;
             }
             {
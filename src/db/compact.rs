@@ -0,0 +1,264 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use parking_lot::MappedRwLockWriteGuard;
+
+use super::{DBBuilder, RWTxGuard, DB};
+use crate::bucket::{Bucket, Entry};
+use crate::errors::Error;
+use crate::tx::{temp_path, Tx};
+
+/// Options of database compaction
+///
+/// # Example
+///
+/// ```
+/// use nut::CompactOptions;
+///
+/// let options = CompactOptions::new().tx_max_size(1 << 20).fill_percent(0.9);
+/// ```
+#[derive(Debug, Clone)]
+pub struct CompactOptions {
+    tx_max_size: u64,
+    fill_percent: f64,
+}
+
+impl Default for CompactOptions {
+    fn default() -> Self {
+        Self {
+            tx_max_size: 65536,
+            fill_percent: Bucket::MAX_FILL_PERCENT,
+        }
+    }
+}
+
+impl CompactOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Size of keys and values copied in a single transaction,
+    /// destination is committed whenever it's exceeded.
+    /// Zero copies everything in one transaction.
+    ///
+    /// Default: 65536
+    pub fn tx_max_size(mut self, v: u64) -> Self {
+        self.tx_max_size = v;
+        self
+    }
+
+    /// Fill percent of destination pages, clamped to 0.1..=1.0.
+    ///
+    /// Default: 1.0
+    pub fn fill_percent(mut self, v: f64) -> Self {
+        self.fill_percent = v;
+        self
+    }
+}
+
+/// Sizes of source and destination database files after compaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactStats {
+    /// source file size, or size of data seen by compaction for in-memory database
+    pub src_size: u64,
+    /// destination file size
+    pub dst_size: u64,
+}
+
+/// Copies all buckets of the database into a new file at dst_path, packing pages densely.
+///
+/// Source is read within a single read transaction, so writers can proceed meanwhile,
+/// changes committed after compaction started are not copied.
+/// Nesting, sequences and comparators of buckets are preserved,
+/// secondary indexes are copied as is.
+/// Comparators of source database are registered in destination one,
+/// so every bucket's comparator must be registered in source.
+///
+/// Returns an error if dst_path already exists. Database is written to a temporary
+/// file next to dst_path, which is renamed to it once complete, or removed on failure.
+///
+/// # Example
+///
+/// ```no_run
+/// use nut::{compact, CompactOptions, DBBuilder};
+///
+/// let db = DBBuilder::new("./test.db").build().unwrap();
+/// let stats = compact(&db, "./test.compact.db", CompactOptions::new()).unwrap();
+/// println!("{} -> {} bytes", stats.src_size, stats.dst_size);
+/// ```
+pub fn compact<P: AsRef<Path>>(
+    src: &DB,
+    dst_path: P,
+    options: CompactOptions,
+) -> Result<CompactStats, Error> {
+    let dst_path = dst_path.as_ref();
+    if dst_path.exists() {
+        return Err(Error::Io(Arc::new(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "destination already exists",
+        ))));
+    }
+
+    let tmp_path = temp_path(dst_path)?;
+    if tmp_path.exists() {
+        // left by interrupted compaction
+        fs::remove_file(&tmp_path)?;
+    }
+    let result = copy_into(src, &tmp_path, &options).and_then(|src_size| {
+        fs::rename(&tmp_path, dst_path)?;
+        Ok(CompactStats {
+            src_size,
+            dst_size: fs::metadata(dst_path)?.len(),
+        })
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// Copies all buckets of src into a new database at path,
+/// returns size of source file.
+fn copy_into(src: &DB, path: &Path, options: &CompactOptions) -> Result<u64, Error> {
+    let mut builder = DBBuilder::new(path)
+        .page_size(src.page_size())
+        .no_sync(true);
+    for (id, comparator) in &src.0.comparators {
+        builder = builder.comparator(*id, *comparator);
+    }
+    let mut dst = builder.build()?;
+
+    let tx = src.begin_tx()?;
+    {
+        let mut compactor = Compactor {
+            db: &dst,
+            tx: None,
+            size: 0,
+            options,
+        };
+        for name in tx.buckets() {
+            let bucket = tx.bucket(&name)?;
+            compactor.copy(&bucket, &mut vec![name])?;
+        }
+        compactor.commit()?;
+    }
+    dst.sync()?;
+    drop(dst);
+
+    Ok(match src.path() {
+        Some(path) => fs::metadata(path)?.len(),
+        None => tx.size() as u64,
+    })
+}
+
+/// Writes copied buckets into destination database,
+/// committing once copied size exceeds tx_max_size.
+struct Compactor<'a> {
+    db: &'a DB,
+    tx: Option<RWTxGuard<'a>>,
+    size: u64,
+    options: &'a CompactOptions,
+}
+
+impl<'a> Compactor<'a> {
+    /// Copies bucket at path with all its keys and nested buckets
+    fn copy(&mut self, src: &Bucket, path: &mut Vec<Vec<u8>>) -> Result<(), Error> {
+        self.create(src, path)?;
+
+        let mut batch = vec![];
+        for (key, entry) in src.iter()?.with_indexes() {
+            match entry {
+                Entry::Value(value) => {
+                    self.size += (key.len() + value.len()) as u64;
+                    batch.push((key, value));
+                    if self.options.tx_max_size != 0 && self.size >= self.options.tx_max_size {
+                        self.load(path, &mut batch)?;
+                        self.commit()?;
+                    }
+                }
+                Entry::Bucket => {
+                    self.load(path, &mut batch)?;
+                    let child = src.try_bucket(key)?.ok_or("Can't get bucket")?;
+                    path.push(key.to_vec());
+                    self.copy(child, path)?;
+                    path.pop();
+                }
+            }
+        }
+        self.load(path, &mut batch)
+    }
+
+    /// Creates empty bucket at path with comparator and sequence of src
+    fn create(&mut self, src: &Bucket, path: &[Vec<u8>]) -> Result<(), Error> {
+        let (name, parent) = path.split_last().ok_or("Empty bucket path")?;
+        self.size += name.len() as u64;
+        let fill_percent = self.options.fill_percent;
+        let tx = self.tx()?;
+        if parent.is_empty() {
+            tx.create_bucket_with_comparator(name, src.comparator_id())
+                .map(|_| ())?;
+        } else {
+            Self::bucket(tx, parent, fill_percent)?.create_nested(
+                name,
+                src.comparator_id(),
+                src.holds_indexes,
+            )?;
+        }
+        Self::bucket(tx, path, fill_percent)?.set_sequence(src.sequence())
+    }
+
+    /// Appends batch of ascending keys to bucket at path
+    fn load(&mut self, path: &[Vec<u8>], batch: &mut Vec<(&[u8], &[u8])>) -> Result<(), Error> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let fill_percent = self.options.fill_percent;
+        let tx = self.tx()?;
+        Self::bucket(tx, path, fill_percent)?.load(batch.drain(..), fill_percent, false)?;
+        Ok(())
+    }
+
+    /// Returns destination bucket at path with fill percent set on every level
+    fn bucket<'t>(
+        tx: &'t mut Tx,
+        path: &[Vec<u8>],
+        fill_percent: f64,
+    ) -> Result<MappedRwLockWriteGuard<'t, Bucket>, Error> {
+        let (name, path) = path.split_first().ok_or("Empty bucket path")?;
+        let mut bucket = tx.bucket_mut(name)?;
+        bucket.set_fill_percent(fill_percent);
+        for key in path {
+            let mut err = None;
+            bucket = MappedRwLockWriteGuard::try_map(bucket, |b| {
+                // nested buckets include the one holding indexes
+                match b.try_bucket_mut(key) {
+                    Ok(child) => child,
+                    Err(e) => {
+                        err = Some(e);
+                        None
+                    }
+                }
+            })
+            .map_err(|_| err.unwrap_or_else(|| "Can't get bucket".into()))?;
+            bucket.set_fill_percent(fill_percent);
+        }
+        Ok(bucket)
+    }
+
+    fn tx(&mut self) -> Result<&mut Tx, Error> {
+        if self.tx.is_none() {
+            self.tx = Some(self.db.begin_rw_tx()?);
+        }
+        Ok(self.tx.as_mut().unwrap())
+    }
+
+    fn commit(&mut self) -> Result<(), Error> {
+        if let Some(mut tx) = self.tx.take() {
+            tx.commit()?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+}
@@ -1,21 +1,24 @@
-use fs2::FileExt;
-use lock_api::{RawMutex, RawRwLock};
+use lock_api::{RawMutex, RawMutexTimed, RawRwLock};
 use parking_lot::{MappedRwLockReadGuard, Mutex, RwLock, RwLockReadGuard};
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
-use std::fs::OpenOptions;
-use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::io::{Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{mpsc, Arc, Weak};
-use std::time::Duration;
+use std::thread;
+use std::time::{Duration, Instant};
 use std::u64;
 
-use crate::bucket::IBucket;
-use crate::consts::{Flags, MAGIC, MAX_MAP_SIZE, MAX_MMAP_STEP, PGID, VERSION};
+use crate::bucket::{Bucket, Comparator, IBucket, IndexFn};
+use crate::consts::{
+    Flags, FLOCK_RETRY_TIMEOUT, IGNORE_NOSYNC, MAGIC, MAX_MAP_SIZE, MAX_MMAP_STEP, PGID, VERSION,
+};
 use crate::errors::Error;
 use crate::freelist::FreeList;
 use crate::meta::Meta;
 use crate::page::{OwnedPage, Page};
+use crate::storage::{Mapping, MmapStorage, Storage};
 use crate::tx::{Tx, TxBuilder};
 
 use super::builder::Options;
@@ -50,28 +53,54 @@ bitflags! {
     }
 }
 
+/// Defines how commits are flushed to stable storage
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncMode {
+    /// fdatasync after dirty pages are written and again after meta page is written.
+    ///
+    /// Committed transaction survives power loss.
+    Full,
+    /// fdatasync only after meta page is written.
+    ///
+    /// Halves number of syncs per commit, but disk may reorder
+    /// page and meta writes, so last transaction may be lost or torn on power loss.
+    MetaOnly,
+    /// no fdatasync at all, data is only flushed to OS.
+    ///
+    /// Survives process crash, but not power loss.
+    None,
+}
+
 pub(crate) struct DBInner {
     pub(crate) check_mode: CheckMode,
     pub(crate) no_sync: bool,
+    pub(crate) sync_mode: SyncMode,
     pub(super) no_grow_sync: bool,
-    pub(super) max_batch_size: usize,
+    pub(crate) no_freelist_sync: bool,
+    pub(crate) auto_shrink: bool,
+    pub(crate) max_batch_size: usize,
     pub(super) max_batch_delay: Duration,
     pub(super) autoremove: bool,
     pub(super) alloc_size: u64,
     pub(super) path: Option<PathBuf>,
-    pub(crate) file: RwLock<BufWriter<File>>,
+    pub(crate) storage: RwLock<Box<dyn Storage>>,
     pub(super) mmap_size: Mutex<usize>,
-    pub(super) mmap: RwLock<memmap::Mmap>,
+    pub(super) mmap: RwLock<Box<dyn Mapping>>,
     pub(super) file_size: RwLock<u64>,
     page_size: usize,
     opened: AtomicBool,
     pub(crate) rw_lock: Mutex<()>,
+    /// makes reading meta and registering read transaction atomic
+    /// for writer releasing pages of closed transactions
+    pub(crate) meta_lock: Mutex<()>,
     pub(crate) rw_tx: RwLock<Option<Tx>>,
     pub(crate) txs: RwLock<Vec<Tx>>,
     pub(crate) freelist: RwLock<FreeList>,
     pub(crate) stats: RwLock<Stats>,
     pub(crate) batch: Mutex<Option<Batch>>,
     pub(crate) page_pool: Mutex<Vec<OwnedPage>>,
+    pub(super) comparators: HashMap<u32, Comparator>,
+    indexes: HashMap<String, IndexFn>,
     read_only: bool,
 }
 
@@ -80,40 +109,33 @@ pub(crate) struct DBInner {
 pub struct DB(pub(crate) Arc<DBInner>);
 
 impl<'a> DB {
-    pub(super) fn open_file<P: Into<PathBuf>>(
-        mut file: File,
+    pub(super) fn open_storage<P: Into<PathBuf>>(
+        mut storage: Box<dyn Storage>,
         path: Option<P>,
         options: Options,
     ) -> Result<Self, Error> {
         let path = path.map(|v| v.into());
-        let needs_initialization = (!std::path::Path::new(path.as_ref().unwrap()).exists())
-            || file.metadata().map_err(|_| "Can't read metadata")?.len() == 0;
+        let needs_initialization = storage.size()? == 0;
 
-        if !options.ignore_flock{
-            if !options.read_only {
-                file.try_lock_exclusive().map_err(|_e| "Cannot lock db file")?;
-            } else {
-                file.try_lock_shared().map_err(|_e| "Cannot lock db file")?;
-            };
+        if !options.ignore_flock {
+            Self::lock(storage.as_ref(), !options.read_only, options.lock_timeout)?;
         }
 
         let page_size = if needs_initialization {
             options.page_size
         } else {
             let mut buf = vec![0u8; 1000];
-            file.read_exact(&mut buf)?;
+            storage.read_at(0, &mut buf)?;
             let page = Page::from_buf(&buf);
             if page.flags != Flags::META {
-                return Err("Database format unknown".into());
+                return Err(Error::Invalid);
             }
             page.meta().page_size as usize
         };
 
         let minimal_file_size = page_size as u64 * 4;
         if options.read_only {
-            let size = file
-                .allocated_size()
-                .map_err(|_e| "Cannot get file allocated size")?;
+            let size = storage.size()?;
             if size < minimal_file_size {
                 return Err(format!(
                     "Minimal file size {} is less than required: {}",
@@ -122,40 +144,39 @@ impl<'a> DB {
                 .into());
             }
         } else {
-            file.allocate(minimal_file_size)
-                .map_err(|_e| "Cannot allocate 4 required pages")?;
+            storage.grow(minimal_file_size)?;
         }
 
-        let mmap = unsafe {
-            memmap::MmapOptions::new()
-                .offset(0)
-                .len(page_size)
-                .map(&file)
-                .map_err(|e| format!("mmap failed: {}", e))?
-        };
+        let mmap = storage.map(page_size)?;
 
         let mut db = Self(Arc::new(DBInner {
             check_mode: options.checkmode,
-            no_sync: false,
+            no_sync: options.no_sync,
+            sync_mode: options.sync_mode,
             no_grow_sync: options.no_grow_sync,
+            no_freelist_sync: options.no_freelist_sync,
+            auto_shrink: options.auto_shrink,
             max_batch_size: options.max_batch_size,
             max_batch_delay: options.max_batch_delay,
             autoremove: options.autoremove,
             alloc_size: 0,
             path,
-            file: RwLock::new(BufWriter::new(file)),
+            storage: RwLock::new(storage),
             mmap_size: Mutex::new(0),
             mmap: RwLock::new(mmap),
             file_size: RwLock::new(0),
             page_size,
             opened: AtomicBool::new(true),
             rw_lock: Mutex::new(()),
+            meta_lock: Mutex::new(()),
             rw_tx: RwLock::new(None),
             txs: RwLock::new(Vec::new()),
-            freelist: RwLock::new(FreeList::default()),
+            freelist: RwLock::new(FreeList::new(options.freelist_type)),
             stats: RwLock::from(Stats::default()),
             page_pool: Mutex::new(Vec::new()),
             batch: Mutex::new(None),
+            comparators: options.comparators,
+            indexes: options.indexes,
             read_only: options.read_only,
         }));
 
@@ -166,47 +187,66 @@ impl<'a> DB {
         db.mmap(options.initial_mmap_size as u64)?;
 
         {
-            let freelist_id = db.meta()?.freelist;
-            db.mmap((freelist_id + 1) * db.0.page_size as u64)?;
-            let freelist_page = db.page(freelist_id);
-            db.0.freelist.try_write().unwrap().read(&freelist_page);
+            // Map every page up to the high water mark, freelist page may be anywhere below it.
+            let meta = db.meta()?;
+            db.mmap(meta.pgid * db.0.page_size as u64)?;
+            if meta.has_freelist() {
+                let freelist_page = db.page(meta.freelist);
+                db.0.freelist.try_write().unwrap().read(&freelist_page);
+            } else {
+                let ids = db.free_pages(&meta);
+                db.0.freelist.try_write().unwrap().init(&ids);
+            }
+
+            // Persist rebuilt freelist if database isn't opened with no_freelist_sync anymore.
+            if !meta.has_freelist() && !db.0.no_freelist_sync && !db.0.read_only {
+                db.begin_rw_tx()?.commit()?;
+            }
         }
 
         Ok(db)
     }
 
+    /// Obtains file lock, retrying until timeout if it is held by another process
+    fn lock(
+        storage: &dyn Storage,
+        exclusive: bool,
+        timeout: Option<Duration>,
+    ) -> Result<(), Error> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        loop {
+            match storage.try_lock(exclusive) {
+                Err(Error::FileLocked(e)) => match deadline {
+                    None => return Err(Error::FileLocked(e)),
+                    Some(deadline) if Instant::now() >= deadline => return Err(Error::Timeout),
+                    Some(_) => thread::sleep(FLOCK_RETRY_TIMEOUT),
+                },
+                result => return result,
+            }
+        }
+    }
+
     pub(super) fn open<P: AsRef<Path>>(path: P, options: Options) -> Result<Self, Error> {
         let path = path.as_ref().to_owned();
-        let file = {
-            let mut open_opts = OpenOptions::new();
-            open_opts.read(true);
-            if !options.read_only {
-                open_opts.write(true).create(true);
-            };
-            open_opts.open(&path).map_err(|e| format!("{}", e))?
-        };
+        let storage = MmapStorage::open(&path, options.read_only)?;
 
-        DB::open_file(file, Some(path), options)
+        DB::open_storage(Box::new(storage), Some(path), options)
     }
 
     /// Returns meta info for given path
-    pub fn get_meta(file: &mut File) -> Result<Meta, String> {
+    pub fn get_meta(file: &mut File) -> Result<Meta, Error> {
         let mut buf = [0u8; 12];
-        file.seek(SeekFrom::Start(Page::header_size() as u64))
-            .map_err(|_| "Can't seek file".to_string())?;
-        file.read_exact(&mut buf)
-            .map_err(|_| "Can't read file to buffer".to_string())?;
+        file.seek(SeekFrom::Start(Page::header_size() as u64))?;
+        file.read_exact(&mut buf)?;
         let magic: u32 = unsafe { *(&buf[0] as *const u8 as *const u32) };
         if magic != MAGIC {
-            return Err("Incorrect header".to_string());
+            return Err(Error::Invalid);
         };
         let page_size: u32 = unsafe { *(&buf[8] as *const u8 as *const u32) };
         let page_size = page_size as usize;
-        file.seek(SeekFrom::Start(0))
-            .map_err(|_| "Can't seek file".to_string())?;
+        file.seek(SeekFrom::Start(0))?;
         let mut metabuf = vec![0u8; page_size * 2];
-        file.read_exact(&mut metabuf)
-            .map_err(|_| "Can't read file to buffer".to_string())?;
+        file.read_exact(&mut metabuf)?;
 
         let metap0 = unsafe { &*(&metabuf[0] as *const u8 as *const Page) };
         let metap1 = unsafe { &*(&metabuf[page_size] as *const u8 as *const Page) };
@@ -224,7 +264,10 @@ impl<'a> DB {
             return Ok(meta1.clone());
         }
 
-        Err("Invalid meta".to_string())
+        Err(Error::Corruption {
+            pgid: 0,
+            reason: "invalid meta pages".to_string(),
+        })
     }
 
     #[inline(always)]
@@ -233,6 +276,17 @@ impl<'a> DB {
         self.0.page_size
     }
 
+    /// Returns key comparator registered under given id
+    pub(crate) fn comparator(&self, id: u32) -> Option<Comparator> {
+        self.0.comparators.get(&id).copied()
+    }
+
+    /// Returns index extractor registered under given name
+    pub(crate) fn index(&self, name: &[u8]) -> Option<IndexFn> {
+        let name = std::str::from_utf8(name).ok()?;
+        self.0.indexes.get(name).cloned()
+    }
+
     pub(crate) fn remove_tx(&mut self, tx: &Tx) -> Result<Tx, Error> {
         if tx.writable() {
             let (freelist_free_n, freelist_pending_n, freelist_alloc) = {
@@ -256,6 +310,7 @@ impl<'a> DB {
             unsafe {
                 self.0.rw_lock.raw().unlock();
             }
+            self.release_pages();
 
             let mut stats = self.0.stats.write();
             stats.free_page_n = freelist_free_n;
@@ -276,6 +331,7 @@ impl<'a> DB {
             unsafe {
                 self.0.mmap.raw().unlock_shared();
             }
+            self.release_pages();
 
             let mut stats = self.0.stats.write();
             stats.open_tx_n = txs.len();
@@ -285,6 +341,15 @@ impl<'a> DB {
         }
     }
 
+    /// Lets mapping drop pages it keeps in memory if no transaction is open.
+    fn release_pages(&self) {
+        if let Some(_rw) = self.0.rw_lock.try_lock() {
+            if let Some(mmap) = self.0.mmap.try_write() {
+                mmap.release();
+            }
+        }
+    }
+
     pub(super) fn init(&mut self) -> Result<(), Error> {
         let mut buf = vec![0u8; self.0.page_size * 4];
         for i in 0..=1 {
@@ -314,7 +379,7 @@ impl<'a> DB {
         p.id = 3;
         p.flags = Flags::LEAVES;
 
-        self.write_at(0, std::io::Cursor::new(&mut buf))?;
+        self.write_at(0, &buf)?;
         self.sync()?;
 
         Ok(())
@@ -328,7 +393,7 @@ impl<'a> DB {
         self.cleanup()?;
         if let Some(path) = &self.0.path {
             if path.exists() {
-                std::fs::remove_file(path).map_err(|_| "Can't remove file")?;
+                std::fs::remove_file(path)?;
             }
         }
 
@@ -354,22 +419,22 @@ impl<'a> DB {
         self.0.opened.store(false, Ordering::Release);
 
         self.0
-            .file
+            .storage
             .try_read()
             .ok_or("Can't acquire file lock")?
-            .get_ref()
-            .unlock()
-            .map_err(|_| "Can't unlock db file")?;
+            .unlock()?;
         if self.0.autoremove {
             if let Some(path) = &self.0.path {
                 if path.exists() {
-                    std::fs::remove_file(path).map_err(|_| "Can't remove file")?;
+                    std::fs::remove_file(path)?;
                 }
             }
         }
         Ok(())
     }
 
+    /// Returns path to database file,
+    /// or None if database lives in memory.
     #[inline(always)]
     pub fn path(&self) -> Option<PathBuf> {
         self.0.path.clone()
@@ -403,6 +468,7 @@ impl<'a> DB {
             self.0.mmap.raw().lock_shared();
         }
 
+        let meta_lock = self.0.meta_lock.lock();
         let tx = TxBuilder::new()
             .db(WeakDB::from(self))
             .writable(false)
@@ -413,6 +479,7 @@ impl<'a> DB {
         txs.push(tx.clone());
         let txs_len = txs.len();
         drop(txs);
+        drop(meta_lock);
 
         {
             let mut stats = self.0.stats.write();
@@ -435,18 +502,54 @@ impl<'a> DB {
     /// If a long running read transaction (for example, a snapshot transaction) is
     /// needed, you might want to set DBBuilder.initial_mmap_size to a large enough value
     /// to avoid potential blocking of write transaction.
-    pub fn begin_rw_tx(&'a mut self) -> Result<RWTxGuard<'a>, Error> {
+    pub fn begin_rw_tx(&'a self) -> Result<RWTxGuard<'a>, Error> {
+        self.check_writable()?;
+
+        unsafe {
+            self.0.rw_lock.raw().lock();
+        };
+        Ok(self.start_rw_tx())
+    }
+
+    /// Starts a new writable transaction if no other write transaction is active.
+    ///
+    /// Returns Error::WriteInProgress instead of waiting for the running one.
+    pub fn try_begin_rw_tx(&'a self) -> Result<RWTxGuard<'a>, Error> {
+        self.check_writable()?;
+
+        if !unsafe { self.0.rw_lock.raw().try_lock() } {
+            return Err(Error::WriteInProgress);
+        }
+        Ok(self.start_rw_tx())
+    }
+
+    /// Starts a new writable transaction,
+    /// waiting at most given time for the running one to finish.
+    ///
+    /// Returns Error::Timeout if write lock wasn't obtained in time.
+    pub fn begin_rw_tx_timeout(&'a self, timeout: Duration) -> Result<RWTxGuard<'a>, Error> {
+        self.check_writable()?;
+
+        if !unsafe { self.0.rw_lock.raw().try_lock_for(timeout) } {
+            return Err(Error::Timeout);
+        }
+        Ok(self.start_rw_tx())
+    }
+
+    fn check_writable(&self) -> Result<(), Error> {
         if self.read_only() {
             return Err(Error::DatabaseReadonly);
         };
         if !self.opened() {
             return Err(Error::DatabaseClosed);
         };
+        Ok(())
+    }
 
-        unsafe {
-            self.0.rw_lock.raw().lock();
-        };
+    /// Creates writable transaction, write lock must be already held
+    fn start_rw_tx(&'a self) -> RWTxGuard<'a> {
         let mut rw_tx = self.0.rw_tx.write();
+        let meta_lock = self.0.meta_lock.lock();
 
         let txs = self.0.txs.read();
         let minid = txs
@@ -462,6 +565,7 @@ impl<'a> DB {
             .check(self.0.check_mode.contains(CheckMode::WRITE))
             .build();
         *rw_tx = Some(tx.clone());
+        drop(meta_lock);
         drop(rw_tx);
 
         // Free any pages associated with closed read-only transactions.
@@ -469,17 +573,37 @@ impl<'a> DB {
             self.0.freelist.try_write().unwrap().release(minid - 1);
         }
 
-        Ok(RWTxGuard {
+        RWTxGuard {
             tx,
             db: std::marker::PhantomData,
-        })
+        }
     }
 
     /// shorthand for db.begin_rw_tx with additional guarantee for panic safery
-    pub fn update<'b>(
-        &mut self,
-        mut handler: Box<dyn FnMut(&mut Tx) -> Result<(), String> + 'b>,
-    ) -> Result<(), Error> {
+    ///
+    /// Transaction is committed if handler returns Ok, its value is then returned.
+    /// On error or panic transaction is rolled back.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use nut::{DBBuilder, Error};
+    ///
+    /// let db = DBBuilder::new("./test.db").build().unwrap();
+    /// let id = db
+    ///     .update(|tx| -> Result<u64, Error> {
+    ///         let mut bucket = tx.create_bucket_if_not_exists(b"users")?;
+    ///         let id = bucket.next_sequence()?;
+    ///         bucket.put(&id.to_be_bytes(), b"john".to_vec())?;
+    ///         Ok(id)
+    ///     })
+    ///     .unwrap();
+    /// ```
+    pub fn update<T, E, F>(&self, handler: F) -> Result<T, E>
+    where
+        F: FnOnce(&mut Tx) -> Result<T, E>,
+        E: From<Error>,
+    {
         use std::panic::{catch_unwind, AssertUnwindSafe};
 
         let mut tx = scopeguard::guard(self.begin_rw_tx()?, |tx| {
@@ -498,23 +622,30 @@ impl<'a> DB {
 
         if result.is_err() {
             tx.__rollback()?;
-            return Err("Panic while update".into());
+            return Err(Error::from("Panic while update").into());
         }
 
         let result = result.unwrap();
-        if let Err(e) = result {
-            tx.rollback()?;
-            return Err(e.into());
-        };
-
-        tx.commit()
+        match result {
+            Err(e) => {
+                tx.rollback()?;
+                Err(e)
+            }
+            Ok(value) => {
+                tx.commit()?;
+                Ok(value)
+            }
+        }
     }
 
     /// shorthand for db.begin_tx with additional guarantee for panic safery
-    pub fn view<'b>(
-        &self,
-        handler: Box<dyn Fn(&Tx) -> Result<(), String> + 'b>,
-    ) -> Result<(), Error> {
+    ///
+    /// Returns value returned by handler.
+    pub fn view<T, E, F>(&self, handler: F) -> Result<T, E>
+    where
+        F: FnOnce(&Tx) -> Result<T, E>,
+        E: From<Error>,
+    {
         use std::panic::{catch_unwind, AssertUnwindSafe};
 
         let tx = scopeguard::guard(self.begin_tx()?, |tx| {
@@ -533,16 +664,12 @@ impl<'a> DB {
 
         if result.is_err() {
             tx.__rollback()?;
-            return Err("Panic while update".into());
+            return Err(Error::from("Panic while update").into());
         }
 
         let result = result.unwrap();
-        if let Err(e) = result {
-            tx.rollback()?;
-            return Err(e.into());
-        };
-
-        tx.rollback()
+        tx.rollback()?;
+        result
     }
 
     /// Calls fn as part of a batch. It behaves similar to Update,
@@ -558,19 +685,55 @@ impl<'a> DB {
     /// take permanent effect only after a successful return is seen in
     /// caller.
     ///
+    /// Value returned by the last call of the function is returned
+    /// once batch transaction is committed.
+    ///
     /// The maximum batch size and delay can be adjusted with DBBuilder.batch_size
     /// and DBBuilder.batch_delay, respectively.
     ///
     /// Batch is only useful when there are multiple threads calling it.
     /// While calling it multiple times from single thread just blocks
     /// thread for each single batch call
-    pub fn batch(
-        &mut self,
-        handler: Box<dyn Fn(&mut Tx) -> Result<(), String> + Send>,
-    ) -> Result<(), Error> {
+    ///
+    /// Handler is run on the batch thread, so it must be Send:
+    ///
+    /// ```compile_fail
+    /// use nut::{DBBuilder, Error};
+    /// use std::rc::Rc;
+    ///
+    /// let db = DBBuilder::new("./test.db").build().unwrap();
+    /// let name = Rc::new(b"bucket".to_vec());
+    /// db.batch(move |tx| -> Result<(), Error> {
+    ///     tx.create_bucket_if_not_exists(&name)?;
+    ///     Ok(())
+    /// })
+    /// .unwrap();
+    /// ```
+    pub fn batch<T, E, F>(&self, handler: F) -> Result<T, E>
+    where
+        F: Fn(&mut Tx) -> Result<T, E> + Send + 'static,
+        T: Send + 'static,
+        E: From<Error> + Send + 'static,
+    {
         let weak_db = WeakDB::from(self);
-        let handler = Arc::new(handler);
-        let handler_clone = handler.clone();
+        // Handler is never called concurrently: caller waits for
+        // the batch to finish before falling back to update.
+        let handler = Arc::new(Mutex::new(handler));
+        let result = Arc::new(Mutex::new(None));
+
+        let call = {
+            let handler = handler.clone();
+            let result = result.clone();
+            Box::new(move |tx: &mut Tx| -> Result<(), Error> {
+                let call_result = (handler.lock())(tx);
+                let failed = call_result.is_err();
+                *result.lock() = Some(call_result);
+                if failed {
+                    return Err("batch call failed".into());
+                }
+                Ok(())
+            })
+        };
 
         let err_receiver = {
             let mut batch = self.0.batch.lock();
@@ -584,30 +747,57 @@ impl<'a> DB {
             let batch = batch.as_mut().unwrap();
 
             let (err_sender, err_receiver) = mpsc::channel();
-            batch.push(Call::new(handler_clone, err_sender))?;
+            batch.push(Call::new(call, err_sender))?;
             err_receiver
         };
 
-        if err_receiver.recv().is_err() {
-            return self.update(Box::new(|tx| handler(tx)));
+        let batch_result = match err_receiver.recv() {
+            Err(_) => return self.update(|tx| (handler.lock())(tx)),
+            Ok(v) => v,
+        };
+        let call_result = result.lock().take();
+        match (batch_result, call_result) {
+            (Ok(()), Some(Ok(value))) => Ok(value),
+            (Err(_), Some(Err(e))) => Err(e),
+            (Err(e), _) => Err(e.into()),
+            (Ok(()), _) => Err(Error::from("batch call result is missing").into()),
         }
+    }
 
-        Ok(())
+    /// Releases free pages at the end of the file and truncates it.
+    ///
+    /// Pages freed while open read transactions may still use them stay
+    /// allocated, and the file isn't truncated until all read transactions
+    /// are closed, the next shrink or commit with auto_shrink does it then.
+    ///
+    /// Returns number of bytes the file shrunk by.
+    pub fn shrink(&self) -> Result<u64, Error> {
+        let size = self.0.storage.read().size()?;
+        // A commit may leave the old freelist page at the end of the file,
+        // it's released and trimmed by the next one.
+        let mut pgid = self.meta()?.pgid;
+        loop {
+            let mut tx = self.begin_rw_tx()?;
+            tx.0.shrink.store(true, Ordering::Release);
+            tx.commit()?;
+            let trimmed = self.meta()?.pgid;
+            if trimmed >= pgid {
+                break;
+            }
+            pgid = trimmed;
+        }
+        Ok(size.saturating_sub(self.0.storage.read().size()?))
     }
 
-    pub(crate) fn write_at<T: Read>(&mut self, pos: u64, mut buf: T) -> Result<(), Error> {
-        let mut file = self.0.file.write();
-        file.seek(SeekFrom::Start(pos))
-            .map_err(|_e| "can't seek to position")?;
-        std::io::copy(&mut buf, &mut *file).map_err(|_e| "can't write buffer")?;
-        Ok(())
+    pub(crate) fn write_at(&mut self, pos: u64, buf: &[u8]) -> Result<(), Error> {
+        self.0.storage.write().write_at(pos, buf)
     }
 
     pub(super) fn mmap(&mut self, mut min_size: u64) -> Result<(), Error> {
-        let file = self
+        let mut storage = self
             .0
-            .file
-            .try_read_for(Duration::from_secs(60))
+            .storage
+            .try_write_for(Duration::from_secs(60))
             .ok_or("can't acquire file lock")?;
         let mut mmap = self
             .0
@@ -621,40 +811,25 @@ impl<'a> DB {
 
         let mut size = self.mmap_size(min_size)?;
 
-        if mmap.len() >= size as usize {
+        if mmap.size() >= size as usize {
             return Ok(());
         }
 
         if self.0.read_only {
-            let file_len = file
-                .get_ref()
-                .metadata()
-                .map_err(|_| "can't get file metadata")?
-                .len();
-            size = u64::min(size, file_len);
+            size = u64::min(size, storage.size()?);
         }
 
         let mut mmap_size = self.0.mmap_size.lock();
 
         if !self.read_only() {
-            file.get_ref()
-                .allocate(size)
-                .map_err(|e| format!("Can't allocate space: {}", e))?;
-        }
-
-        // TODO: madvise
-        let mut mmap_opts = memmap::MmapOptions::new();
-        let nmmap = unsafe {
-            mmap_opts
-                .offset(0)
-                .len(size as usize)
-                .map(&*file.get_ref())
-                .map_err(|e| format!("mmap failed: {}", e))?
-        };
-        *mmap_size = nmmap.len();
+            storage.grow(size)?;
+        }
+
+        let nmmap = storage.map(size as usize)?;
+        *mmap_size = nmmap.size();
         *mmap = nmmap;
 
-        drop(file);
+        drop(storage);
         drop(mmap);
         drop(mmap_size);
 
@@ -664,8 +839,11 @@ impl<'a> DB {
         // Validate the meta pages. We only return an error if both meta pages fail
         // validation, since meta0 failing validation means that it wasn't saved
         // properly -- but we can recover using meta1. And vice-versa.
-        if check0.is_err() && check1.is_err() {
-            return Err(format!("mmap fail: {}", check0.unwrap_err()).into());
+        if let (Err(e), Err(_)) = (check0, check1) {
+            return Err(Error::Corruption {
+                pgid: 0,
+                reason: format!("invalid meta pages: {}", e),
+            });
         }
 
         Ok(())
@@ -705,12 +883,25 @@ impl<'a> DB {
         Ok(size)
     }
 
+    /// Flushes buffered writes to OS without waiting for them to reach the disk.
+    pub(crate) fn flush(&mut self) -> Result<(), Error> {
+        self.0.storage.write().flush()
+    }
+
+    /// Flushes buffered writes and waits until they reach stable storage.
     pub(crate) fn sync(&mut self) -> Result<(), Error> {
-        self.0
-            .file
-            .write()
-            .flush()
-            .map_err(|_e| "can't sync data".into())
+        self.0.storage.write().sync()
+    }
+
+    /// Returns sync mode which is used on commit.
+    ///
+    /// If database opened with no_sync then it is always SyncMode::None,
+    /// unless platform requires syncing anyway.
+    pub fn sync_mode(&self) -> SyncMode {
+        if self.0.no_sync && !IGNORE_NOSYNC {
+            return SyncMode::None;
+        }
+        self.0.sync_mode
     }
 
     pub fn stats(&self) -> Stats {
@@ -718,19 +909,73 @@ impl<'a> DB {
     }
 
     pub fn info(&self) -> Info {
-        let ptr = self.0.mmap.try_read().unwrap().as_ref()[0] as *const u8;
+        let ptr = self
+            .0
+            .mmap
+            .try_read()
+            .unwrap()
+            .read(0, self.0.page_size)
+            .as_ptr();
         Info {
             data: ptr,
             page_size: self.0.page_size as i64,
         }
     }
 
+    /// Returns ids of pages which aren't reachable from the meta, which are free
+    /// unless they are still used by open read transactions.
+    pub(crate) fn free_pages(&self, meta: &Meta) -> Vec<PGID> {
+        let mut reachable = HashSet::new();
+        reachable.insert(0);
+        reachable.insert(1);
+        let mut roots = vec![meta.root.root];
+        if meta.has_freelist() {
+            roots.push(meta.freelist);
+        }
+
+        while let Some(pgid) = roots.pop() {
+            let p = self.page(pgid);
+            for i in 0..=u64::from(p.overflow) {
+                reachable.insert(p.id + i);
+            }
+            match p.flags {
+                Flags::BRANCHES => {
+                    roots.extend(p.branch_page_elements().iter().map(|e| e.pgid));
+                }
+                Flags::LEAVES => {
+                    for e in p.leaf_page_elements() {
+                        if e.flags & Bucket::FLAG != 0 {
+                            let bucket = unsafe {
+                                std::ptr::read_unaligned(e.value().as_ptr() as *const IBucket)
+                            };
+                            // Inline buckets have no pages.
+                            if bucket.root != 0 {
+                                roots.push(bucket.root);
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        (2..meta.pgid)
+            .filter(|id| !reachable.contains(id))
+            .collect()
+    }
+
     /// Retrieves page from mmap
     pub(crate) fn page(&self, id: PGID) -> MappedRwLockReadGuard<Page> {
         let page_size = self.0.page_size;
         let pos = id as usize * page_size as usize;
         let mmap = self.0.mmap.read_recursive();
-        RwLockReadGuard::map(mmap, |mmap| Page::from_buf(&mmap.as_ref()[pos..]))
+        RwLockReadGuard::map(mmap, |mmap| {
+            let buf = mmap.read(pos, page_size);
+            match Page::from_buf(buf).overflow as usize {
+                0 => Page::from_buf(buf),
+                overflow => Page::from_buf(mmap.read(pos, (overflow + 1) * page_size)),
+            }
+        })
     }
 
     fn page_in_buffer<'b>(&'a self, buf: &'b mut [u8], id: PGID) -> &'b mut Page {
@@ -756,7 +1001,10 @@ impl<'a> DB {
             return Ok(meta1.clone());
         };
 
-        Err("nut::DB.meta(): invalid meta pages".into())
+        Err(Error::Corruption {
+            pgid: 0,
+            reason: "invalid meta pages".to_string(),
+        })
     }
 
     pub(crate) fn allocate(&mut self, count: u64, tx: &mut Tx) -> Result<OwnedPage, Error> {
@@ -802,16 +1050,16 @@ impl<'a> DB {
             return Err(Error::DatabaseReadonly);
         };
 
-        let file = self.0.file.try_write().unwrap();
+        let mut storage = self.0.storage.try_write().unwrap();
 
-        if file.get_ref().metadata().unwrap().len() >= size {
+        if storage.size()? >= size {
             return Ok(());
         }
 
         // If the data is smaller than the alloc size then only allocate what's needed.
         // Once it goes over the allocation size then allocate in chunks.
         {
-            let mmapsize = self.0.mmap.try_read().unwrap().as_ref().len() as u64;
+            let mmapsize = self.0.mmap.try_read().unwrap().size() as u64;
             if mmapsize < self.0.alloc_size {
                 size = mmapsize;
             } else {
@@ -819,19 +1067,43 @@ impl<'a> DB {
             };
         }
 
-        file.get_ref()
-            .allocate(size)
-            .map_err(|_e| Error::ResizeFail)?;
+        storage.grow(size)?;
+
+        if !self.0.no_grow_sync {
+            storage.sync()?;
+        }
+
+        *self.0.file_size.write() = storage.size()?;
+        Ok(())
+    }
+
+    /// Truncates storage to pgid pages, mapping keeps its size.
+    ///
+    /// Does nothing while read transactions hold the mapping.
+    pub(crate) fn truncate(&mut self, pgid: PGID) -> Result<(), Error> {
+        let page_size = self.0.page_size as u64;
+        let size = u64::max(pgid * page_size, page_size * 4);
+
+        let mut storage = self
+            .0
+            .storage
+            .try_write_for(Duration::from_secs(60))
+            .ok_or("can't acquire file lock")?;
+        if storage.size()? <= size {
+            return Ok(());
+        }
+        let _mmap = match self.0.mmap.try_write() {
+            Some(mmap) => mmap,
+            None => return Ok(()),
+        };
 
+        // Pages past the end of the file are free, so mapping may reach past it.
+        storage.truncate(size)?;
         if !self.0.no_grow_sync {
-            file.get_ref().sync_all().map_err(|_| "can't flush file")?;
+            storage.sync()?;
         }
 
-        *self.0.file_size.write() = file
-            .get_ref()
-            .metadata()
-            .map_err(|_| "can't get metadata file")?
-            .len();
+        *self.0.file_size.write() = storage.size()?;
         Ok(())
     }
 }
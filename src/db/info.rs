@@ -1,5 +1,5 @@
 pub struct Info {
-    /// pointer to data
+    /// pointer to data, only first page is readable unless storage is memory mapped
     pub data: *const u8,
     /// database page size
     pub page_size: i64,
@@ -3,14 +3,16 @@ pub mod tests;
 
 pub(crate) mod builder;
 mod common;
+mod compact;
 pub(crate) mod db;
 pub(crate) mod info;
 pub(crate) mod stats;
 mod txguard;
 
 pub use builder::DBBuilder;
+pub use compact::{compact, CompactOptions, CompactStats};
 pub use stats::Stats;
 
 pub(crate) use db::WeakDB;
-pub use db::{CheckMode, DB};
+pub use db::{CheckMode, SyncMode, DB};
 pub use txguard::{RWTxGuard, TxGuard};
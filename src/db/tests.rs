@@ -1,6 +1,13 @@
-use super::{CheckMode, DBBuilder};
-use crate::consts::MAGIC;
+use super::{compact, CheckMode, CompactOptions, DBBuilder, SyncMode, DB};
+use crate::consts::{MAGIC, PGID_NO_FREELIST};
+use crate::errors::Error;
+use crate::freelist::FreelistType;
+use crate::storage::{Mapping, MemoryStorage, Storage};
 use crate::test_utils::temp_file;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 pub(crate) fn db_mock<'a>() -> DBBuilder {
     DBBuilder::new(temp_file())
@@ -42,40 +49,242 @@ fn open_existing() {
     }
 }
 
+#[test]
+fn open_locked() {
+    let path = temp_file();
+    let _db = DBBuilder::new(&path).autoremove(true).build().unwrap();
+
+    let err = DBBuilder::new(&path).build().err().unwrap();
+    assert!(matches!(err, Error::FileLocked(_)));
+    let source = std::error::Error::source(&err).unwrap();
+    assert!(source.downcast_ref::<std::io::Error>().is_some());
+}
+
+#[test]
+fn open_lock_timeout() {
+    let path = temp_file();
+    let db = DBBuilder::new(&path).build().unwrap();
+
+    let start = Instant::now();
+    let err = DBBuilder::new(&path)
+        .lock_timeout(Duration::from_millis(200))
+        .build()
+        .err()
+        .unwrap();
+    assert!(matches!(err, Error::Timeout));
+    assert!(start.elapsed() >= Duration::from_millis(200));
+
+    let handle = {
+        let path = path.clone();
+        thread::spawn(move || {
+            DBBuilder::new(path)
+                .autoremove(true)
+                .lock_timeout(Duration::from_secs(10))
+                .build()
+                .map(|_| ())
+        })
+    };
+    thread::sleep(Duration::from_millis(100));
+    drop(db);
+    handle.join().unwrap().unwrap();
+}
+
+#[test]
+fn open_missing_file() {
+    let err = DBBuilder::new(temp_file())
+        .read_only(true)
+        .build()
+        .err()
+        .unwrap();
+    match err {
+        Error::Io(e) => assert_eq!(e.kind(), std::io::ErrorKind::NotFound),
+        e => panic!("unexpected error: {}", e),
+    }
+}
+
+#[test]
+fn open_in_memory() {
+    let db = DBBuilder::in_memory()
+        .checkmode(CheckMode::PARANOID)
+        .build()
+        .unwrap();
+    assert_eq!(db.path(), None);
+    assert_eq!(db.meta().unwrap().root.root, 3);
+
+    db.update(|tx| -> Result<(), Error> {
+        let mut bucket = tx.create_bucket(b"bucket").unwrap();
+        bucket.put(b"key", b"value".to_vec()).unwrap();
+        Ok(())
+    })
+    .unwrap();
+
+    db.view(|tx| -> Result<(), Error> {
+        assert_eq!(tx.bucket(b"bucket").unwrap().get(b"key").unwrap(), b"value");
+        Ok(())
+    })
+    .unwrap();
+
+    db.remove().unwrap();
+}
+
+#[test]
+fn in_memory_write_to_file() {
+    let path = temp_file();
+    {
+        let db = DBBuilder::in_memory().build().unwrap();
+        db.update(|tx| -> Result<(), Error> {
+            let mut bucket = tx.create_bucket(b"bucket").unwrap();
+            for i in 0..500 {
+                bucket
+                    .put(format!("key{}", i).as_bytes(), vec![1u8; 100])
+                    .unwrap();
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        let tx = db.begin_tx().unwrap();
+        let file = std::fs::File::create(&path).unwrap();
+        let written = tx.write_to(file).unwrap();
+        assert_eq!(written, std::fs::metadata(&path).unwrap().len() as i64);
+    }
+
+    let db = DBBuilder::new(&path)
+        .autoremove(true)
+        .checkmode(CheckMode::PARANOID)
+        .build()
+        .unwrap();
+    db.view(|tx| -> Result<(), Error> {
+        let bucket = tx.bucket(b"bucket").unwrap();
+        for i in 0..500 {
+            assert_eq!(
+                bucket.get(format!("key{}", i).as_bytes()).unwrap(),
+                &[1u8; 100][..]
+            );
+        }
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn try_begin_rw_tx() {
+    let db = db_mock().build().unwrap();
+    let db2 = db.clone();
+
+    let tx = db.begin_rw_tx().unwrap();
+    assert!(matches!(
+        db2.try_begin_rw_tx().err().unwrap(),
+        Error::WriteInProgress
+    ));
+
+    let start = Instant::now();
+    assert!(matches!(
+        db2.begin_rw_tx_timeout(Duration::from_millis(100))
+            .err()
+            .unwrap(),
+        Error::Timeout
+    ));
+    assert!(start.elapsed() >= Duration::from_millis(100));
+
+    tx.rollback().unwrap();
+    db2.try_begin_rw_tx().unwrap().rollback().unwrap();
+    db2.begin_rw_tx_timeout(Duration::from_millis(100))
+        .unwrap()
+        .rollback()
+        .unwrap();
+}
+
 #[test]
 fn panic_while_update() {
-    let mut db = db_mock().build().unwrap();
+    let db = db_mock().build().unwrap();
 
     // create bucket
-    db.update(Box::new(|tx| {
+    db.update(|tx| -> Result<(), Error> {
         let _ = tx.create_bucket(b"exists").unwrap();
         Ok(())
-    }))
+    })
     .unwrap();
 
     // ensure bucket exists
-    db.view(Box::new(|tx| {
+    db.view(|tx| -> Result<(), Error> {
         assert!(tx.bucket(b"exists").is_ok());
         Ok(())
-    }))
+    })
     .unwrap();
 
     // panicking
-    db.update(Box::new(|tx| {
+    db.update(|tx| -> Result<(), Error> {
         let _ = tx.create_bucket(b"not exists").unwrap();
         panic!("oh shi!");
-    }))
+    })
     .unwrap_err();
 
     // ensure transaction wasn't commited
-    db.view(Box::new(|tx| {
+    db.view(|tx| -> Result<(), Error> {
         assert!(tx.bucket(b"exists").is_ok());
         assert!(tx.bucket(b"not exists").is_err());
         Ok(())
-    }))
+    })
     .unwrap();
 }
 
+#[test]
+fn update_returns_value() {
+    let db = db_mock().build().unwrap();
+
+    let seq = db
+        .update(|tx| -> Result<u64, Error> {
+            let mut bucket = tx.create_bucket(b"bucket")?;
+            bucket.put(b"key", b"value".to_vec())?;
+            bucket.next_sequence()
+        })
+        .unwrap();
+    assert_eq!(seq, 1);
+
+    let value = db
+        .view(|tx| -> Result<Vec<u8>, Error> {
+            let bucket = tx.bucket(b"bucket")?;
+            Ok(bucket.get(b"key").unwrap().to_vec())
+        })
+        .unwrap();
+    assert_eq!(value, b"value");
+}
+
+#[test]
+fn update_custom_error() {
+    #[derive(Debug, PartialEq)]
+    enum AppError {
+        Db(String),
+        NotEnough(u64),
+    }
+
+    impl From<Error> for AppError {
+        fn from(e: Error) -> Self {
+            AppError::Db(e.to_string())
+        }
+    }
+
+    let db = db_mock().build().unwrap();
+
+    let result = db.update(|tx| {
+        let mut bucket = tx.create_bucket(b"bucket")?;
+        let seq = bucket.next_sequence()?;
+        if seq < 10 {
+            return Err(AppError::NotEnough(seq));
+        }
+        Ok(seq)
+    });
+    assert_eq!(result, Err(AppError::NotEnough(1)));
+
+    // transaction was rolled back
+    let result = db.view(|tx| -> Result<(), AppError> {
+        let _ = tx.bucket(b"bucket")?;
+        Ok(())
+    });
+    assert!(matches!(result, Err(AppError::Db(_))));
+}
+
 #[test]
 fn batch() {
     use std::time::Duration;
@@ -88,13 +297,16 @@ fn batch() {
 
     let mut handles = vec![];
     for i in 0..10 {
-        let mut db = db.clone();
+        let db = db.clone();
         handles.push(std::thread::spawn(move || {
-            db.batch(Box::new(move |tx| {
-                let _ = tx.create_bucket(format!("{}bubu", i).as_bytes()).unwrap();
-                Ok(())
-            }))
-            .unwrap();
+            let name = db
+                .batch(move |tx| -> Result<Vec<u8>, Error> {
+                    let name = format!("{}bubu", i).into_bytes();
+                    let _ = tx.create_bucket(&name).unwrap();
+                    Ok(name)
+                })
+                .unwrap();
+            assert_eq!(name, format!("{}bubu", i).into_bytes());
         }));
     }
 
@@ -102,11 +314,540 @@ fn batch() {
         h.join().unwrap();
     }
 
-    db.view(Box::new(|tx| {
+    db.view(|tx| -> Result<(), Error> {
         for i in 0..10 {
             let _ = tx.bucket(format!("{}bubu", i).as_bytes()).unwrap();
         }
         Ok(())
-    }))
+    })
+    .unwrap();
+}
+
+#[test]
+fn sync_mode() {
+    let db = db_mock().build().unwrap();
+    assert_eq!(db.sync_mode(), SyncMode::Full);
+
+    let db = db_mock().sync_mode(SyncMode::MetaOnly).build().unwrap();
+    assert_eq!(db.sync_mode(), SyncMode::MetaOnly);
+
+    let db = db_mock().no_sync(true).build().unwrap();
+    assert_eq!(db.sync_mode(), SyncMode::None);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum StorageOp {
+    /// write of data or freelist page
+    Write,
+    /// write of meta page
+    WriteMeta,
+    Flush,
+    Sync,
+}
+
+/// Memory storage which records order of writes, flushes and syncs
+struct RecordingStorage {
+    storage: MemoryStorage,
+    page_size: u64,
+    ops: Arc<Mutex<Vec<StorageOp>>>,
+}
+
+impl Storage for RecordingStorage {
+    fn size(&self) -> Result<u64, Error> {
+        self.storage.size()
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<(), Error> {
+        self.storage.read_at(offset, buf)
+    }
+
+    fn write_at(&mut self, offset: u64, buf: &[u8]) -> Result<(), Error> {
+        let op = if offset < self.page_size * 2 {
+            StorageOp::WriteMeta
+        } else {
+            StorageOp::Write
+        };
+        self.ops.lock().push(op);
+        self.storage.write_at(offset, buf)
+    }
+
+    fn grow(&mut self, size: u64) -> Result<(), Error> {
+        self.storage.grow(size)
+    }
+
+    fn truncate(&mut self, size: u64) -> Result<(), Error> {
+        self.storage.truncate(size)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.ops.lock().push(StorageOp::Flush);
+        self.storage.flush()
+    }
+
+    fn sync(&mut self) -> Result<(), Error> {
+        self.ops.lock().push(StorageOp::Sync);
+        self.storage.sync()
+    }
+
+    fn try_lock(&self, exclusive: bool) -> Result<(), Error> {
+        self.storage.try_lock(exclusive)
+    }
+
+    fn unlock(&self) -> Result<(), Error> {
+        self.storage.unlock()
+    }
+
+    fn map(&mut self, len: usize) -> Result<Box<dyn Mapping>, Error> {
+        self.storage.map(len)
+    }
+}
+
+/// Commits a transaction in given sync mode and returns storage operations it made,
+/// with consecutive page writes collapsed into one.
+fn commit_ops(mode: SyncMode) -> Vec<StorageOp> {
+    let ops = Arc::new(Mutex::new(vec![]));
+    let db = db_mock()
+        .page_size(4096)
+        .sync_mode(mode)
+        .no_grow_sync(true)
+        .storage(RecordingStorage {
+            storage: MemoryStorage::new(),
+            page_size: 4096,
+            ops: ops.clone(),
+        })
+        .build()
+        .unwrap();
+    ops.lock().clear();
+    db.update(|tx| -> Result<(), Error> {
+        let mut bucket = tx.create_bucket(b"bucket")?;
+        for i in 0..100u32 {
+            bucket.put(&i.to_be_bytes(), vec![0; 100])?;
+        }
+        Ok(())
+    })
     .unwrap();
+    let mut ops = ops.lock().clone();
+    ops.dedup_by(|a, b| *a == StorageOp::Write && *b == StorageOp::Write);
+    ops
+}
+
+#[test]
+fn sync_order() {
+    use StorageOp::*;
+
+    // Data reaches the disk before meta page points to it.
+    assert_eq!(
+        commit_ops(SyncMode::Full),
+        vec![Write, Sync, WriteMeta, Sync]
+    );
+    // Meta page may reach the disk before data.
+    assert_eq!(
+        commit_ops(SyncMode::MetaOnly),
+        vec![Write, Flush, WriteMeta, Sync]
+    );
+    // Nothing waits for the disk.
+    assert_eq!(
+        commit_ops(SyncMode::None),
+        vec![Write, Flush, WriteMeta, Flush]
+    );
+}
+
+/// Writes two transactions and returns file images taken after each commit
+/// together with page size and the id of meta page written by the second one.
+fn crash_images() -> (Vec<u8>, Vec<u8>, usize, usize) {
+    let db = db_mock().build().unwrap();
+    let path = db.path().unwrap();
+
+    db.update(|tx| -> Result<(), Error> {
+        let mut bucket = tx.create_bucket(b"first").unwrap();
+        bucket.put(b"key", b"value".to_vec()).unwrap();
+        Ok(())
+    })
+    .unwrap();
+    let committed = std::fs::read(&path).unwrap();
+
+    db.update(|tx| -> Result<(), Error> {
+        let mut bucket = tx.create_bucket(b"second").unwrap();
+        for i in 0..100 {
+            bucket
+                .put(format!("key{}", i).as_bytes(), vec![i as u8; 100])
+                .unwrap();
+        }
+        Ok(())
+    })
+    .unwrap();
+    let crashed = std::fs::read(&path).unwrap();
+
+    let meta_id = (db.meta().unwrap().txid % 2) as usize;
+    (committed, crashed, db.page_size(), meta_id)
+}
+
+fn assert_recovered(image: &[u8]) {
+    let path = temp_file();
+    std::fs::write(&path, image).unwrap();
+    {
+        let db = DBBuilder::new(&path)
+            .autoremove(true)
+            .checkmode(CheckMode::STRONG)
+            .build()
+            .unwrap();
+        let tx = db.begin_tx().unwrap();
+        assert_eq!(tx.bucket(b"first").unwrap().get(b"key").unwrap(), b"value");
+        assert!(tx.bucket(b"second").is_err());
+        tx.check_sync().unwrap();
+    }
+    assert!(!path.exists());
+}
+
+#[test]
+fn crash_drops_meta_write() {
+    // Data pages of the second transaction reached the disk,
+    // but its meta page didn't.
+    let (committed, mut crashed, page_size, meta_id) = crash_images();
+    let range = meta_id * page_size..(meta_id + 1) * page_size;
+    crashed[range.clone()].copy_from_slice(&committed[range]);
+    assert_recovered(&crashed);
+}
+
+#[test]
+fn crash_tears_meta_write() {
+    // Meta page of the second transaction was only partially written.
+    let (_, mut crashed, page_size, meta_id) = crash_images();
+    let start = meta_id * page_size + 32;
+    for b in &mut crashed[start..start + 32] {
+        *b = 0xFF;
+    }
+    assert_recovered(&crashed);
+}
+
+#[test]
+fn crash_truncates_meta_write() {
+    // Meta page of the second transaction was lost and file
+    // was cut right after the pages of the first one.
+    let (committed, mut crashed, page_size, meta_id) = crash_images();
+    let range = meta_id * page_size..(meta_id + 1) * page_size;
+    crashed[range.clone()].copy_from_slice(&committed[range]);
+    crashed.truncate(committed.len());
+    assert_recovered(&crashed);
+}
+
+#[test]
+fn compact_db() {
+    fn reverse(a: &[u8], b: &[u8]) -> std::cmp::Ordering {
+        b.cmp(a)
+    }
+    fn first_byte(value: &[u8]) -> Vec<Vec<u8>> {
+        value.iter().take(1).map(|b| vec![*b]).collect()
+    }
+
+    let db = db_mock()
+        .comparator(1, reverse)
+        .index("first", first_byte)
+        .build()
+        .unwrap();
+    {
+        let mut tx = db.begin_rw_tx().unwrap();
+        {
+            let mut garbage = tx.create_bucket(b"garbage").unwrap();
+            for i in 0..20_000u32 {
+                garbage.put(&i.to_be_bytes(), vec![b'x'; 100]).unwrap();
+            }
+        }
+        {
+            let mut data = tx.create_bucket(b"data").unwrap();
+            for i in 0..1000u32 {
+                data.put(&i.to_be_bytes(), vec![b'x'; 100]).unwrap();
+            }
+            data.set_sequence(42).unwrap();
+            let nested = data.create_bucket(b"nested").unwrap();
+            nested.put(b"key", b"value".to_vec()).unwrap();
+            nested
+                .create_bucket(b"deeper")
+                .unwrap()
+                .set_sequence(7)
+                .unwrap();
+        }
+        {
+            let mut reversed = tx.create_bucket_with_comparator(b"reversed", 1).unwrap();
+            reversed.create_index("first").unwrap();
+            for key in &[&b"a"[..], b"b", b"c"] {
+                reversed.put(key, key.to_vec()).unwrap();
+            }
+        }
+        tx.commit().unwrap();
+    }
+    {
+        let mut tx = db.begin_rw_tx().unwrap();
+        tx.delete_bucket(b"garbage").unwrap();
+        tx.commit().unwrap();
+    }
+
+    let path = temp_file();
+    let stats = compact(&db, &path, CompactOptions::new().tx_max_size(4096)).unwrap();
+    assert_eq!(
+        stats.src_size,
+        std::fs::metadata(db.path().unwrap()).unwrap().len()
+    );
+    assert_eq!(stats.dst_size, std::fs::metadata(&path).unwrap().len());
+    assert!(stats.dst_size < stats.src_size / 4);
+
+    let dst = DBBuilder::new(&path)
+        .autoremove(true)
+        .checkmode(CheckMode::PARANOID)
+        .comparator(1, reverse)
+        .index("first", first_byte)
+        .build()
+        .unwrap();
+    let tx = dst.begin_tx().unwrap();
+    tx.check_sync().unwrap();
+    assert_eq!(tx.buckets(), vec![b"data".to_vec(), b"reversed".to_vec()]);
+
+    let data = tx.bucket(b"data").unwrap();
+    assert_eq!(data.sequence(), 42);
+    let keys: Vec<_> = data.iter().unwrap().map(|(k, _)| k.to_vec()).collect();
+    let mut expected: Vec<_> = (0..1000u32).map(|i| i.to_be_bytes().to_vec()).collect();
+    expected.push(b"nested".to_vec());
+    assert_eq!(keys, expected);
+    assert_eq!(data.get(&500u32.to_be_bytes()).unwrap(), &[b'x'; 100][..]);
+    let nested = data.bucket(b"nested").unwrap();
+    assert_eq!(nested.get(b"key").unwrap(), b"value");
+    assert_eq!(nested.bucket(b"deeper").unwrap().sequence(), 7);
+
+    let reversed = tx.bucket(b"reversed").unwrap();
+    assert_eq!(reversed.comparator_id(), 1);
+    let keys: Vec<_> = reversed.iter().unwrap().map(|(k, _)| k.to_vec()).collect();
+    assert_eq!(keys, vec![b"c".to_vec(), b"b".to_vec(), b"a".to_vec()]);
+    assert_eq!(reversed.index_keys("first", b"b").unwrap(), vec![&b"b"[..]]);
+
+    match compact(&db, &path, CompactOptions::new()) {
+        Err(Error::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::AlreadyExists),
+        r => panic!("unexpected result {:?}", r.map(|_| ())),
+    }
+}
+
+#[test]
+fn compact_failure() {
+    fn reverse(a: &[u8], b: &[u8]) -> std::cmp::Ordering {
+        b.cmp(a)
+    }
+
+    let src_path = temp_file();
+    {
+        let db = db_mock()
+            .path(&src_path)
+            .autoremove(false)
+            .comparator(1, reverse)
+            .build()
+            .unwrap();
+        db.update(|tx| -> Result<(), Error> {
+            tx.create_bucket(b"plain")?.put(b"key", b"value".to_vec())?;
+            tx.create_bucket_with_comparator(b"reversed", 1)?;
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // comparator isn't registered, so the bucket can't be copied
+    let db = db_mock().path(&src_path).build().unwrap();
+    let path = temp_file();
+    assert!(matches!(
+        compact(&db, &path, CompactOptions::new()),
+        Err(Error::ComparatorNotFound(1))
+    ));
+    assert!(!path.exists());
+    assert!(!crate::tx::temp_path(&path).unwrap().exists());
+}
+
+#[test]
+fn freelist_hashmap() {
+    let path = temp_file();
+    let pgid = {
+        let db = db_mock()
+            .path(&path)
+            .autoremove(false)
+            .freelist_type(FreelistType::HashMap)
+            .build()
+            .unwrap();
+        for round in 0..5u8 {
+            db.update(|tx| -> Result<(), Error> {
+                let _ = tx.delete_bucket(b"data");
+                let mut data = tx.create_bucket(b"data")?;
+                for i in 0..2000u32 {
+                    data.put(&i.to_be_bytes(), vec![round; 100 + (i % 7) as usize * 300])?;
+                }
+                Ok(())
+            })
+            .unwrap();
+        }
+        assert!(db.stats().free_page_n > 0);
+        let tx = db.begin_tx().unwrap();
+        tx.check_sync().unwrap();
+        db.meta().unwrap().pgid
+    };
+
+    // On-disk freelist is the same for both types.
+    let db = DBBuilder::new(&path)
+        .autoremove(true)
+        .checkmode(CheckMode::PARANOID)
+        .freelist_type(FreelistType::Array)
+        .build()
+        .unwrap();
+    assert_eq!(db.meta().unwrap().pgid, pgid);
+    let tx = db.begin_tx().unwrap();
+    tx.check_sync().unwrap();
+    let data = tx.bucket(b"data").unwrap();
+    assert_eq!(data.get(&1999u32.to_be_bytes()).unwrap()[0], 4);
+}
+
+#[test]
+fn no_freelist_sync() {
+    let path = temp_file();
+    let fill = |db: &DB, round: u8| {
+        db.update(|tx| -> Result<(), Error> {
+            let _ = tx.delete_bucket(b"data");
+            let mut data = tx.create_bucket(b"data")?;
+            for i in 0..500u32 {
+                data.put(&i.to_be_bytes(), vec![round; 500])?;
+            }
+            Ok(())
+        })
+        .unwrap();
+    };
+
+    // Database which stores freelist switches to the sentinel.
+    let pgids = {
+        let db = db_mock().path(&path).autoremove(false).build().unwrap();
+        fill(&db, 0);
+        assert!(db.meta().unwrap().has_freelist());
+        drop(db);
+
+        let db = db_mock()
+            .path(&path)
+            .autoremove(false)
+            .no_freelist_sync(true)
+            .build()
+            .unwrap();
+        for round in 1..4 {
+            fill(&db, round);
+        }
+        assert_eq!(db.meta().unwrap().freelist, PGID_NO_FREELIST);
+
+        // Rollback restores free pages without freelist page.
+        let pgids = db.0.freelist.read().get_pgids();
+        {
+            let mut tx = db.begin_rw_tx().unwrap();
+            tx.bucket_mut(b"data")
+                .unwrap()
+                .put(b"key", vec![0; 10_000])
+                .unwrap();
+            tx.rollback().unwrap();
+        }
+        assert_eq!(db.0.freelist.read().free_ids(), pgids);
+        pgids
+    };
+
+    // Freelist is rebuilt on open.
+    {
+        let db = db_mock()
+            .path(&path)
+            .autoremove(false)
+            .no_freelist_sync(true)
+            .build()
+            .unwrap();
+        assert!(!db.meta().unwrap().has_freelist());
+        assert_eq!(db.0.freelist.read().free_ids(), pgids);
+        let tx = db.begin_tx().unwrap();
+        assert_eq!(
+            tx.bucket(b"data")
+                .unwrap()
+                .get(&1u32.to_be_bytes())
+                .unwrap()[0],
+            3
+        );
+    }
+
+    // And persisted again when opened without no_freelist_sync.
+    let db = db_mock().path(&path).build().unwrap();
+    let meta = db.meta().unwrap();
+    assert!(meta.has_freelist());
+    assert!(!db.0.freelist.read().freed(meta.freelist));
+    db.begin_tx().unwrap().check_sync().unwrap();
+}
+
+#[test]
+fn shrink() {
+    let path = temp_file();
+    let size = || std::fs::metadata(&path).unwrap().len();
+    let fill = |db: &DB| {
+        db.update(|tx| -> Result<(), Error> {
+            tx.create_bucket_if_not_exists(b"keep")?
+                .put(b"key", b"value".to_vec())?;
+            let mut garbage = tx.create_bucket(b"garbage")?;
+            for i in 0..2000u32 {
+                garbage.put(&i.to_be_bytes(), vec![0; 500])?;
+            }
+            Ok(())
+        })
+        .unwrap();
+    };
+    // Delete garbage and rewrite root, so only free pages are left at the end.
+    let free = |db: &DB| {
+        db.update(|tx| -> Result<(), Error> { tx.delete_bucket(b"garbage") })
+            .unwrap();
+        db.update(|tx| -> Result<(), Error> {
+            tx.bucket_mut(b"keep")?.put(b"key", b"new".to_vec())
+        })
+        .unwrap();
+    };
+
+    // Read check compares old snapshot with current freelist,
+    // so it fails for a reader which outlives commits.
+    let db = db_mock()
+        .path(&path)
+        .autoremove(false)
+        .checkmode(CheckMode::PARANOID - CheckMode::READ)
+        .build()
+        .unwrap();
+    let initial = size();
+    fill(&db);
+    let full = size();
+    assert!(full > initial);
+    free(&db);
+    assert_eq!(size(), full);
+
+    // Reader keeps pages and mapping.
+    {
+        let tx = db.begin_tx().unwrap();
+        assert_eq!(db.shrink().unwrap(), 0);
+        assert_eq!(size(), full);
+        assert!(tx.bucket(b"keep").is_ok());
+    }
+
+    assert_eq!(db.shrink().unwrap(), full - size());
+    // File is cut right after the last page, below the mapping size.
+    let pgid = db.meta().unwrap().pgid;
+    let shrunk = pgid * db.page_size() as u64;
+    assert_eq!(size(), shrunk);
+    assert!(shrunk < initial);
+    assert_eq!(db.shrink().unwrap(), 0);
+    db.begin_tx().unwrap().check_sync().unwrap();
+    drop(db);
+
+    // Shrunk file opens, and auto_shrink keeps it small.
+    let db = db_mock().path(&path).auto_shrink(true).build().unwrap();
+    assert_eq!(db.meta().unwrap().pgid, pgid);
+    {
+        let tx = db.begin_tx().unwrap();
+        assert_eq!(tx.bucket(b"keep").unwrap().get(b"key"), Some(&b"new"[..]));
+    }
+    fill(&db);
+    assert_eq!(size(), db.meta().unwrap().pgid * db.page_size() as u64);
+    assert!(size() > shrunk);
+    free(&db);
+    // Old freelist pages at the end are trimmed by the following commits.
+    for _ in 0..2 {
+        db.update(|_| -> Result<(), Error> { Ok(()) }).unwrap();
+    }
+    assert_eq!(size(), shrunk);
+    db.begin_tx().unwrap().check_sync().unwrap();
 }
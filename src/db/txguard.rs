@@ -25,12 +25,14 @@ impl<'a> Deref for TxGuard<'a> {
 
 /// Guard returned by DB.begin_rw_tx()
 ///
-/// Statically guards against multiple mutable db borrows.
+/// Statically guards against outliving db.
+/// Only one writable transaction exists at a time,
+/// others wait in DB.begin_rw_tx() until guard is committed or rolled back.
 ///
 /// Implements Deref and DerefMut to Tx
 pub struct RWTxGuard<'a> {
     pub(crate) tx: Tx,
-    pub(crate) db: PhantomData<&'a mut DB>,
+    pub(crate) db: PhantomData<&'a DB>,
 }
 
 impl<'a> Deref for RWTxGuard<'a> {
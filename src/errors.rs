@@ -1,11 +1,7 @@
-#![allow(clippy::zero_prefixed_literal)]
-
 use std::error::Error as RError;
 use std::fmt;
 use std::io::Error as IOError;
-use std::sync::PoisonError;
-use std::time::SystemTimeError;
-// use parking_lot;
+use std::sync::{Arc, PoisonError};
 
 /// These error can be returned when opening or calling methods on a DB, Transaction etc...
 #[derive(Debug, Clone)]
@@ -30,6 +26,8 @@ pub enum Error {
     TxClosed,
     TxManaged,
     TxUnmanaged,
+    SavepointGone,
+    BackupCancelled,
 
     // bucket related
     IncompatibleValue,
@@ -40,10 +38,23 @@ pub enum Error {
     KeyRequired,
     KeyTooLarge,
     ValueTooLarge,
+    KeyOutOfOrder,
+    ComparatorNotFound(u32),
+    Codec(String),
+    IndexNotFound(String),
+    IndexNotRegistered(String),
+    IndexedBucket,
 
     ReadInProgress,
     WriteInProgress,
 
+    // storage related
+    Io(Arc<IOError>),
+    Mmap(Arc<IOError>),
+    Lock(Arc<IOError>),
+    FileLocked(Arc<IOError>),
+    Corruption { pgid: u64, reason: String },
+
     // cursor related errors
     StackEmpty,
     ExpectedLeaf,
@@ -77,6 +88,8 @@ impl fmt::Display for Error {
             Error::TxClosed => "tx closed".to_string(),
             Error::TxUnmanaged => "tx is unmanaged".to_string(),
             Error::TxManaged => "tx in use".to_string(),
+            Error::SavepointGone => "savepoint released".to_string(),
+            Error::BackupCancelled => "backup cancelled".to_string(),
 
             Error::IncompatibleValue => "incompatible value".to_string(),
             Error::AllocationFailed => "allocation failed".to_string(),
@@ -86,10 +99,22 @@ impl fmt::Display for Error {
             Error::KeyRequired => "key required".to_string(),
             Error::KeyTooLarge => "key too large".to_string(),
             Error::ValueTooLarge => "value too large".to_string(),
+            Error::KeyOutOfOrder => "key out of order".to_string(),
+            Error::ComparatorNotFound(id) => format!("key comparator {} is not registered", id),
+            Error::Codec(s) => format!("codec error: {}", s),
+            Error::IndexNotFound(name) => format!("index {} not found", name),
+            Error::IndexNotRegistered(name) => format!("index {} is not registered", name),
+            Error::IndexedBucket => "operation is not supported on indexed bucket".to_string(),
 
             Error::ReadInProgress => "database locked on read".to_string(),
             Error::WriteInProgress => "database locked on write".to_string(),
 
+            Error::Io(e) => format!("io error: {}", e),
+            Error::Mmap(e) => format!("mmap failed: {}", e),
+            Error::Lock(e) => format!("file lock failed: {}", e),
+            Error::FileLocked(_) => "database file is locked".to_string(),
+            Error::Corruption { pgid, reason } => format!("page {} corrupted: {}", pgid, reason),
+
             Error::StackEmpty => "stack empty".to_string(),
             Error::ExpectedLeaf => "expected leaf node".to_string(),
             Error::ExpectedBranch => "expected branch node".to_string(),
@@ -104,77 +129,12 @@ impl fmt::Display for Error {
     }
 }
 
-impl Error {
-    fn code(&self) -> u32 {
-        match self {
-            Error::DatabaseGone => 01_000_000,
-            Error::DatabaseClosed => 01_000_001,
-            Error::DatabaseOpen => 01_000_002,
-            Error::DatabaseReadonly => 01_000_003,
-            Error::FileTooSmall => 01_000_004,
-            Error::NoMeta => 01_000_005,
-            Error::Invalid => 01_000_006,
-            Error::VersionMismatch => 01_000_007,
-            Error::Checksum => 01_000_008,
-            Error::Timeout => 01_000_009,
-            Error::ResizeFail => 01_000_010,
-            Error::SyncFail => 01_000_011,
-
-            Error::TxReadonly => 02_000_000,
-            Error::TxGone => 02_000_001,
-            Error::TxClosed => 02_000_002,
-            Error::TxUnmanaged => 02_000_003,
-            Error::TxManaged => 02_000_004,
-
-            Error::IncompatibleValue => 03_000_000,
-            Error::AllocationFailed => 03_000_001,
-            Error::BucketNotFound => 03_000_002,
-            Error::BucketExists => 03_000_003,
-            Error::NameRequired => 03_000_004,
-            Error::KeyRequired => 03_000_005,
-            Error::KeyTooLarge => 03_000_006,
-            Error::ValueTooLarge => 03_000_007,
-
-            Error::ReadInProgress => 04_000_000,
-            Error::WriteInProgress => 04_000_001,
-
-            Error::StackEmpty => 05_000_000,
-            Error::ExpectedLeaf => 05_000_001,
-            Error::ExpectedBranch => 05_000_002,
-            Error::TraverseFailed => 06_000_003,
-
-            Error::CheckFail(_) => 07_000_000,
-            Error::NoneError(_) => 08_000_000,
-            Error::LockError(_) => 09_000_000,
-            Error::Unexpected(_) => 10_000_000,
-        }
-    }
-}
-
-impl PartialEq for Error {
-    fn eq(&self, o: &Self) -> bool {
-        match self {
-            Error::CheckFail(_)
-            | Error::NoneError(_)
-            | Error::LockError(_)
-            | Error::Unexpected(_) => format!("{}", self) == format!("{}", o),
-            _ => self.code() == o.code(),
-        }
-    }
-}
-
 impl<T> From<PoisonError<T>> for Error {
     fn from(e: PoisonError<T>) -> Self {
         Error::LockError(e.to_string())
     }
 }
 
-impl From<SystemTimeError> for Error {
-    fn from(e: SystemTimeError) -> Self {
-        Error::Unexpected(e.to_string())
-    }
-}
-
 impl From<String> for Error {
     fn from(e: String) -> Self {
         Error::Unexpected(e)
@@ -189,7 +149,7 @@ impl From<&str> for Error {
 
 impl From<IOError> for Error {
     fn from(e: IOError) -> Self {
-        Error::Unexpected(e.to_string())
+        Error::Io(Arc::new(e))
     }
 }
 
@@ -199,4 +159,13 @@ impl From<Error> for String {
     }
 }
 
-impl RError for Error {}
+impl RError for Error {
+    fn source(&self) -> Option<&(dyn RError + 'static)> {
+        match self {
+            Error::Io(e) | Error::Mmap(e) | Error::Lock(e) | Error::FileLocked(e) => {
+                Some(e.as_ref())
+            }
+            _ => None,
+        }
+    }
+}
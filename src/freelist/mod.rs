@@ -1,7 +1,9 @@
 #[cfg(test)]
 pub mod tests;
 
-use std::collections::HashMap;
+mod spans;
+
+use std::collections::{HashMap, HashSet};
 use std::mem::size_of;
 
 use crate::consts::{Flags, PGID, TXID};
@@ -9,29 +11,145 @@ use crate::errors::Error;
 use crate::page::{merge_pgids, Page};
 use crate::utils::find_contiguous;
 
+use self::spans::Spans;
+
+/// Type of freelist which keeps free page ids in memory,
+/// both types store free page ids on disk the same way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FreelistType {
+    /// Sorted array of page ids, allocation scans it for contiguous pages.
+    #[default]
+    Array,
+    /// Spans of contiguous pages indexed by size and by first and last page id.
+    /// Allocation and release don't scan all free pages,
+    /// which pays off with many free pages.
+    HashMap,
+}
+
+/// free and available page ids
+#[derive(Debug, Clone)]
+enum FreeIds {
+    Array(Vec<PGID>),
+    HashMap(Spans),
+}
+
+impl FreeIds {
+    fn new(freelist_type: FreelistType, ids: &[PGID]) -> Self {
+        match freelist_type {
+            FreelistType::Array => FreeIds::Array(ids.to_vec()),
+            FreelistType::HashMap => FreeIds::HashMap(Spans::from_sorted(ids)),
+        }
+    }
+
+    fn freelist_type(&self) -> FreelistType {
+        match self {
+            FreeIds::Array(_) => FreelistType::Array,
+            FreeIds::HashMap(_) => FreelistType::HashMap,
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            FreeIds::Array(ids) => ids.len(),
+            FreeIds::HashMap(spans) => spans.len(),
+        }
+    }
+
+    fn contains(&self, id: PGID) -> bool {
+        match self {
+            FreeIds::Array(ids) => ids.binary_search(&id).is_ok(),
+            FreeIds::HashMap(spans) => spans.contains(id),
+        }
+    }
+
+    /// Returns sorted page ids
+    fn to_vec(&self) -> Vec<PGID> {
+        match self {
+            FreeIds::Array(ids) => ids.clone(),
+            FreeIds::HashMap(spans) => spans.ids(),
+        }
+    }
+
+    fn allocate(&mut self, span: u64) -> PGID {
+        match self {
+            FreeIds::Array(ids) => match find_contiguous(ids, span as usize) {
+                Some(index) => {
+                    let pgid = ids[index];
+                    ids.drain(index..index + span as usize);
+                    pgid
+                }
+                None => 0,
+            },
+            FreeIds::HashMap(spans) => spans.allocate(span),
+        }
+    }
+
+    /// Removes free pages at the end of the file, returns new high water mark
+    fn trim(&mut self, mut hw: PGID) -> PGID {
+        match self {
+            FreeIds::Array(ids) => {
+                while ids.last().is_some_and(|id| id + 1 == hw) {
+                    ids.pop();
+                    hw -= 1;
+                }
+                hw
+            }
+            FreeIds::HashMap(spans) => spans.trim(hw),
+        }
+    }
+
+    /// Adds sorted page ids
+    fn merge(&mut self, sorted: &[PGID]) {
+        match self {
+            FreeIds::Array(ids) => *ids = merge_pgids(ids.as_slice(), sorted),
+            FreeIds::HashMap(spans) => {
+                for id in sorted {
+                    spans.insert(*id);
+                }
+            }
+        }
+    }
+}
+
 /// freelist represents a list of all pages that are available for allocation.
 /// It also tracks pages that have been freed but are still in use by open transactions.
 #[derive(Debug, Clone)]
 pub(crate) struct FreeList {
     /// all free and available free page ids
-    ids: Vec<PGID>,
+    free: FreeIds,
     /// mapping of soon-to-be free page ids by tx
     pending: HashMap<TXID, Vec<PGID>>,
-    /// fast lookup of all free and pending page ids.
-    cache: HashMap<PGID, bool>,
+    /// fast lookup of pending page ids.
+    cache: HashSet<PGID>,
 }
 
 impl Default for FreeList {
     fn default() -> Self {
+        Self::new(FreelistType::default())
+    }
+}
+
+impl FreeList {
+    pub fn new(freelist_type: FreelistType) -> Self {
         Self {
-            ids: Vec::new(),
+            free: FreeIds::new(freelist_type, &[]),
             pending: HashMap::new(),
-            cache: HashMap::new(),
+            cache: HashSet::new(),
         }
     }
-}
 
-impl FreeList {
+    /// init replaces free page ids with given ones.
+    pub fn init(&mut self, ids: &[PGID]) {
+        let mut ids = ids.to_vec();
+        ids.sort_unstable();
+        self.free = FreeIds::new(self.free.freelist_type(), &ids);
+    }
+
+    /// free_ids returns sorted free page ids, not including pending ones.
+    pub fn free_ids(&self) -> Vec<PGID> {
+        self.free.to_vec()
+    }
+
     /// size returns the size of the page after serialization.
     pub fn size(&self) -> usize {
         let mut n = self.count();
@@ -48,7 +166,7 @@ impl FreeList {
 
     /// free_count returns count of free pages
     pub fn free_count(&self) -> usize {
-        self.ids.len()
+        self.free.len()
     }
 
     /// pending_count returns count of pending pages
@@ -62,7 +180,7 @@ impl FreeList {
         for list in self.pending.values() {
             m.extend_from_slice(&list);
         }
-        m.extend_from_slice(&self.ids);
+        m.extend_from_slice(&self.free_ids());
         m.sort_unstable();
         m
     }
@@ -70,31 +188,32 @@ impl FreeList {
     /// allocate returns the starting page id of a contiguous list of pages of a given size.
     /// If a contiguous block cannot be found then 0 is returned.
     pub fn allocate(&mut self, span: u64) -> PGID {
-        if self.ids.is_empty() {
+        if self.free.len() == 0 {
             return 0;
         }
 
-        if let Some(index) = find_contiguous(&self.ids, span as usize) {
-            let pgid = self.ids[index];
-            if pgid <= 1 {
-                panic!("invalid page allocation: {}", pgid);
-            };
-            self.ids.drain(index..index + span as usize);
-            // Remove from the free cache.
-            for i in 0..span {
-                self.cache.remove(&(pgid as u64 + i));
-            }
-            pgid
-        } else {
-            0
-        }
+        let pgid = self.free.allocate(span);
+        if pgid == 1 {
+            panic!("invalid page allocation: {}", pgid);
+        };
+        pgid
+    }
+
+    /// trim removes free pages right below high water mark hw,
+    /// so the file can be shrunk. Returns new high water mark.
+    /// Pending pages are kept, they may still be read by open transactions.
+    pub fn trim(&mut self, hw: PGID) -> PGID {
+        self.free.trim(hw)
     }
 
     /// free releases a page and its overflow for a given transaction id.
     /// If the page is already free then a panic will occur.
     pub(crate) fn free(&mut self, txid: TXID, p: &Page) -> Result<(), Error> {
         if p.id <= 1 {
-            return Err(format!("cannot free page 0 or 1: {}", p.id).into());
+            return Err(Error::Corruption {
+                pgid: p.id,
+                reason: "cannot free meta page".to_string(),
+            });
         }
 
         // Free page and all its overflow pages.
@@ -102,13 +221,16 @@ impl FreeList {
         let max = p.id + u64::from(p.overflow);
         for id in p.id..=max {
             // Verify that page is not already free.
-            if self.cache.contains_key(&id) {
-                return Err(format!("page {} already freed", id).into());
+            if self.cache.contains(&id) || self.free.contains(id) {
+                return Err(Error::Corruption {
+                    pgid: id,
+                    reason: "page already freed".to_string(),
+                });
             }
 
             // Add to the freelist and cache.
             ids.push(id);
-            self.cache.insert(id, true);
+            self.cache.insert(id);
         }
 
         Ok(())
@@ -131,7 +253,10 @@ impl FreeList {
         }
 
         m.sort_unstable();
-        self.ids = merge_pgids(self.ids.as_slice(), &m.as_slice());
+        for id in &m {
+            self.cache.remove(id);
+        }
+        self.free.merge(&m);
     }
 
     /// release moves all page ids for a transaction id (or older) to the freelist.
@@ -139,7 +264,7 @@ impl FreeList {
         // Remove page ids from cache.
         if let Some(pending) = self.pending.get(&txid) {
             for id in pending {
-                self.cache.remove(&id).unwrap();
+                self.cache.remove(id);
             }
         }
 
@@ -147,9 +272,30 @@ impl FreeList {
         self.pending.remove(&txid);
     }
 
+    /// pending_len returns count of page ids freed by transaction so far.
+    pub fn pending_len(&self, txid: TXID) -> usize {
+        self.pending.get(&txid).map_or(0, |ids| ids.len())
+    }
+
+    /// rollback_pending removes page ids freed by transaction after first len ones.
+    pub fn rollback_pending(&mut self, txid: TXID, len: usize) {
+        if let Some(pending) = self.pending.get_mut(&txid) {
+            for id in pending.drain(len..) {
+                self.cache.remove(&id);
+            }
+        }
+    }
+
+    /// restore returns allocated page ids back to the freelist.
+    pub fn restore(&mut self, ids: &[PGID]) {
+        let mut ids = ids.to_vec();
+        ids.sort_unstable();
+        self.free.merge(&ids);
+    }
+
     /// freed returns whether a given page is in the free list.
     pub fn freed(&self, pgid: PGID) -> bool {
-        self.cache.contains_key(&pgid)
+        self.cache.contains(&pgid) || self.free.contains(pgid)
     }
 
     /// read initializes the freelist from a freelist page.
@@ -169,11 +315,10 @@ impl FreeList {
         }
 
         if count == 0 {
-            self.ids.clear();
+            self.init(&[]);
         } else {
             let ids = p.freelist();
-            self.ids = ids[idx..count].to_vec();
-            self.ids.sort_unstable();
+            self.init(&ids[idx..count]);
         }
 
         self.reindex();
@@ -207,34 +352,28 @@ impl FreeList {
     pub fn reload(&mut self, p: &Page) {
         self.read(p);
 
-        let mut pcache = Vec::<PGID>::new();
-        for (_, pids) in self.pending.iter() {
-            for pid in pids.iter() {
-                pcache.push(*pid);
-            }
-        }
-
         // Check each page in the freelist and build a new available freelist
         // with any pages not in the pending lists.
-        let mut a = Vec::<PGID>::new();
-        for id in self.ids.iter() {
-            if !pcache.contains(id) {
-                a.push(*id);
-            }
-        }
-        self.ids = a;
+        let ids = self.free_ids();
+        self.reload_free(&ids);
+    }
 
-        self.reindex();
+    /// reload_free replaces free page ids with given ones, filtering out pending items.
+    /// Used instead of reload when freelist isn't stored.
+    pub fn reload_free(&mut self, ids: &[PGID]) {
+        let ids: Vec<PGID> = ids
+            .iter()
+            .copied()
+            .filter(|id| !self.cache.contains(id))
+            .collect();
+        self.init(&ids);
     }
 
     pub fn reindex(&mut self) {
-        let mut new_cache: HashMap<PGID, bool> = HashMap::new();
-        for id in self.ids.iter() {
-            new_cache.insert(*id, true);
-        }
+        let mut new_cache = HashSet::new();
         for (_key, ids) in self.pending.iter() {
             for id in ids.iter() {
-                new_cache.insert(*id, true);
+                new_cache.insert(*id);
             }
         }
         self.cache = new_cache;
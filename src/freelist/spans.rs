@@ -0,0 +1,130 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::consts::PGID;
+
+/// Free page ids kept as spans of contiguous pages.
+///
+/// Spans are indexed by size for allocation and by first and last page id
+/// for merging released pages with their neighbours, so neither scans all ids.
+#[derive(Debug, Clone, Default)]
+pub(super) struct Spans {
+    /// first page ids of spans by span size
+    by_size: BTreeMap<u64, BTreeSet<PGID>>,
+    /// span size by its first page id
+    forward: BTreeMap<PGID, u64>,
+    /// span size by its last page id
+    backward: HashMap<PGID, u64>,
+    /// count of free pages in all spans
+    count: usize,
+}
+
+impl Spans {
+    /// Builds spans from sorted page ids
+    pub(super) fn from_sorted(ids: &[PGID]) -> Self {
+        let mut spans = Self::default();
+        let mut iter = ids.iter().copied();
+        let mut start = match iter.next() {
+            Some(id) => id,
+            None => return spans,
+        };
+        let mut size = 1;
+        for id in iter {
+            if id == start + size {
+                size += 1;
+            } else {
+                spans.add(start, size);
+                start = id;
+                size = 1;
+            }
+        }
+        spans.add(start, size);
+        spans
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.count
+    }
+
+    pub(super) fn contains(&self, id: PGID) -> bool {
+        self.forward
+            .range(..=id)
+            .next_back()
+            .is_some_and(|(start, size)| id < start + size)
+    }
+
+    /// Returns all page ids in ascending order
+    pub(super) fn ids(&self) -> Vec<PGID> {
+        let mut ids = Vec::with_capacity(self.count);
+        for (start, size) in &self.forward {
+            ids.extend(*start..start + size);
+        }
+        ids
+    }
+
+    /// Takes n pages from the smallest span which fits them, lowest span first.
+    /// Returns 0 if there is no such span.
+    pub(super) fn allocate(&mut self, n: u64) -> PGID {
+        if n == 0 {
+            return 0;
+        }
+        let (size, start) = match self.by_size.range(n..).next() {
+            Some((size, starts)) => (*size, *starts.iter().next().unwrap()),
+            None => return 0,
+        };
+        self.remove(start, size);
+        if size > n {
+            self.add(start + n, size - n);
+        }
+        start
+    }
+
+    /// Adds a page, merging it with adjacent spans
+    pub(super) fn insert(&mut self, id: PGID) {
+        let mut start = id;
+        let mut size = 1;
+        if let Some(prev) = id.checked_sub(1).and_then(|end| self.backward.get(&end)) {
+            let prev = *prev;
+            start = id - prev;
+            size += prev;
+            self.remove(start, prev);
+        }
+        if let Some(next) = self.forward.get(&(id + 1)) {
+            let next = *next;
+            size += next;
+            self.remove(id + 1, next);
+        }
+        self.add(start, size);
+    }
+
+    /// Removes span ending right before hw page id.
+    /// Returns its first page id or hw if there is no such span.
+    pub(super) fn trim(&mut self, hw: PGID) -> PGID {
+        match self.forward.iter().next_back() {
+            Some((start, size)) if start + size == hw => {
+                let (start, size) = (*start, *size);
+                self.remove(start, size);
+                start
+            }
+            _ => hw,
+        }
+    }
+
+    fn add(&mut self, start: PGID, size: u64) {
+        self.by_size.entry(size).or_default().insert(start);
+        self.forward.insert(start, size);
+        self.backward.insert(start + size - 1, size);
+        self.count += size as usize;
+    }
+
+    fn remove(&mut self, start: PGID, size: u64) {
+        if let Some(starts) = self.by_size.get_mut(&size) {
+            starts.remove(&start);
+            if starts.is_empty() {
+                self.by_size.remove(&size);
+            }
+        }
+        self.forward.remove(&start);
+        self.backward.remove(&(start + size - 1));
+        self.count -= size as usize;
+    }
+}
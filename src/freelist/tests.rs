@@ -1,7 +1,18 @@
-use super::FreeList;
+use super::{FreeList, FreelistType};
 use crate::consts::{Flags, PGID};
+use crate::errors::Error;
 use crate::page::{OwnedPage, PageData};
 
+const TYPES: [FreelistType; 2] = [FreelistType::Array, FreelistType::HashMap];
+
+fn page(id: PGID, overflow: u32) -> OwnedPage {
+    let mut page = OwnedPage::new(1024);
+    page.id = id;
+    page.overflow = overflow;
+    page.flags = Flags::FREELIST;
+    page
+}
+
 #[test]
 fn free() {
     let mut f = FreeList::default();
@@ -16,6 +27,25 @@ fn free() {
     assert_eq!(&vec![12], f.pending.get(&100).unwrap());
 }
 
+#[test]
+fn free_twice() {
+    let mut f = FreeList::default();
+    let mut page = OwnedPage::new(1024);
+    page.id = 12;
+    page.flags = Flags::FREELIST;
+    f.free(100, &page).unwrap();
+    match f.free(101, &page) {
+        Err(Error::Corruption { pgid, .. }) => assert_eq!(pgid, 12),
+        _ => panic!("double free must be reported as corruption"),
+    }
+
+    page.id = 1;
+    assert!(matches!(
+        f.free(100, &page),
+        Err(Error::Corruption { pgid: 1, .. })
+    ));
+}
+
 #[test]
 fn free_overflow() {
     let mut f = FreeList::default();
@@ -60,37 +90,37 @@ fn release() {
     f.release(100);
     f.release(101);
 
-    assert_eq!(vec![9, 12, 13], f.ids);
+    assert_eq!(vec![9, 12, 13], f.free_ids());
 
     f.release(102);
-    assert_eq!(vec![9, 12, 13, 39], f.ids);
+    assert_eq!(vec![9, 12, 13, 39], f.free_ids());
 }
 
 #[test]
 fn allocate() {
     let mut f = FreeList::default();
-    f.ids = vec![3, 4, 5, 6, 7, 9, 12, 13, 18];
+    f.init(&[3, 4, 5, 6, 7, 9, 12, 13, 18]);
 
     assert_eq!(3, f.allocate(3));
-    assert_eq!(vec![6, 7, 9, 12, 13, 18], f.ids);
+    assert_eq!(vec![6, 7, 9, 12, 13, 18], f.free_ids());
     assert_eq!(6, f.allocate(1));
-    assert_eq!(vec![7, 9, 12, 13, 18], f.ids);
+    assert_eq!(vec![7, 9, 12, 13, 18], f.free_ids());
     assert_eq!(0, f.allocate(3));
-    assert_eq!(vec![7, 9, 12, 13, 18], f.ids);
+    assert_eq!(vec![7, 9, 12, 13, 18], f.free_ids());
     assert_eq!(12, f.allocate(2));
-    assert_eq!(vec![7, 9, 18], f.ids);
+    assert_eq!(vec![7, 9, 18], f.free_ids());
     assert_eq!(7, f.allocate(1));
-    assert_eq!(vec![9, 18], f.ids);
+    assert_eq!(vec![9, 18], f.free_ids());
     assert_eq!(0, f.allocate(0));
-    assert_eq!(vec![9, 18], f.ids);
+    assert_eq!(vec![9, 18], f.free_ids());
     assert_eq!(0, f.allocate(0));
-    assert_eq!(vec![9, 18], f.ids);
+    assert_eq!(vec![9, 18], f.free_ids());
     assert_eq!(9, f.allocate(1));
-    assert_eq!(vec![18], f.ids);
+    assert_eq!(vec![18], f.free_ids());
     assert_eq!(18, f.allocate(1));
-    assert_eq!(Vec::<PGID>::new(), f.ids);
+    assert_eq!(Vec::<PGID>::new(), f.free_ids());
     assert_eq!(0, f.allocate(1));
-    assert_eq!(Vec::<PGID>::new(), f.ids);
+    assert_eq!(Vec::<PGID>::new(), f.free_ids());
 }
 
 #[test]
@@ -109,13 +139,13 @@ fn read() {
     let mut f = FreeList::default();
     f.read(&page);
 
-    assert_eq!(&[23 as PGID, 50], f.ids.as_slice());
+    assert_eq!(&[23 as PGID, 50], f.free_ids().as_slice());
 }
 
 #[test]
 fn write() {
     let mut fl = FreeList::default();
-    fl.ids = vec![12, 39];
+    fl.init(&[12, 39]);
     fl.pending.insert(100, vec![28, 11]);
     fl.pending.insert(101, vec![3]);
 
@@ -132,5 +162,228 @@ fn write() {
     let mut fl2 = FreeList::default();
     fl2.read(&page);
 
-    assert_eq!(vec![3, 11, 12, 28, 39], fl2.ids);
+    assert_eq!(vec![3, 11, 12, 28, 39], fl2.free_ids());
+}
+
+#[test]
+fn free_any_type() {
+    for t in TYPES.iter().copied() {
+        let mut f = FreeList::new(t);
+        f.free(100, &page(12, 0)).unwrap();
+        assert_eq!(&vec![12], f.pending.get(&100).unwrap());
+        assert!(f.freed(12));
+    }
+}
+
+#[test]
+fn free_twice_any_type() {
+    for t in TYPES.iter().copied() {
+        let mut f = FreeList::new(t);
+        let mut page = page(12, 0);
+        f.free(100, &page).unwrap();
+        match f.free(101, &page) {
+            Err(Error::Corruption { pgid, .. }) => assert_eq!(pgid, 12),
+            _ => panic!("double free must be reported as corruption"),
+        }
+
+        page.id = 1;
+        assert!(matches!(
+            f.free(100, &page),
+            Err(Error::Corruption { pgid: 1, .. })
+        ));
+
+        // Released page is free too.
+        f.release(100);
+        page.id = 12;
+        assert!(matches!(
+            f.free(102, &page),
+            Err(Error::Corruption { pgid: 12, .. })
+        ));
+    }
+}
+
+#[test]
+fn free_overflow_any_type() {
+    for t in TYPES.iter().copied() {
+        let mut f = FreeList::new(t);
+        f.free(100, &page(12, 3)).unwrap();
+        assert_eq!(&vec![12, 13, 14, 15], f.pending.get(&100).unwrap());
+    }
+}
+
+#[test]
+fn release_any_type() {
+    for t in TYPES.iter().copied() {
+        let mut f = FreeList::new(t);
+        f.free(100, &page(12, 1)).unwrap();
+        f.free(100, &page(9, 0)).unwrap();
+        f.free(102, &page(39, 0)).unwrap();
+        f.release(100);
+        f.release(101);
+
+        assert_eq!(vec![9, 12, 13], f.free_ids());
+
+        f.release(102);
+        assert_eq!(vec![9, 12, 13, 39], f.free_ids());
+        assert_eq!(4, f.free_count());
+        assert_eq!(0, f.pending_count());
+    }
+}
+
+#[test]
+fn rollback() {
+    for t in TYPES.iter().copied() {
+        let mut f = FreeList::new(t);
+        f.init(&[3, 4]);
+        f.free(100, &page(12, 1)).unwrap();
+        f.rollback(100);
+
+        assert!(!f.freed(12));
+        assert!(!f.freed(13));
+        assert!(f.freed(4));
+        assert_eq!(vec![3, 4], f.get_pgids());
+    }
+}
+
+#[test]
+fn allocate_any_type() {
+    for t in TYPES.iter().copied() {
+        let mut f = FreeList::new(t);
+        f.init(&[3, 4, 5, 6, 7, 9, 12, 13, 18]);
+
+        assert_eq!(3, f.allocate(3));
+        assert_eq!(vec![6, 7, 9, 12, 13, 18], f.free_ids());
+        assert_eq!(0, f.allocate(3));
+        assert_eq!(vec![6, 7, 9, 12, 13, 18], f.free_ids());
+        assert_eq!(6, f.allocate(2));
+        assert_eq!(vec![9, 12, 13, 18], f.free_ids());
+        assert_eq!(12, f.allocate(2));
+        assert_eq!(vec![9, 18], f.free_ids());
+        assert_eq!(0, f.allocate(0));
+        assert_eq!(vec![9, 18], f.free_ids());
+        assert_eq!(9, f.allocate(1));
+        assert_eq!(vec![18], f.free_ids());
+        assert_eq!(18, f.allocate(1));
+        assert_eq!(Vec::<PGID>::new(), f.free_ids());
+        assert_eq!(0, f.allocate(1));
+        assert_eq!(Vec::<PGID>::new(), f.free_ids());
+        assert!(!f.freed(18));
+    }
+}
+
+#[test]
+fn allocate_array() {
+    // Array freelist takes the first contiguous run.
+    let mut f = FreeList::new(FreelistType::Array);
+    f.init(&[3, 4, 5, 6, 7, 9, 12, 13, 18]);
+
+    assert_eq!(3, f.allocate(1));
+    assert_eq!(vec![4, 5, 6, 7, 9, 12, 13, 18], f.free_ids());
+    assert_eq!(4, f.allocate(2));
+    assert_eq!(vec![6, 7, 9, 12, 13, 18], f.free_ids());
+}
+
+#[test]
+fn allocate_hashmap() {
+    // HashMap freelist takes the lowest of the smallest spans which fit.
+    let mut f = FreeList::new(FreelistType::HashMap);
+    f.init(&[3, 4, 5, 6, 7, 9, 12, 13, 18]);
+
+    assert_eq!(9, f.allocate(1));
+    assert_eq!(vec![3, 4, 5, 6, 7, 12, 13, 18], f.free_ids());
+    assert_eq!(12, f.allocate(2));
+    assert_eq!(vec![3, 4, 5, 6, 7, 18], f.free_ids());
+    assert_eq!(18, f.allocate(1));
+    assert_eq!(3, f.allocate(1));
+    assert_eq!(vec![4, 5, 6, 7], f.free_ids());
+    assert_eq!(4, f.allocate(4));
+    assert_eq!(0, f.free_count());
+}
+
+#[test]
+fn merge_spans() {
+    for t in TYPES.iter().copied() {
+        let mut f = FreeList::new(t);
+        f.init(&[3, 7]);
+        // Joins both neighbours into a single span.
+        f.free(100, &page(4, 2)).unwrap();
+        f.release(100);
+        assert_eq!(vec![3, 4, 5, 6, 7], f.free_ids());
+        assert_eq!(3, f.allocate(5));
+
+        f.init(&[10, 11]);
+        f.restore(&[12, 9]);
+        assert_eq!(vec![9, 10, 11, 12], f.free_ids());
+        assert_eq!(9, f.allocate(4));
+    }
+}
+
+#[test]
+fn trim() {
+    for t in TYPES.iter().copied() {
+        let mut f = FreeList::new(t);
+        f.init(&[3, 4, 7, 8, 9]);
+        assert_eq!(11, f.trim(11));
+        assert_eq!(7, f.trim(10));
+        assert_eq!(vec![3, 4], f.free_ids());
+
+        // Pending pages are not trimmed.
+        f.free(100, &page(5, 1)).unwrap();
+        assert_eq!(7, f.trim(7));
+        f.release(100);
+        assert_eq!(3, f.trim(7));
+        assert_eq!(0, f.free_count());
+    }
+}
+
+#[test]
+fn read_any_type() {
+    for t in TYPES.iter().copied() {
+        let mut page = page(1, 0);
+        let ids: &[PGID] = &[23, 50];
+        page.copy_data_from(PageData::Freelist(ids));
+
+        // Deserialize page into a freelist.
+        let mut f = FreeList::new(t);
+        f.read(&page);
+
+        assert_eq!(vec![23 as PGID, 50], f.free_ids());
+    }
+}
+
+#[test]
+fn write_any_type() {
+    for t in TYPES.iter().copied() {
+        let mut fl = FreeList::new(t);
+        fl.init(&[12, 39]);
+        fl.pending.insert(100, vec![28, 11]);
+        fl.pending.insert(101, vec![3]);
+
+        let mut page = page(1, 0);
+
+        fl.write(&mut page);
+
+        let mut fl2 = FreeList::new(t);
+        fl2.read(&page);
+
+        assert_eq!(vec![3, 11, 12, 28, 39], fl2.free_ids());
+    }
+}
+
+#[test]
+fn reload() {
+    for t in TYPES.iter().copied() {
+        let mut fl = FreeList::new(t);
+        fl.init(&[12, 39]);
+        fl.pending.insert(100, vec![28, 11]);
+        let mut page = page(1, 0);
+        fl.write(&mut page);
+
+        // Pending pages stay pending.
+        let mut fl2 = FreeList::new(t);
+        fl2.pending.insert(100, vec![28, 11]);
+        fl2.reload(&page);
+        assert_eq!(vec![12, 39], fl2.free_ids());
+        assert!(fl2.freed(28));
+    }
 }
@@ -7,7 +7,7 @@
 //! ```no_run
 //! use nut::DBBuilder;
 //!
-//! let mut db = DBBuilder::new("test.db").build().unwrap();
+//! let db = DBBuilder::new("test.db").build().unwrap();
 //! let mut tx = db.begin_rw_tx().unwrap();
 //! {
 //!   let mut flowers = tx.create_bucket(b"flowers").unwrap();
@@ -48,7 +48,7 @@
 //! ```no_run
 //! use nut::DBBuilder;
 //!
-//! let mut db = DBBuilder::new("test.db").build().unwrap();
+//! let db = DBBuilder::new("test.db").build().unwrap();
 //!
 //! // creating read only transaction
 //! // read only ransaction will be automatically rolled back
@@ -67,7 +67,7 @@
 //! ```no_run
 //! use nut::DBBuilder;
 //!
-//! let mut db = DBBuilder::new("test.db").build().unwrap();
+//! let db = DBBuilder::new("test.db").build().unwrap();
 //! let mut tx = db.begin_tx().unwrap();
 //!
 //! {
@@ -105,7 +105,10 @@ mod test_utils;
 #[cfg(test)]
 mod concurrency_tests;
 
+#[cfg(feature = "async")]
+mod asyncdb;
 mod bucket;
+mod codec;
 mod consts;
 mod db;
 mod errors;
@@ -113,11 +116,26 @@ mod freelist;
 mod meta;
 mod node;
 mod page;
+mod storage;
 mod tx;
 mod utils;
 
-pub use bucket::{Bucket, BucketStats, Cursor, CursorItem};
+#[cfg(feature = "async")]
+pub use asyncdb::{AsyncDB, Completion};
+pub use bucket::{
+    Bucket, BucketStats, Comparator, Cursor, CursorItem, Entry, Iter, TypedBucket, TypedIter,
+};
+#[cfg(feature = "bincode")]
+pub use codec::BincodeCodec;
+#[cfg(feature = "json")]
+pub use codec::JsonCodec;
+pub use codec::{Codec, NativeCodec};
 pub use consts::Flags;
-pub use db::{CheckMode, DBBuilder, RWTxGuard, Stats as DBStats, TxGuard, DB};
+pub use db::{
+    compact, CheckMode, CompactOptions, CompactStats, DBBuilder, RWTxGuard, Stats as DBStats,
+    SyncMode, TxGuard, DB,
+};
 pub use errors::Error;
-pub use tx::{Tx, TxStats};
+pub use freelist::FreelistType;
+pub use storage::{CachedFileStorage, Mapping, MemoryStorage, MmapStorage, Storage};
+pub use tx::{Backup, Savepoint, Tx, TxStats};
@@ -5,7 +5,7 @@ use fnv::FnvHasher;
 use std::hash::Hasher;
 
 use crate::bucket::IBucket;
-use crate::consts::{MAGIC, PGID, TXID, VERSION};
+use crate::consts::{COMPARATOR_VERSION, MAGIC, PGID, PGID_NO_FREELIST, TXID, VERSION};
 use crate::errors::Error;
 use crate::page::{Page, PageData};
 
@@ -68,7 +68,7 @@ impl Meta {
     pub fn validate(&self) -> Result<(), Error> {
         if self.magic != MAGIC {
             return Err(Error::Invalid);
-        } else if self.version != VERSION {
+        } else if self.version != VERSION && self.version != COMPARATOR_VERSION {
             return Err(Error::VersionMismatch);
         } else if self.checksum != 0 && self.checksum != self.sum64() {
             return Err(Error::Checksum);
@@ -76,19 +76,22 @@ impl Meta {
         Ok(())
     }
 
+    /// Returns whether freelist is stored in Meta.freelist page
+    pub fn has_freelist(&self) -> bool {
+        self.freelist != PGID_NO_FREELIST
+    }
+
     pub(crate) fn write(&mut self, p: &mut Page) -> Result<(), Error> {
         if self.root.root >= self.pgid {
-            return Err(format!(
-                "root bucket pgid ({}) above high water mark ({})",
-                self.root.root, self.pgid
-            )
-            .into());
-        } else if self.freelist >= self.pgid {
-            return Err(format!(
-                "freelist pgid ({}) above high water mark ({})",
-                self.freelist, self.pgid
-            )
-            .into());
+            return Err(Error::Corruption {
+                pgid: self.root.root,
+                reason: format!("root bucket pgid above high water mark ({})", self.pgid),
+            });
+        } else if self.has_freelist() && self.freelist >= self.pgid {
+            return Err(Error::Corruption {
+                pgid: self.freelist,
+                reason: format!("freelist pgid above high water mark ({})", self.pgid),
+            });
         }
 
         // Page id is either going to be 0 or 1 which we can determine by the transaction ID.
@@ -1,4 +1,4 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 use std::sync::atomic::AtomicBool;
 
@@ -57,6 +57,7 @@ impl NodeBuilder {
             parent: RefCell::new(self.parent),
             children: RefCell::new(self.children),
             inodes: RefCell::new(vec![]),
+            fill_percent: Cell::new(None),
         }))
     }
 }
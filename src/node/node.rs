@@ -1,4 +1,6 @@
-use std::cell::RefCell;
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell, RefMut};
+use std::cmp;
 use std::rc::{Rc, Weak};
 use std::sync::atomic::{AtomicBool, Ordering};
 
@@ -38,6 +40,9 @@ pub(crate) struct NodeInner {
 
     /// node's data
     pub(crate) inodes: RefCell<Vec<INode>>,
+
+    /// fill percent of bulk loaded node, overrides bucket's one on split
+    pub(crate) fill_percent: Cell<Option<f64>>,
 }
 
 #[derive(Clone, Debug)]
@@ -116,16 +121,45 @@ impl Node {
     ///
     /// returns None if no split occured, or parent Node otherwise
     pub(crate) fn split(&mut self, page_size: usize) -> Result<Option<Node>, Error> {
-        // let mut parent = self.parent();
-        let mut nodes = vec![self.clone()];
-        while let Some(n) = nodes.last().unwrap().clone().split_two(page_size)? {
-            nodes.push(n);
+        // Find all split points first, so every inode is moved only once
+        // no matter how large the node is.
+        let mut indexes = vec![];
+        {
+            let inodes = self.0.inodes.borrow();
+            let mut start = 0;
+            while let Some(index) = self.split_point(&inodes[start..], page_size)? {
+                start += index;
+                indexes.push(start);
+            }
         }
 
-        if nodes.len() == 1 {
+        if indexes.is_empty() {
             return Ok(None);
         }
 
+        let mut nodes = Vec::with_capacity(indexes.len() + 1);
+        {
+            let mut inodes = self.0.inodes.borrow_mut();
+            for index in indexes.iter().rev() {
+                let next = NodeBuilder::new(self.0.bucket)
+                    .is_leaf(self.is_leaf())
+                    .build();
+                *next.0.inodes.borrow_mut() = inodes.split_off(*index);
+                nodes.push(next);
+            }
+            inodes.shrink_to_fit();
+        }
+        nodes.push(self.clone());
+        nodes.reverse();
+
+        self.bucket_mut()
+            .ok_or("bucket empty")?
+            .tx()?
+            .0
+            .stats
+            .lock()
+            .split += indexes.len();
+
         let parent = match self.parent() {
             Some(p) => {
                 let mut children = p.0.children.borrow_mut();
@@ -152,44 +186,25 @@ impl Node {
         Ok(Some(parent))
     }
 
-    /// Breaks up a node into two smaller nodes, if appropriate.
-    /// This should only be called from the split() function.
-    ///
-    /// Returns next node if splid did occur, or None if not
-    pub(super) fn split_two(&mut self, page_size: usize) -> Result<Option<Node>, Error> {
-        let split_index = {
-            if self.0.inodes.borrow().len() <= (MIN_KEYS_PER_PAGE * 2)
-                || self.size_less_than(page_size)
-            {
-                return Ok(None);
-            }
+    /// Returns index at which given inodes are split off into the next node,
+    /// or None if they fit into a single page.
+    pub(super) fn split_point(
+        &self,
+        inodes: &[INode],
+        page_size: usize,
+    ) -> Result<Option<usize>, Error> {
+        if inodes.len() <= (MIN_KEYS_PER_PAGE * 2) || self.size_less_than(inodes, page_size) {
+            return Ok(None);
+        }
 
-            let fp = clamp(
-                self.bucket().ok_or("bucket empty")?.fill_percent,
-                Bucket::MIN_FILL_PERCENT,
-                Bucket::MAX_FILL_PERCENT,
-            );
-            let threshold = (fp * page_size as f64) as usize;
-            let (split_index, _) = self.split_index(threshold);
-            split_index
+        let fp = match self.0.fill_percent.get() {
+            Some(fp) => fp,
+            None => self.bucket().ok_or("bucket empty")?.fill_percent,
         };
-
-        let next = NodeBuilder::new(self.0.bucket)
-            .is_leaf(self.is_leaf())
-            .build();
-        let nodes = self.0.inodes.borrow_mut().drain(split_index..).collect();
-        // parent.0.children.borrow_mut().push(next.clone());
-        *next.0.inodes.borrow_mut() = nodes;
-
-        self.bucket_mut()
-            .ok_or("bucket empty")?
-            .tx()?
-            .0
-            .stats
-            .lock()
-            .split += 1;
-
-        Ok(Some(next))
+        let fp = clamp(fp, Bucket::MIN_FILL_PERCENT, Bucket::MAX_FILL_PERCENT);
+        let threshold = (fp * page_size as f64) as usize;
+        let (split_index, _) = self.split_index(inodes, threshold);
+        Ok(Some(split_index))
     }
 
     /// Writes the nodes to dirty pages and splits nodes as it goes.
@@ -202,7 +217,9 @@ impl Node {
         let page_size = self.bucket().unwrap().tx()?.db()?.page_size();
         {
             let mut children = self.0.children.borrow_mut().clone();
-            children.sort_by(Node::cmp_by_key);
+            children.sort_by(|a, b| {
+                self.compare(&a.0.inodes.borrow()[0].key, &b.0.inodes.borrow()[0].key)
+            });
             for child in &mut *children {
                 child.spill()?;
             }
@@ -263,7 +280,7 @@ impl Node {
                         okey = Some(nkey.clone());
                     }
                     let pgid = *node.0.pgid.borrow();
-                    p.put(okey.unwrap().as_slice(), &nkey, vec![], pgid, 0);
+                    p.put(okey.unwrap().as_slice(), &nkey, &[][..], pgid, 0);
                     *node.0.key.borrow_mut() = Some(nkey);
                     assert!(
                         !node.0.key.borrow().as_ref().unwrap().is_empty(),
@@ -521,13 +538,13 @@ impl Node {
         sz
     }
 
-    /// Returns true if the node is less than a given size.
+    /// Returns true if inodes of the node are less than a given size.
     /// This is an optimization to avoid calculating a large node when we only need
     /// to know if it fits inside a certain page size.
-    pub(super) fn size_less_than(&self, v: usize) -> bool {
+    pub(super) fn size_less_than(&self, inodes: &[INode], v: usize) -> bool {
         let mut sz = Page::header_size();
         let elsz = self.page_element_size();
-        for ind in &*self.0.inodes.borrow() {
+        for ind in inodes {
             sz += elsz + ind.key.len() + ind.value.len();
             if sz >= v {
                 return false;
@@ -536,8 +553,60 @@ impl Node {
         true
     }
 
-    /// Inserts a key/value.
-    pub fn put(&mut self, old_key: &[u8], new_key: &[u8], value: Vec<u8>, pgid: PGID, flags: u32) {
+    /// Inserts a key/value and returns stored value.
+    pub fn put<'v>(
+        &mut self,
+        old_key: &[u8],
+        new_key: &[u8],
+        value: impl Into<Cow<'v, [u8]>>,
+        pgid: PGID,
+        flags: u32,
+    ) -> RefMut<'_, [u8]> {
+        let mut inode = self.insert(old_key, new_key, pgid, flags);
+        match value.into() {
+            Cow::Owned(value) => inode.value = value,
+            Cow::Borrowed(value) => {
+                // reuse buffer of replaced value
+                inode.value.clear();
+                inode.value.extend_from_slice(value);
+            }
+        }
+        RefMut::map(inode, |inode| inode.value.as_mut_slice())
+    }
+
+    /// Inserts a key with zeroed value of given length
+    /// and returns the value to be filled in place.
+    pub(crate) fn put_reserve(&mut self, key: &[u8], len: usize) -> RefMut<'_, [u8]> {
+        let mut inode = self.insert(key, key, 0, 0);
+        inode.value.clear();
+        inode.value.resize(len, 0);
+        RefMut::map(inode, |inode| inode.value.as_mut_slice())
+    }
+
+    /// Appends key/values after the last inode, keys must be sorted
+    /// and greater than keys of the node.
+    pub(crate) fn append(&mut self, items: Vec<(Vec<u8>, Vec<u8>)>) {
+        let mut inodes = self.0.inodes.borrow_mut();
+        debug_assert!(match (inodes.last(), items.first()) {
+            (Some(last), Some((first, _))) => self.compare(&last.key, first) == cmp::Ordering::Less,
+            _ => true,
+        });
+        inodes.extend(items.into_iter().map(|(key, value)| INode {
+            flags: 0,
+            pgid: 0,
+            key,
+            value,
+        }));
+    }
+
+    /// Finds or creates inode for a key and updates its key, page id and flags.
+    fn insert(
+        &mut self,
+        old_key: &[u8],
+        new_key: &[u8],
+        pgid: PGID,
+        flags: u32,
+    ) -> RefMut<'_, INode> {
         let meta_pgid = self.bucket().unwrap().tx().unwrap().pgid();
 
         if pgid >= meta_pgid {
@@ -549,7 +618,7 @@ impl Node {
         }
 
         let mut inodes = self.0.inodes.borrow_mut();
-        let (exact, index) = match inodes.binary_search_by(|i| i.key.as_slice().cmp(old_key)) {
+        let (exact, index) = match inodes.binary_search_by(|i| self.compare(&i.key, old_key)) {
             Ok(n) => (true, n),
             Err(n) => (false, n),
         };
@@ -558,18 +627,20 @@ impl Node {
             inodes.insert(index, INode::default());
         }
 
-        let mut inode = &mut inodes[index];
-        inode.key = new_key.to_vec();
-        inode.value = value;
+        let mut inode = RefMut::map(inodes, |inodes| &mut inodes[index]);
+        if !exact || inode.key != new_key {
+            inode.key = new_key.to_vec();
+        }
         inode.flags = flags;
         inode.pgid = pgid;
+        inode
     }
 
     // Removes a key from the node.
     pub fn del(&mut self, key: &[u8]) {
         let mut inodes = self.0.inodes.borrow_mut();
 
-        let (exact, index) = match inodes.binary_search_by(|i| i.key.as_slice().cmp(key)) {
+        let (exact, index) = match inodes.binary_search_by(|i| self.compare(&i.key, key)) {
             Ok(n) => (true, n),
             Err(n) => (false, n),
         };
@@ -695,11 +766,10 @@ impl Node {
     /// Finds the position where a page will fill a given threshold.
     /// It returns the index as well as the size of the first page.
     /// This is only be called from split().
-    pub(super) fn split_index(&self, threshold: usize) -> (usize, usize) {
+    pub(super) fn split_index(&self, inodes: &[INode], threshold: usize) -> (usize, usize) {
         let mut rindex = 0;
         let mut pgsize = Page::header_size();
 
-        let inodes = self.0.inodes.borrow();
         let pelsize = self.page_element_size();
         let max = inodes.len() - MIN_KEYS_PER_PAGE;
 
@@ -750,8 +820,12 @@ impl Node {
     // }
     // }
 
-    fn cmp_by_key(a: &Node, b: &Node) -> std::cmp::Ordering {
-        a.0.inodes.borrow()[0].key.cmp(&b.0.inodes.borrow()[0].key)
+    /// Compares keys in the order of node's bucket
+    fn compare(&self, a: &[u8], b: &[u8]) -> cmp::Ordering {
+        match self.bucket() {
+            Some(bucket) => bucket.compare(a, b),
+            None => a.cmp(b),
+        }
     }
 }
 
@@ -165,9 +165,9 @@ fn split_two() {
     n.put(b"00000005", b"00000005", b"0123456701234567".to_vec(), 0, 0);
 
     // Split between 2 & 3.
-    let next = n.split_two(100).unwrap();
+    let next = n.split_point(&n.0.inodes.borrow(), 100).unwrap();
 
-    assert!(next.is_some());
+    assert_eq!(next, Some(2));
 }
 
 #[test]
@@ -182,12 +182,35 @@ fn split_two_fail() {
     n.put(b"00000004", b"00000004", b"0123456701234567".to_vec(), 0, 0);
     n.put(b"00000005", b"00000005", b"0123456701234567".to_vec(), 0, 0);
 
-    // Split between 2 & 3.
-    let next = n.split_two(4096).unwrap();
+    let next = n.split_point(&n.0.inodes.borrow(), 4096).unwrap();
 
     assert!(next.is_none());
 }
 
+#[test]
+fn split_fill_percent() {
+    let tx = tx_mock();
+    let bucket = bucket_mock(WeakTx::from(&tx));
+    let mut n = node_mock(&bucket);
+    n.0.is_leaf.store(true, Ordering::Release);
+    for i in 1..1000 {
+        let key = format!("{:08}", i);
+        n.put(
+            &key.as_bytes(),
+            &key.as_bytes(),
+            b"0123456701234567".to_vec(),
+            0,
+            0,
+        );
+    }
+
+    // node's fill percent takes precedence over bucket's one
+    n.0.fill_percent.set(Some(1.0));
+    let parent = n.split(4096).unwrap().unwrap();
+
+    assert_eq!(parent.0.children.borrow().len(), 10);
+}
+
 #[test]
 fn split() {
     let tx = tx_mock();
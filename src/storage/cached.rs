@@ -0,0 +1,262 @@
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::errors::Error;
+
+use super::memory::Block;
+use super::mmap::read_exact_at;
+use super::{Mapping, MmapStorage, Storage};
+
+/// Cached page bytes
+struct Entry {
+    block: Block,
+    /// tick of the last read
+    used: u64,
+}
+
+/// Bytes read from file, shared by storage and its mappings.
+///
+/// Entries are dropped only on release, since bytes handed out
+/// by mapping stay in use until then.
+struct PageCache {
+    /// cached bytes by file offset
+    entries: BTreeMap<u64, Entry>,
+    /// entries replaced by longer reads, kept until release
+    retired: Vec<Block>,
+    /// count of cached bytes
+    size: usize,
+    /// length of the longest entry, bounds search of entries overlapping a write
+    max_len: usize,
+    /// count of reads, orders entries by use
+    tick: u64,
+}
+
+impl PageCache {
+    fn new() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            retired: Vec::new(),
+            size: 0,
+            max_len: 0,
+            tick: 0,
+        }
+    }
+
+    /// Returns len bytes from offset, reading them from file if not cached
+    fn read(&mut self, file: &File, offset: u64, len: usize) -> Result<&[u8], Error> {
+        self.tick += 1;
+        let cached = match self.entries.get(&offset) {
+            Some(entry) => entry.block.as_slice().len() >= len,
+            None => false,
+        };
+        if !cached {
+            let mut data = vec![0u8; len];
+            read_exact_at(file, offset, &mut data)?;
+            let entry = Entry {
+                block: Block::from_vec(data),
+                used: 0,
+            };
+            if let Some(old) = self.entries.insert(offset, entry) {
+                self.size -= old.block.as_slice().len();
+                self.retired.push(old.block);
+            }
+            self.size += len;
+            self.max_len = usize::max(self.max_len, len);
+        }
+        let entry = self.entries.get_mut(&offset).unwrap();
+        entry.used = self.tick;
+        Ok(&entry.block.as_slice()[..len])
+    }
+
+    /// Copies written bytes into every entry they overlap
+    fn write(&self, offset: u64, buf: &[u8]) {
+        let end = offset + buf.len() as u64;
+        let start = offset.saturating_sub(self.max_len as u64);
+        for (&pos, entry) in self.entries.range(start..end) {
+            let entry_end = pos + entry.block.as_slice().len() as u64;
+            if entry_end <= offset {
+                continue;
+            }
+            let from = u64::max(pos, offset);
+            let to = u64::min(entry_end, end);
+            entry.block.write(
+                (from - pos) as usize,
+                &buf[(from - offset) as usize..(to - offset) as usize],
+            );
+        }
+    }
+
+    /// Drops entries reaching past size
+    fn truncate(&mut self, size: u64) {
+        let dropped: Vec<u64> = self
+            .entries
+            .iter()
+            .filter(|(&pos, entry)| pos + entry.block.as_slice().len() as u64 > size)
+            .map(|(&pos, _)| pos)
+            .collect();
+        for pos in dropped {
+            let entry = self.entries.remove(&pos).unwrap();
+            self.size -= entry.block.as_slice().len();
+        }
+    }
+
+    /// Drops least recently read entries until at most capacity bytes are cached
+    fn release(&mut self, capacity: usize) {
+        self.retired.clear();
+        if self.size <= capacity {
+            return;
+        }
+        let mut entries: Vec<(u64, u64)> = self
+            .entries
+            .iter()
+            .map(|(&pos, entry)| (entry.used, pos))
+            .collect();
+        entries.sort_unstable();
+        for (_, pos) in entries {
+            if self.size <= capacity {
+                break;
+            }
+            let entry = self.entries.remove(&pos).unwrap();
+            self.size -= entry.block.as_slice().len();
+        }
+    }
+}
+
+struct CachedMapping {
+    file: Arc<File>,
+    cache: Arc<Mutex<PageCache>>,
+    capacity: usize,
+    len: usize,
+}
+
+impl Mapping for CachedMapping {
+    fn size(&self) -> usize {
+        self.len
+    }
+
+    fn read(&self, offset: usize, len: usize) -> &[u8] {
+        assert!(offset + len <= self.len, "read out of mapping bounds");
+        let mut cache = self.cache.lock();
+        let bytes = match cache.read(&self.file, offset as u64, len) {
+            Ok(bytes) => bytes as *const [u8],
+            // same as a fault on memory mapped page
+            Err(e) => panic!("can't read {} bytes at {}: {}", len, offset, e),
+        };
+        // Entry is kept by the cache until release.
+        unsafe { &*bytes }
+    }
+
+    fn release(&self) {
+        self.cache.lock().release(self.capacity);
+    }
+}
+
+/// File storage which reads pages with positioned reads instead of memory map.
+///
+/// Read pages are kept in a heap cache, which is trimmed to cache size
+/// whenever no transaction is open, and writes update cached copies.
+/// Meant for platforms and filesystems where mmap is unavailable.
+///
+/// Failed page read panics, as a fault on memory mapped page would crash.
+pub struct CachedFileStorage {
+    file: MmapStorage,
+    cache: Arc<Mutex<PageCache>>,
+    capacity: usize,
+}
+
+impl CachedFileStorage {
+    /// Default size of page cache in bytes
+    pub const DEFAULT_CACHE_SIZE: usize = 64 * 1024 * 1024;
+
+    /// Opens file at given path, creating it unless read_only is set
+    pub fn open<P: AsRef<Path>>(path: P, read_only: bool) -> Result<Self, Error> {
+        Ok(Self::from_storage(MmapStorage::open(path, read_only)?))
+    }
+
+    /// Creates storage over already opened file
+    pub fn from_file(file: File) -> Self {
+        Self::from_storage(MmapStorage::from_file(file))
+    }
+
+    fn from_storage(file: MmapStorage) -> Self {
+        Self {
+            file,
+            cache: Arc::new(Mutex::new(PageCache::new())),
+            capacity: Self::DEFAULT_CACHE_SIZE,
+        }
+    }
+
+    /// Sets size of page cache in bytes.
+    ///
+    /// Cache is trimmed to it once no transaction is open,
+    /// so it grows past it while transactions overlap.
+    pub fn cache_size(mut self, size: usize) -> Self {
+        self.capacity = size;
+        self
+    }
+
+    /// Returns count of cached bytes
+    #[cfg(test)]
+    pub(super) fn cached(&self) -> usize {
+        self.cache.lock().size
+    }
+}
+
+impl Storage for CachedFileStorage {
+    fn size(&self) -> Result<u64, Error> {
+        self.file.size()
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<(), Error> {
+        self.file.read_at(offset, buf)
+    }
+
+    fn write_at(&mut self, offset: u64, buf: &[u8]) -> Result<(), Error> {
+        self.file.write_at(offset, buf)?;
+        self.cache.lock().write(offset, buf);
+        Ok(())
+    }
+
+    fn grow(&mut self, size: u64) -> Result<(), Error> {
+        self.file.grow(size)
+    }
+
+    fn truncate(&mut self, size: u64) -> Result<(), Error> {
+        self.file.truncate(size)?;
+        self.cache.lock().truncate(size);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.file.flush()
+    }
+
+    fn sync(&mut self) -> Result<(), Error> {
+        self.file.sync()
+    }
+
+    fn try_lock(&self, exclusive: bool) -> Result<(), Error> {
+        self.file.try_lock(exclusive)
+    }
+
+    fn unlock(&self) -> Result<(), Error> {
+        self.file.unlock()
+    }
+
+    fn map(&mut self, len: usize) -> Result<Box<dyn Mapping>, Error> {
+        // Pages of previous mapping aren't in use.
+        let mut cache = self.cache.lock();
+        cache.truncate(len as u64);
+        cache.release(self.capacity);
+        drop(cache);
+        Ok(Box::new(CachedMapping {
+            file: self.file.file(),
+            cache: self.cache.clone(),
+            capacity: self.capacity,
+            len,
+        }))
+    }
+}
@@ -0,0 +1,199 @@
+use std::io;
+use std::ptr;
+use std::slice;
+use std::sync::Arc;
+
+use crate::errors::Error;
+
+use super::{Mapping, Storage};
+
+/// Heap block of fixed size shared by storage and its mappings.
+///
+/// Block is never resized, so mapped bytes keep their address.
+/// Storage changes bytes only through the raw pointer and only outside
+/// of pages being read, same as file writes change memory mapped pages.
+pub(super) struct Block {
+    ptr: *mut u8,
+    len: usize,
+}
+
+unsafe impl Send for Block {}
+unsafe impl Sync for Block {}
+
+impl Block {
+    pub(super) fn from_vec(data: Vec<u8>) -> Self {
+        let len = data.len();
+        let ptr = Box::into_raw(data.into_boxed_slice()) as *mut u8;
+        Self { ptr, len }
+    }
+
+    pub(super) fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+
+    /// Copies buf into block at offset
+    pub(super) fn write(&self, offset: usize, buf: &[u8]) {
+        assert!(offset + buf.len() <= self.len, "write out of block bounds");
+        unsafe { ptr::copy_nonoverlapping(buf.as_ptr(), self.ptr.add(offset), buf.len()) }
+    }
+
+    /// Zeroes bytes from start to end
+    fn clear(&self, start: usize, end: usize) {
+        assert!(start <= end && end <= self.len, "clear out of block bounds");
+        unsafe { ptr::write_bytes(self.ptr.add(start), 0, end - start) }
+    }
+}
+
+impl Drop for Block {
+    fn drop(&mut self) {
+        unsafe {
+            drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+                self.ptr, self.len,
+            )))
+        }
+    }
+}
+
+struct MemoryMapping {
+    block: Arc<Block>,
+    len: usize,
+}
+
+impl Mapping for MemoryMapping {
+    fn size(&self) -> usize {
+        self.len
+    }
+
+    fn read(&self, offset: usize, len: usize) -> &[u8] {
+        &self.block.as_slice()[..self.len][offset..offset + len]
+    }
+}
+
+/// Storage which keeps all data in heap memory.
+///
+/// Data is lost once storage is dropped.
+pub struct MemoryStorage {
+    /// first bytes of data, reallocated only by map()
+    block: Arc<Block>,
+    /// data past the block
+    tail: Vec<u8>,
+    /// size of data
+    len: usize,
+}
+
+impl MemoryStorage {
+    /// Creates empty storage
+    pub fn new() -> Self {
+        Self::from_vec(Vec::new())
+    }
+
+    /// Creates storage holding given data
+    pub fn from_vec(data: Vec<u8>) -> Self {
+        Self {
+            len: data.len(),
+            block: Arc::new(Block::from_vec(data)),
+            tail: Vec::new(),
+        }
+    }
+}
+
+impl Default for MemoryStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn size(&self) -> Result<u64, Error> {
+        Ok(self.len as u64)
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<(), Error> {
+        let start = offset as usize;
+        let end = start + buf.len();
+        if end > self.len {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        let split = usize::min(usize::max(start, self.block.len), end);
+        if start < split {
+            buf[..split - start].copy_from_slice(&self.block.as_slice()[start..split]);
+        }
+        if split < end {
+            let tail_start = split - self.block.len;
+            buf[split - start..].copy_from_slice(&self.tail[tail_start..end - self.block.len]);
+        }
+        Ok(())
+    }
+
+    fn write_at(&mut self, offset: u64, buf: &[u8]) -> Result<(), Error> {
+        let start = offset as usize;
+        let end = start + buf.len();
+        self.grow(end as u64)?;
+        let split = usize::min(usize::max(start, self.block.len), end);
+        if start < split {
+            self.block.write(start, &buf[..split - start]);
+        }
+        if split < end {
+            let tail_start = split - self.block.len;
+            self.tail[tail_start..end - self.block.len].copy_from_slice(&buf[split - start..]);
+        }
+        Ok(())
+    }
+
+    fn grow(&mut self, size: u64) -> Result<(), Error> {
+        let size = size as usize;
+        if size <= self.len {
+            return Ok(());
+        }
+        // Bytes past the end are not mapped, and may be left by truncate.
+        let block_len = self.block.len;
+        if self.len < block_len {
+            self.block.clear(self.len, usize::min(size, block_len));
+        }
+        if size > block_len {
+            self.tail.resize(size - block_len, 0);
+        }
+        self.len = size;
+        Ok(())
+    }
+
+    fn truncate(&mut self, size: u64) -> Result<(), Error> {
+        let size = size as usize;
+        if size < self.len {
+            self.tail.truncate(size.saturating_sub(self.block.len));
+            self.len = size;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn try_lock(&self, _exclusive: bool) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn unlock(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn map(&mut self, len: usize) -> Result<Box<dyn Mapping>, Error> {
+        self.grow(len as u64)?;
+        // Previous mapping is not in use, it keeps old block alive until dropped.
+        if len > self.block.len {
+            let mut data = Vec::with_capacity(self.len);
+            data.extend_from_slice(&self.block.as_slice()[..usize::min(self.len, self.block.len)]);
+            data.append(&mut self.tail);
+            self.block = Arc::new(Block::from_vec(data));
+        }
+        Ok(Box::new(MemoryMapping {
+            block: self.block.clone(),
+            len,
+        }))
+    }
+}
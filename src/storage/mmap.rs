@@ -0,0 +1,160 @@
+use fs2::FileExt;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::errors::Error;
+
+use super::{Mapping, Storage};
+
+impl Mapping for memmap::Mmap {
+    fn size(&self) -> usize {
+        self.len()
+    }
+
+    fn read(&self, offset: usize, len: usize) -> &[u8] {
+        &self[offset..offset + len]
+    }
+}
+
+/// Reads exactly buf.len() bytes of file starting from offset, without moving file cursor
+#[cfg(unix)]
+pub(super) fn read_exact_at(file: &File, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+/// Reads exactly buf.len() bytes of file starting from offset
+#[cfg(windows)]
+pub(super) fn read_exact_at(file: &File, mut offset: u64, mut buf: &mut [u8]) -> io::Result<()> {
+    while !buf.is_empty() {
+        match std::os::windows::fs::FileExt::seek_read(file, buf, offset) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Writes whole buf to file starting from offset, without moving file cursor
+#[cfg(unix)]
+fn write_all_at(file: &File, offset: u64, buf: &[u8]) -> io::Result<()> {
+    std::os::unix::fs::FileExt::write_all_at(file, buf, offset)
+}
+
+/// Writes whole buf to file starting from offset
+#[cfg(windows)]
+fn write_all_at(file: &File, mut offset: u64, mut buf: &[u8]) -> io::Result<()> {
+    while !buf.is_empty() {
+        match std::os::windows::fs::FileExt::seek_write(file, buf, offset) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => {
+                buf = &buf[n..];
+                offset += n as u64;
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// File storage which reads pages through memory map.
+///
+/// Writes go straight to the file with positioned writes.
+pub struct MmapStorage {
+    file: Arc<File>,
+}
+
+impl MmapStorage {
+    /// Opens file at given path, creating it unless read_only is set
+    pub fn open<P: AsRef<Path>>(path: P, read_only: bool) -> Result<Self, Error> {
+        let mut open_opts = OpenOptions::new();
+        open_opts.read(true);
+        if !read_only {
+            open_opts.write(true).create(true);
+        };
+        let file = open_opts.open(path)?;
+        Ok(Self::from_file(file))
+    }
+
+    /// Creates storage over already opened file
+    pub fn from_file(file: File) -> Self {
+        Self {
+            file: Arc::new(file),
+        }
+    }
+
+    /// Returns the file shared with mappings reading it
+    pub(super) fn file(&self) -> Arc<File> {
+        self.file.clone()
+    }
+}
+
+impl Storage for MmapStorage {
+    fn size(&self) -> Result<u64, Error> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<(), Error> {
+        Ok(read_exact_at(&self.file, offset, buf)?)
+    }
+
+    fn write_at(&mut self, offset: u64, buf: &[u8]) -> Result<(), Error> {
+        Ok(write_all_at(&self.file, offset, buf)?)
+    }
+
+    fn grow(&mut self, size: u64) -> Result<(), Error> {
+        Ok(FileExt::allocate(&*self.file, size)?)
+    }
+
+    fn truncate(&mut self, size: u64) -> Result<(), Error> {
+        Ok(self.file.set_len(size)?)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        // Writes aren't buffered.
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<(), Error> {
+        Ok(self.file.sync_data()?)
+    }
+
+    fn try_lock(&self, exclusive: bool) -> Result<(), Error> {
+        let file = &*self.file;
+        if exclusive {
+            FileExt::try_lock_exclusive(file)
+        } else {
+            FileExt::try_lock_shared(file)
+        }
+        .map_err(|e| {
+            if e.kind() == fs2::lock_contended_error().kind() {
+                Error::FileLocked(Arc::new(e))
+            } else {
+                Error::Lock(Arc::new(e))
+            }
+        })
+    }
+
+    fn unlock(&self) -> Result<(), Error> {
+        FileExt::unlock(&*self.file).map_err(|e| Error::Lock(Arc::new(e)))
+    }
+
+    fn map(&mut self, len: usize) -> Result<Box<dyn Mapping>, Error> {
+        // TODO: madvise
+        let mmap = unsafe {
+            memmap::MmapOptions::new()
+                .offset(0)
+                .len(len)
+                .map(&self.file)
+                .map_err(|e| Error::Mmap(Arc::new(e)))?
+        };
+        Ok(Box::new(mmap))
+    }
+}
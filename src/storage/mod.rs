@@ -0,0 +1,71 @@
+#[cfg(test)]
+mod tests;
+
+mod cached;
+mod memory;
+mod mmap;
+
+use crate::errors::Error;
+
+pub use cached::CachedFileStorage;
+pub use memory::MemoryStorage;
+pub use mmap::MmapStorage;
+
+/// Read-only view of the first bytes of storage.
+///
+/// Pages are read directly from returned bytes, so they must stay valid
+/// and keep their address until the mapping is dropped or released.
+pub trait Mapping: Send + Sync {
+    /// Returns count of mapped bytes
+    fn size(&self) -> usize;
+
+    /// Returns len mapped bytes starting from offset
+    fn read(&self, offset: usize, len: usize) -> &[u8];
+
+    /// Called when no bytes returned by read() are in use,
+    /// lets mapping drop copies it keeps in memory.
+    fn release(&self) {}
+}
+
+/// Backend which holds database data.
+///
+/// Database reads pages through the mapping returned by map()
+/// and writes them with write_at(). Writes within mapped range
+/// must be visible through the current mapping.
+pub trait Storage: Send + Sync {
+    /// Returns size of storage in bytes
+    fn size(&self) -> Result<u64, Error>;
+
+    /// Reads exactly buf.len() bytes starting from offset
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<(), Error>;
+
+    /// Writes whole buf starting from offset
+    fn write_at(&mut self, offset: u64, buf: &[u8]) -> Result<(), Error>;
+
+    /// Extends storage to be at least size bytes
+    fn grow(&mut self, size: u64) -> Result<(), Error>;
+
+    /// Shrinks storage to size bytes.
+    ///
+    /// Current mapping may be longer, but its bytes past size
+    /// aren't read until storage grows again.
+    fn truncate(&mut self, size: u64) -> Result<(), Error>;
+
+    /// Flushes buffered writes without waiting for them to reach stable storage
+    fn flush(&mut self) -> Result<(), Error>;
+
+    /// Flushes buffered writes and waits until they reach stable storage
+    fn sync(&mut self) -> Result<(), Error>;
+
+    /// Tries to lock storage exclusively or shared,
+    /// returns error if it is already locked by someone else
+    fn try_lock(&self, exclusive: bool) -> Result<(), Error>;
+
+    /// Releases lock acquired by try_lock
+    fn unlock(&self) -> Result<(), Error>;
+
+    /// Maps first len bytes of storage.
+    ///
+    /// Called only when no pages of previous mapping are in use.
+    fn map(&mut self, len: usize) -> Result<Box<dyn Mapping>, Error>;
+}
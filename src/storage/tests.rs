@@ -0,0 +1,184 @@
+use super::{CachedFileStorage, MemoryStorage, Storage};
+use crate::db::{CheckMode, DBBuilder};
+use crate::errors::Error;
+use crate::test_utils::temp_file;
+
+#[test]
+fn memory_write_read() {
+    let mut storage = MemoryStorage::new();
+    assert_eq!(storage.size().unwrap(), 0);
+
+    storage.write_at(4, b"nut").unwrap();
+    assert_eq!(storage.size().unwrap(), 7);
+
+    let mut buf = [0u8; 7];
+    storage.read_at(0, &mut buf).unwrap();
+    assert_eq!(&buf, b"\0\0\0\0nut");
+    assert!(storage.read_at(5, &mut buf).is_err());
+
+    storage.grow(16).unwrap();
+    assert_eq!(storage.size().unwrap(), 16);
+
+    storage.truncate(5).unwrap();
+    assert_eq!(storage.size().unwrap(), 5);
+    storage.read_at(0, &mut buf[..5]).unwrap();
+    assert_eq!(&buf[..5], b"\0\0\0\0n");
+}
+
+#[test]
+fn memory_mapping_sees_writes() {
+    let mut storage = MemoryStorage::new();
+    let mapping = storage.map(8).unwrap();
+    assert_eq!(mapping.read(0, mapping.size()), &[0u8; 8]);
+
+    storage.write_at(2, b"bolt").unwrap();
+    assert_eq!(&mapping.read(0, mapping.size())[2..6], b"bolt");
+
+    // Growing past mapped range doesn't move mapped bytes.
+    let ptr = mapping.read(0, 8).as_ptr();
+    storage.write_at(64, b"tail").unwrap();
+    storage.write_at(6, b"db").unwrap();
+    assert_eq!(mapping.read(0, 8).as_ptr(), ptr);
+    assert_eq!(mapping.read(0, mapping.size()), b"\0\0boltdb");
+
+    let mut buf = [0u8; 8];
+    storage.read_at(62, &mut buf[..6]).unwrap();
+    assert_eq!(&buf[..6], b"\0\0tail");
+
+    let mapping = storage.map(68).unwrap();
+    assert_eq!(&mapping.read(0, mapping.size())[2..8], b"boltdb");
+    assert_eq!(&mapping.read(0, mapping.size())[64..], b"tail");
+    drop(mapping);
+
+    // Truncated bytes are zeroed when storage grows again.
+    storage.truncate(4).unwrap();
+    storage.grow(8).unwrap();
+    storage.read_at(0, &mut buf).unwrap();
+    assert_eq!(&buf, b"\0\0bo\0\0\0\0");
+}
+
+#[test]
+fn cached_file_mapping_sees_writes() {
+    let path = temp_file();
+    {
+        let mut storage = CachedFileStorage::open(&path, false).unwrap();
+        storage.write_at(0, b"nut").unwrap();
+        storage.grow(4).unwrap();
+
+        let mapping = storage.map(4).unwrap();
+        assert_eq!(mapping.read(1, 2), b"ut");
+        assert_eq!(mapping.read(0, mapping.size()), b"nut\0");
+
+        // write crossing end of cached pages
+        storage.write_at(2, b"db").unwrap();
+        assert_eq!(mapping.read(0, mapping.size()), b"nudb");
+        assert_eq!(mapping.read(1, 2), b"ud");
+        storage.flush().unwrap();
+    }
+    assert_eq!(std::fs::read(&path).unwrap(), b"nudb");
+    {
+        let mut storage = CachedFileStorage::open(&path, false).unwrap();
+        let mapping = storage.map(2).unwrap();
+        storage.truncate(2).unwrap();
+        assert_eq!(storage.size().unwrap(), 2);
+        assert_eq!(mapping.read(0, mapping.size()), b"nu");
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn cached_file_release() {
+    let path = temp_file();
+    let mut storage = CachedFileStorage::open(&path, false).unwrap().cache_size(8);
+    storage.write_at(0, &[1u8; 32]).unwrap();
+
+    let mapping = storage.map(32).unwrap();
+    let first = mapping.read(0, 8).as_ptr();
+    for i in 0..4 {
+        assert_eq!(mapping.read(i * 8, 8), &[1u8; 8]);
+    }
+    // pages in use stay until released
+    assert_eq!(storage.cached(), 32);
+    assert_eq!(mapping.read(0, 8).as_ptr(), first);
+
+    // least recently read pages are dropped first
+    mapping.read(8, 8);
+    mapping.release();
+    assert_eq!(storage.cached(), 8);
+    storage.write_at(8, &[2u8; 8]).unwrap();
+    assert_eq!(mapping.read(0, 16), &[[1u8; 8], [2u8; 8]].concat()[..]);
+
+    drop(mapping);
+    drop(storage);
+    std::fs::remove_file(&path).unwrap();
+}
+
+fn fill(db: &mut crate::DB) {
+    db.update(|tx| -> Result<(), Error> {
+        let mut bucket = tx.create_bucket_if_not_exists(b"bucket").unwrap();
+        for i in 0..1000 {
+            bucket
+                .put(format!("key{}", i).as_bytes(), vec![(i % 256) as u8; 64])
+                .unwrap();
+        }
+        Ok(())
+    })
+    .unwrap();
+}
+
+fn verify(db: &crate::DB) {
+    db.view(|tx| -> Result<(), Error> {
+        let bucket = tx.bucket(b"bucket").unwrap();
+        for i in 0..1000 {
+            let value = bucket.get(format!("key{}", i).as_bytes()).unwrap();
+            assert_eq!(value, &vec![(i % 256) as u8; 64][..]);
+        }
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn db_on_memory() {
+    let path = temp_file();
+    let mut db = DBBuilder::new(&path)
+        .storage(MemoryStorage::new())
+        .checkmode(CheckMode::PARANOID)
+        .build()
+        .unwrap();
+    fill(&mut db);
+    verify(&db);
+    assert!(!path.exists());
+}
+
+#[test]
+fn db_on_cached_file() {
+    let path = temp_file();
+    {
+        let mut db = DBBuilder::new(&path)
+            .storage(
+                CachedFileStorage::open(&path, false)
+                    .unwrap()
+                    .cache_size(16 * 1024),
+            )
+            .checkmode(CheckMode::PARANOID)
+            .build()
+            .unwrap();
+        fill(&mut db);
+        verify(&db);
+    }
+    {
+        let db = DBBuilder::new(&path)
+            .storage(CachedFileStorage::open(&path, true).unwrap())
+            .read_only(true)
+            .build()
+            .unwrap();
+        verify(&db);
+    }
+    {
+        // file format is the same as for memory mapped one
+        let db = DBBuilder::new(&path).autoremove(true).build().unwrap();
+        verify(&db);
+    }
+    assert!(!path.exists());
+}
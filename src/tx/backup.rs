@@ -0,0 +1,188 @@
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::consts::Flags;
+use crate::errors::Error;
+use crate::page::OwnedPage;
+
+use super::Tx;
+
+/// bytes copied between progress reports, throttling and cancellation checks
+const CHUNK_SIZE: u64 = 1 << 20;
+
+/// Copy of the database made from a transaction
+///
+/// Created by Tx.backup(). Data is copied in chunks, and storage is locked
+/// only while a chunk is read, so writers aren't blocked by slow targets
+/// or throttling. Backup contains only pages up to the transaction's
+/// high water mark, with both meta pages pointing to its snapshot.
+pub struct Backup<'a> {
+    tx: &'a Tx,
+    rate: u64,
+    progress: Option<Box<dyn FnMut(u64, u64) + 'a>>,
+    cancel: Option<Arc<AtomicBool>>,
+    sync: bool,
+}
+
+impl<'a> Backup<'a> {
+    pub(super) fn new(tx: &'a Tx) -> Self {
+        Self {
+            tx,
+            rate: 0,
+            progress: None,
+            cancel: None,
+            sync: false,
+        }
+    }
+
+    /// Limits copy speed to given number of bytes per second, 0 disables the limit.
+    ///
+    /// Default: 0
+    pub fn rate_limit(mut self, bytes_per_sec: u64) -> Self {
+        self.rate = bytes_per_sec;
+        self
+    }
+
+    /// Calls handler with count of copied pages and total count of pages
+    /// after meta pages and after every copied chunk.
+    pub fn progress<F: FnMut(u64, u64) + 'a>(mut self, handler: F) -> Self {
+        self.progress = Some(Box::new(handler));
+        self
+    }
+
+    /// Stops backup with Error::BackupCancelled once flag is set.
+    pub fn cancel(mut self, flag: Arc<AtomicBool>) -> Self {
+        self.cancel = Some(flag);
+        self
+    }
+
+    /// Syncs backup file to disk before copy_to returns.
+    /// Writers passed to write_to are only flushed.
+    ///
+    /// Default: false
+    pub fn sync(mut self, v: bool) -> Self {
+        self.sync = v;
+        self
+    }
+
+    /// Writes backup to the writer, returns number of bytes written.
+    pub fn write_to<W: Write>(mut self, mut w: W) -> Result<u64, Error> {
+        let db = self.tx.db()?;
+        let page_size = db.page_size() as u64;
+        let total = self.tx.pgid();
+        let start = Instant::now();
+
+        let mut page = OwnedPage::new(page_size as usize);
+        page.flags = Flags::META;
+
+        // first page
+        {
+            *page.meta_mut() = self.tx.0.meta.try_read().unwrap().clone();
+            page.meta_mut().checksum = page.meta().sum64();
+            w.write_all(page.buf())?;
+        }
+
+        // second page
+        {
+            page.id = 1;
+            page.meta_mut().txid -= 1;
+            page.meta_mut().checksum = page.meta().sum64();
+            w.write_all(page.buf())?;
+        }
+
+        let mut copied = 2;
+        self.report(copied, total);
+
+        // Smaller chunks keep throttled copy smooth.
+        let chunk_size = match self.rate {
+            0 => CHUNK_SIZE,
+            rate => u64::min(rate, CHUNK_SIZE),
+        };
+        let chunk = u64::max(chunk_size / page_size, 1);
+        let mut buf = vec![0u8; (chunk * page_size) as usize];
+        while copied < total {
+            if self.cancelled() {
+                return Err(Error::BackupCancelled);
+            }
+
+            let n = u64::min(chunk, total - copied);
+            let buf = &mut buf[..(n * page_size) as usize];
+            db.0.storage.read().read_at(copied * page_size, buf)?;
+            w.write_all(buf)?;
+            copied += n;
+
+            self.report(copied, total);
+            self.throttle(start, copied * page_size);
+        }
+        w.flush()?;
+
+        Ok(copied * page_size)
+    }
+
+    /// Writes backup to file at given path, replacing existing file.
+    /// Returns number of bytes written.
+    ///
+    /// Backup is written to a temporary file next to the target, which replaces
+    /// the target once complete. If backup fails or is cancelled, temporary file
+    /// is removed and existing file is left untouched.
+    pub fn copy_to<P: AsRef<Path>>(self, path: P) -> Result<u64, Error> {
+        let path = path.as_ref();
+        if let Some(db_path) = self.tx.db()?.path() {
+            if path.exists() && fs::canonicalize(path)? == fs::canonicalize(db_path)? {
+                return Err("can't back up database into its own file".into());
+            }
+        }
+        let tmp_path = temp_path(path)?;
+        let sync = self.sync;
+        let mut file = File::create(&tmp_path)?;
+        let result = self.write_to(&mut file).and_then(|written| {
+            if sync {
+                file.sync_all()?;
+            }
+            drop(file);
+            fs::rename(&tmp_path, path)?;
+            Ok(written)
+        });
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        result
+    }
+
+    fn report(&mut self, copied: u64, total: u64) {
+        if let Some(progress) = self.progress.as_mut() {
+            progress(copied, total);
+        }
+    }
+
+    fn cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .is_some_and(|flag| flag.load(Ordering::Acquire))
+    }
+
+    /// Sleeps until written bytes fit into the rate limit
+    fn throttle(&self, start: Instant, written: u64) {
+        if self.rate == 0 {
+            return;
+        }
+        let expected = Duration::from_secs_f64(written as f64 / self.rate as f64);
+        let elapsed = start.elapsed();
+        if expected > elapsed {
+            thread::sleep(expected - elapsed);
+        }
+    }
+}
+
+/// Returns path of temporary file written by copy_to or compact before it replaces the target
+pub(crate) fn temp_path(path: &Path) -> Result<PathBuf, Error> {
+    let mut name = OsString::from(path.file_name().ok_or("path has no file name")?);
+    name.push(".tmp");
+    Ok(path.with_file_name(name))
+}
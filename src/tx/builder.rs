@@ -1,6 +1,6 @@
 use parking_lot::{Mutex, RwLock};
 use std::collections::HashMap;
-use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicBool, AtomicU64};
 use std::sync::Arc;
 
 use crate::bucket::Bucket;
@@ -52,11 +52,14 @@ impl TxBuilder {
             writable: self.writable,
             managed: AtomicBool::new(false),
             check: AtomicBool::new(self.check),
+            shrink: AtomicBool::new(false),
             db: RwLock::new(self.db),
             meta: RwLock::new(meta),
             root: RwLock::new(Bucket::new(WeakTx::new())),
             pages: RwLock::new(HashMap::new()),
             stats: Mutex::new(Default::default()),
+            savepoints: Mutex::new(Vec::new()),
+            savepoint_seq: AtomicU64::new(0),
             commit_handlers: Mutex::new(Vec::new()),
             write_flag: 0,
         }));
@@ -1,11 +1,16 @@
 #[cfg(test)]
 pub mod tests;
 
+mod backup;
 mod builder;
+mod savepoint;
 mod stats;
 mod tx;
 
+pub(crate) use backup::temp_path;
+pub use backup::Backup;
 pub(crate) use builder::TxBuilder;
+pub use savepoint::Savepoint;
 pub use stats::TxStats;
 pub use tx::Tx;
 pub(crate) use tx::{TxInner, WeakTx};
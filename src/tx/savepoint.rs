@@ -0,0 +1,112 @@
+use std::collections::HashSet;
+use std::sync::atomic::Ordering;
+
+use crate::consts::PGID;
+use crate::errors::Error;
+use crate::meta::Meta;
+
+use super::{Tx, WeakTx};
+
+/// Savepoint inside of writable transaction
+///
+/// Created by Tx.savepoint(). Changes made after savepoint can be undone
+/// with rollback_to, while the rest of transaction stays intact.
+///
+/// Savepoints can be nested. Rolling back to or releasing a savepoint
+/// also releases all savepoints created after it.
+/// Dropping savepoint releases it.
+pub struct Savepoint {
+    /// the associated transaction
+    tx: WeakTx,
+
+    /// savepoint id within transaction
+    id: u64,
+
+    /// transaction meta at savepoint
+    meta: Meta,
+
+    /// dirty pages allocated before savepoint
+    pages: HashSet<PGID>,
+
+    /// count of pages freed by transaction before savepoint
+    pending: usize,
+}
+
+impl Savepoint {
+    pub(super) fn new(tx: &Tx) -> Result<Self, Error> {
+        let db = tx.db()?;
+        let id = tx.0.savepoint_seq.fetch_add(1, Ordering::AcqRel);
+        tx.0.savepoints.lock().push(id);
+        let meta = tx.0.meta.try_read().unwrap().clone();
+        let pages = tx.0.pages.try_read().unwrap().keys().cloned().collect();
+        let pending = db.0.freelist.read().pending_len(meta.txid);
+
+        Ok(Self {
+            tx: WeakTx::from(tx),
+            id,
+            meta,
+            pages,
+            pending,
+        })
+    }
+
+    /// Undoes all changes made in transaction since savepoint was created.
+    pub fn rollback_to(self) -> Result<(), Error> {
+        let tx = self.tx()?;
+        let db = tx.db()?;
+
+        // Drop dirty pages allocated after savepoint and return the ones
+        // taken from the freelist, rest goes back with high water mark.
+        let mut restored = Vec::new();
+        tx.0.pages.try_write().unwrap().retain(|id, page| {
+            if self.pages.contains(id) {
+                return true;
+            }
+            if *id < self.meta.pgid {
+                restored.extend(*id..=*id + page.overflow as PGID);
+            }
+            false
+        });
+
+        {
+            let mut freelist = db.0.freelist.write();
+            freelist.rollback_pending(self.meta.txid, self.pending);
+            freelist.restore(&restored);
+        }
+
+        *tx.0.meta.try_write().unwrap() = self.meta.clone();
+
+        let mut root = tx.0.root.try_write().unwrap();
+        root.clear();
+        root.bucket = self.meta.root.clone();
+        Ok(())
+    }
+
+    /// Keeps changes made since savepoint and forgets it.
+    pub fn release(self) -> Result<(), Error> {
+        self.tx()?;
+        Ok(())
+    }
+
+    fn tx(&self) -> Result<Tx, Error> {
+        let tx = self.tx.upgrade().ok_or(Error::TxGone)?;
+        if !tx.opened() {
+            return Err(Error::TxClosed);
+        }
+        if !tx.0.savepoints.lock().contains(&self.id) {
+            return Err(Error::SavepointGone);
+        }
+        Ok(tx)
+    }
+}
+
+impl Drop for Savepoint {
+    fn drop(&mut self) {
+        if let Some(tx) = self.tx.upgrade() {
+            let mut savepoints = tx.0.savepoints.lock();
+            if let Some(index) = savepoints.iter().position(|id| *id == self.id) {
+                savepoints.truncate(index);
+            }
+        }
+    }
+}
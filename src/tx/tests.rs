@@ -1,11 +1,13 @@
 use super::{Tx, TxBuilder};
 use crate::db::tests::db_mock;
-use crate::db::DBBuilder;
+use crate::db::{CheckMode, DBBuilder};
 use crate::errors::Error;
+use crate::test_utils::temp_file;
 use fnv::FnvHasher;
 use std::hash::Hasher;
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 pub(crate) fn tx_mock() -> Tx {
     let tx = TxBuilder::new().writable(true).build();
@@ -15,7 +17,7 @@ pub(crate) fn tx_mock() -> Tx {
 
 #[test]
 fn commit_empty() {
-    let mut db = db_mock().build().unwrap();
+    let db = db_mock().build().unwrap();
     assert!(db.0.rw_tx.try_read().unwrap().is_none());
 
     {
@@ -25,17 +27,17 @@ fn commit_empty() {
     }
     assert!(db.0.rw_tx.try_read().unwrap().is_none());
 
-    db.view(Box::new(|tx| {
+    db.view(|tx| -> Result<(), Error> {
         assert_eq!(tx.db().unwrap().0.txs.try_read().unwrap().len(), 1);
         Ok(())
-    }))
+    })
     .unwrap();
     assert_eq!(db.0.txs.try_read().unwrap().len(), 0);
 }
 
 #[test]
 fn commit_some() {
-    let mut db = db_mock().build().unwrap();
+    let db = db_mock().build().unwrap();
     let mut tx = db.begin_rw_tx().unwrap();
     {
         let mut bucket = tx.create_bucket(b"bucket").unwrap();
@@ -48,7 +50,7 @@ fn commit_some() {
 fn commit_multiple() {
     let n_commits = 5;
     let n_values = 1000;
-    let mut db = db_mock().build().unwrap();
+    let db = db_mock().build().unwrap();
     for i in 0..n_commits {
         let mut tx = db.begin_rw_tx().unwrap();
         let mut bucket = tx.create_bucket_if_not_exists(b"bucket").unwrap();
@@ -81,7 +83,7 @@ fn db_closed() {
 
 #[test]
 fn delete_bucket() {
-    let mut db = db_mock().build().unwrap();
+    let db = db_mock().build().unwrap();
     let mut tx = db.begin_rw_tx().unwrap();
     {
         drop(tx.create_bucket(b"bucket").unwrap());
@@ -96,7 +98,7 @@ fn delete_bucket() {
 ///ensure that writes produce idempotent file
 fn commit_hash_ensure() {
     for _ in 0..20 {
-        let mut db = db_mock().page_size(4096).autoremove(true).build().unwrap();
+        let db = db_mock().page_size(4096).autoremove(true).build().unwrap();
         let mut tx = db.begin_rw_tx().unwrap();
         {
             let mut bucket = tx.create_bucket(b"bucket").unwrap();
@@ -106,14 +108,16 @@ fn commit_hash_ensure() {
         }
         tx.commit().unwrap();
 
-        let mut file = db.0.file.try_write().unwrap();
-        file.flush().unwrap();
-        file.seek(SeekFrom::Start(0)).unwrap();
+        let mut storage = db.0.storage.try_write().unwrap();
+        let size = storage.size().unwrap();
 
         let mut hasher = FnvHasher::default();
         let mut buf = vec![0u8; db.page_size()];
-        while let Ok(()) = file.get_ref().read_exact(&mut buf) {
+        let mut offset = 0;
+        while offset < size {
+            storage.read_at(offset, &mut buf).unwrap();
             hasher.write(&buf);
+            offset += buf.len() as u64;
         }
         let hash = hasher.finish();
         assert_eq!(hash, 9680149046811131486)
@@ -124,7 +128,7 @@ fn commit_hash_ensure() {
 /// ensures that data actually written to disk
 fn commit_ensure() {
     let path = {
-        let mut db = db_mock().autoremove(false).build().unwrap();
+        let db = db_mock().autoremove(false).build().unwrap();
         let mut tx = db.begin_rw_tx().unwrap();
         {
             let mut bucket = tx.create_bucket(b"bucket").unwrap();
@@ -141,13 +145,13 @@ fn commit_ensure() {
         .read_only(false)
         .build()
         .unwrap();
-    db.view(Box::new(|tx| -> Result<(), String> {
+    db.view(|tx| -> Result<(), Error> {
         let bucket = tx.bucket(b"bucket").unwrap();
         assert_eq!(bucket.get(b"donald").expect("no donald"), b"trump");
         assert_eq!(bucket.get(b"barack").expect("no barack"), b"obama");
         assert_eq!(bucket.get(b"thomas").expect("no thomas"), b"jefferson");
         Ok(())
-    }))
+    })
     .unwrap();
 }
 
@@ -162,7 +166,7 @@ fn rollback_empty() {
 
 #[test]
 fn rollback_some() {
-    let mut db = db_mock().build().unwrap();
+    let db = db_mock().build().unwrap();
     let mut tx = db.begin_rw_tx().unwrap();
     {
         let mut bucket = tx.create_bucket(b"bucket").unwrap();
@@ -173,7 +177,7 @@ fn rollback_some() {
 
 #[test]
 fn for_each() {
-    let mut db = db_mock().build().unwrap();
+    let db = db_mock().build().unwrap();
     let mut tx = db.begin_rw_tx().unwrap();
     {
         let mut bucket = tx.create_bucket(b"bucket").unwrap();
@@ -198,7 +202,7 @@ fn for_each() {
 
 #[test]
 fn check() {
-    let mut db = db_mock().build().unwrap();
+    let db = db_mock().build().unwrap();
     {
         let mut tx = db.begin_rw_tx().unwrap();
         {
@@ -232,3 +236,213 @@ fn check_corrupted() {
         .unwrap();
     db.begin_tx().unwrap().check_sync().unwrap_err();
 }
+
+#[test]
+fn savepoint_rollback_to() {
+    let db = db_mock().build().unwrap();
+    let mut tx = db.begin_rw_tx().unwrap();
+    {
+        let mut bucket = tx.create_bucket(b"bucket").unwrap();
+        bucket.put(b"a", b"1".to_vec()).unwrap();
+        bucket.put(b"b", b"2".to_vec()).unwrap();
+    }
+
+    let savepoint = tx.savepoint().unwrap();
+    {
+        let mut bucket = tx.bucket_mut(b"bucket").unwrap();
+        bucket.put(b"a", b"changed".to_vec()).unwrap();
+        bucket.delete(b"b").unwrap();
+        bucket.put(b"c", b"3".to_vec()).unwrap();
+    }
+    let _ = tx.create_bucket(b"other").unwrap();
+    savepoint.rollback_to().unwrap();
+
+    {
+        let bucket = tx.bucket(b"bucket").unwrap();
+        assert_eq!(bucket.get(b"a"), Some(&b"1"[..]));
+        assert_eq!(bucket.get(b"b"), Some(&b"2"[..]));
+        assert_eq!(bucket.get(b"c"), None);
+    }
+    assert!(tx.bucket(b"other").is_err());
+
+    // transaction is still usable after rollback
+    tx.bucket_mut(b"bucket")
+        .unwrap()
+        .put(b"d", b"4".to_vec())
+        .unwrap();
+    tx.commit().unwrap();
+
+    db.view(|tx| -> Result<(), Error> {
+        let bucket = tx.bucket(b"bucket")?;
+        assert_eq!(bucket.get(b"a"), Some(&b"1"[..]));
+        assert_eq!(bucket.get(b"d"), Some(&b"4"[..]));
+        assert_eq!(bucket.get(b"c"), None);
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn savepoint_nested() {
+    let db = db_mock().build().unwrap();
+    let mut tx = db.begin_rw_tx().unwrap();
+    let _ = tx.create_bucket(b"bucket").unwrap();
+
+    let outer = tx.savepoint().unwrap();
+    tx.bucket_mut(b"bucket")
+        .unwrap()
+        .put(b"outer", vec![1])
+        .unwrap();
+
+    let inner = tx.savepoint().unwrap();
+    tx.bucket_mut(b"bucket")
+        .unwrap()
+        .put(b"inner", vec![2])
+        .unwrap();
+    inner.release().unwrap();
+
+    let discarded = tx.savepoint().unwrap();
+    tx.bucket_mut(b"bucket")
+        .unwrap()
+        .put(b"discarded", vec![3])
+        .unwrap();
+    let later = tx.savepoint().unwrap();
+    discarded.rollback_to().unwrap();
+
+    // rolling back discards savepoints created after
+    assert!(matches!(later.rollback_to(), Err(Error::SavepointGone)));
+    {
+        let bucket = tx.bucket(b"bucket").unwrap();
+        assert!(bucket.get(b"outer").is_some());
+        assert!(bucket.get(b"inner").is_some());
+        assert!(bucket.get(b"discarded").is_none());
+    }
+
+    outer.rollback_to().unwrap();
+    assert!(tx.bucket(b"bucket").unwrap().get(b"outer").is_none());
+    tx.commit().unwrap();
+}
+
+#[test]
+fn savepoint_pages() {
+    let db = db_mock().build().unwrap();
+    db.update(|tx| -> Result<(), Error> {
+        {
+            let mut bucket = tx.create_bucket(b"bucket")?;
+            for i in 0..1000u32 {
+                bucket.put(&i.to_be_bytes(), vec![0; 100])?;
+            }
+        }
+        tx.create_bucket(b"removed")?.put(b"key", vec![0; 5000])?;
+        Ok(())
+    })
+    .unwrap();
+
+    let mut tx = db.begin_rw_tx().unwrap();
+    let savepoint = tx.savepoint().unwrap();
+    let pgid = tx.pgid();
+    let pages = tx.0.pages.read().len();
+    let free = db.0.freelist.read().count();
+    {
+        let mut bucket = tx.bucket_mut(b"bucket").unwrap();
+        for i in 1000..2000u32 {
+            bucket.put(&i.to_be_bytes(), vec![1; 100]).unwrap();
+        }
+        for i in 0..500u32 {
+            bucket.delete(&i.to_be_bytes()).unwrap();
+        }
+    }
+    tx.delete_bucket(b"removed").unwrap();
+    let nested = tx.savepoint().unwrap();
+    assert!(tx.0.pages.read().len() > pages);
+    nested.release().unwrap();
+
+    savepoint.rollback_to().unwrap();
+    assert_eq!(tx.pgid(), pgid);
+    assert_eq!(tx.0.pages.read().len(), pages);
+    assert_eq!(db.0.freelist.read().count(), free);
+    assert!(tx.bucket(b"removed").is_ok());
+    tx.commit().unwrap();
+
+    db.view(|tx| -> Result<(), Error> {
+        let bucket = tx.bucket(b"bucket")?;
+        assert!(bucket.get(&0u32.to_be_bytes()).is_some());
+        assert!(bucket.get(&1000u32.to_be_bytes()).is_none());
+        tx.check_sync()
+    })
+    .unwrap();
+}
+
+#[test]
+fn savepoint_read_only() {
+    let mut tx = TxBuilder::new().build();
+    assert!(matches!(tx.savepoint(), Err(Error::TxReadonly)));
+}
+
+#[test]
+fn backup() {
+    let db = db_mock().build().unwrap();
+    db.update(|tx| -> Result<(), Error> {
+        let mut bucket = tx.create_bucket(b"bucket")?;
+        for i in 0..1000u32 {
+            bucket.put(&i.to_be_bytes(), vec![(i % 256) as u8; 2000])?;
+        }
+        Ok(())
+    })
+    .unwrap();
+
+    let path = temp_file();
+    let tx = db.begin_tx().unwrap();
+    let size = tx.pgid() * db.page_size() as u64;
+    let mut reports = vec![];
+    let start = Instant::now();
+    let written = tx
+        .backup()
+        .rate_limit(size * 5)
+        .progress(|copied, total| reports.push((copied, total)))
+        .sync(true)
+        .copy_to(&path)
+        .unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(150));
+    assert_eq!(written, size);
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), size);
+    assert!(reports.len() > 2);
+    assert_eq!(reports[0], (2, tx.pgid()));
+    assert_eq!(*reports.last().unwrap(), (tx.pgid(), tx.pgid()));
+    assert!(reports.windows(2).all(|w| w[0].0 < w[1].0));
+
+    // Cancelled backup keeps the previous one and leaves no file behind.
+    let previous = std::fs::read(&path).unwrap();
+    let cancel = Arc::new(AtomicBool::new(false));
+    let flag = cancel.clone();
+    let result = tx
+        .backup()
+        .cancel(cancel)
+        .progress(move |copied, _| flag.store(copied > 2, Ordering::Release))
+        .copy_to(&path);
+    assert!(matches!(result, Err(Error::BackupCancelled)));
+    assert_eq!(std::fs::read(&path).unwrap(), previous);
+    assert!(!super::backup::temp_path(&path).unwrap().exists());
+
+    let mut buf = vec![];
+    assert_eq!(tx.write_to(&mut buf).unwrap() as u64, size);
+    drop(tx);
+    std::fs::write(&path, buf).unwrap();
+
+    let db = DBBuilder::new(&path)
+        .autoremove(true)
+        .checkmode(CheckMode::PARANOID)
+        .build()
+        .unwrap();
+    db.view(|tx| -> Result<(), Error> {
+        let bucket = tx.bucket(b"bucket")?;
+        for i in 0..1000u32 {
+            assert_eq!(
+                bucket.get(&i.to_be_bytes()).unwrap(),
+                &vec![(i % 256) as u8; 2000][..]
+            );
+        }
+        tx.check_sync()
+    })
+    .unwrap();
+}
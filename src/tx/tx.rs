@@ -4,19 +4,21 @@ use parking_lot::{
 use std::collections::HashMap;
 use std::fmt;
 use std::fs::OpenOptions;
-use std::io::{copy, Cursor as IOCursor, Read, Seek, SeekFrom, Write};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{mpsc, Arc, Weak};
 use std::thread;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant};
 
 use crate::bucket::{Bucket, Cursor};
-use crate::consts::{Flags, IGNORE_NOSYNC, PGID, TXID};
-use crate::db::{CheckMode, WeakDB, DB};
+use crate::consts::{Flags, PGID, PGID_NO_FREELIST, TXID};
+use crate::db::{CheckMode, SyncMode, WeakDB, DB};
 use crate::errors::Error;
 use crate::meta::Meta;
 use crate::page::{OwnedPage, Page, PageInfo};
 
+use super::backup::Backup;
+use super::savepoint::Savepoint;
 use super::stats::TxStats;
 
 pub(crate) struct TxInner {
@@ -33,6 +35,9 @@ pub(crate) struct TxInner {
     /// if transaction closed then ref points to null
     pub(crate) db: RwLock<WeakDB>,
 
+    /// whether commit releases free pages at the end of the file
+    pub(crate) shrink: AtomicBool,
+
     /// transaction meta
     pub(crate) meta: RwLock<Meta>,
 
@@ -46,6 +51,12 @@ pub(crate) struct TxInner {
     /// transactions statistics
     pub(crate) stats: Mutex<TxStats>,
 
+    /// ids of savepoints which are not released yet
+    pub(crate) savepoints: Mutex<Vec<u64>>,
+
+    /// id of the next savepoint
+    pub(crate) savepoint_seq: AtomicU64,
+
     /// list of callbacks that will be called after commit
     pub(crate) commit_handlers: Mutex<Vec<Box<dyn Fn()>>>,
 
@@ -136,13 +147,57 @@ impl Tx {
         Ok(())
     }
 
+    /// Creates a savepoint which allows to undo changes made after it
+    /// without rolling back the whole transaction.
+    ///
+    /// Changes made so far are written to dirty pages, so nodes are
+    /// read again from them after savepoint.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use nut::DBBuilder;
+    ///
+    /// let db = DBBuilder::new("./test.db").build().unwrap();
+    /// let mut tx = db.begin_rw_tx().unwrap();
+    /// tx.create_bucket(b"bucket").unwrap().put(b"a", vec![1]).unwrap();
+    ///
+    /// let savepoint = tx.savepoint().unwrap();
+    /// tx.bucket_mut(b"bucket").unwrap().put(b"b", vec![2]).unwrap();
+    /// savepoint.rollback_to().unwrap();
+    ///
+    /// assert!(tx.bucket(b"bucket").unwrap().get(b"b").is_none());
+    /// tx.commit().unwrap();
+    /// ```
+    pub fn savepoint(&mut self) -> Result<Savepoint, Error> {
+        if !self.writable() {
+            return Err(Error::TxReadonly);
+        };
+        if !self.opened() {
+            return Err(Error::TxClosed);
+        };
+
+        {
+            let mut root = self.0.root.try_write().unwrap();
+            root.rebalance();
+            root.spill()?;
+            self.0.meta.try_write().unwrap().root = root.bucket.clone();
+
+            // Spilled nodes are dropped, further changes materialize
+            // them again from dirty pages.
+            root.clear();
+        }
+
+        Savepoint::new(self)
+    }
+
     /// Adds a handler function to be executed after the transaction successfully commits.
     pub fn on_commit(&mut self, handler: Box<dyn Fn()>) {
         self.0.commit_handlers.lock().push(handler);
     }
 
     /// Returns current database size in bytes as seen by this transaction.
-    pub(super) fn size(&self) -> i64 {
+    pub(crate) fn size(&self) -> i64 {
         self.pgid() as i64 * self.db().unwrap().page_size() as i64
     }
 
@@ -167,7 +222,14 @@ impl Tx {
     pub fn bucket(&self, key: &[u8]) -> Result<MappedRwLockReadGuard<Bucket>, Error> {
         let bucket = self.0.root.try_read().ok_or("Can't acquire bucket")?;
 
-        RwLockReadGuard::try_map(bucket, |b| b.bucket(key)).map_err(|_| "Can't get bucket".into())
+        let mut err = None;
+        RwLockReadGuard::try_map(bucket, |b| {
+            b.try_bucket(key).unwrap_or_else(|e| {
+                err = Some(e);
+                None
+            })
+        })
+        .map_err(|_| err.unwrap_or_else(|| "Can't get bucket".into()))
     }
 
     /// Bucket retrieves a mutable bucket by name.
@@ -180,8 +242,14 @@ impl Tx {
 
         let bucket = self.0.root.try_write().ok_or("Can't acquire bucket")?;
 
-        RwLockWriteGuard::try_map(bucket, |b| b.bucket_mut(key))
-            .map_err(|_| "Can't get bucket".into())
+        let mut err = None;
+        RwLockWriteGuard::try_map(bucket, |b| {
+            b.try_bucket_mut(key).unwrap_or_else(|e| {
+                err = Some(e);
+                None
+            })
+        })
+        .map_err(|_| err.unwrap_or_else(|| "Can't get bucket".into()))
     }
 
     /// returns bucket keys for db
@@ -203,6 +271,30 @@ impl Tx {
             .map_err(|_| "Can't get bucket".into())
     }
 
+    /// Creates a new bucket which keeps keys in order of comparator
+    /// registered under given id with DBBuilder.comparator.
+    /// Returns ComparatorNotFound error if comparator is not registered,
+    /// otherwise same errors as create_bucket.
+    pub fn create_bucket_with_comparator(
+        &mut self,
+        key: &[u8],
+        comparator_id: u32,
+    ) -> Result<MappedRwLockWriteGuard<'_, Bucket>, Error> {
+        if !self.0.writable {
+            return Err(Error::TxReadonly);
+        };
+
+        let bucket = self.0.root.try_write().ok_or("Can't acquire bucket")?;
+
+        let mut err = None;
+        RwLockWriteGuard::try_map(bucket, |b| {
+            b.create_bucket_with_comparator(key, comparator_id)
+                .map_err(|e| err = Some(e))
+                .ok()
+        })
+        .map_err(|_| err.unwrap_or_else(|| "Can't get bucket".into()))
+    }
+
     /// Creates a new bucket if it doesn't already exist.
     /// Returns an error if the bucket name is blank, or if the bucket name is too long.
     /// The bucket instance is only valid for the lifetime of the transaction.
@@ -241,57 +333,42 @@ impl Tx {
     ) -> Result<(), Error> {
         let root = self.0.root.try_write().unwrap();
         root.for_each(Box::new(|k: &[u8], _v: Option<&[u8]>| -> Result<(), E> {
-            handler(k, root.bucket(k))
+            handler(k, root.bucket(k).ok())
         }))
     }
 
+    /// Returns builder which copies the entire database from this transaction,
+    /// with throttling, progress reporting and cancellation.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use nut::DBBuilder;
+    ///
+    /// let db = DBBuilder::new("./test.db").build().unwrap();
+    /// let tx = db.begin_tx().unwrap();
+    /// tx.backup()
+    ///     .rate_limit(16 << 20)
+    ///     .progress(|copied, total| println!("{}/{} pages", copied, total))
+    ///     .sync(true)
+    ///     .copy_to("./test.backup.db")
+    ///     .unwrap();
+    /// ```
+    pub fn backup(&self) -> Backup<'_> {
+        Backup::new(self)
+    }
+
     /// Writes the entire database to a writer.
     /// If err == nil then exactly tx.Size() bytes will be written into the writer.
-    pub fn write_to<W: Write>(&self, mut w: W) -> Result<i64, Error> {
-        let db = self.db()?;
-        let page_size = db.page_size();
-        let mut file =
-            db.0.file
-                .try_write()
-                .ok_or("can't obtain file write access")?;
-
-        let mut written = 0;
-
-        let mut page = OwnedPage::new(page_size);
-        page.flags = Flags::META;
-
-        // first page
-        {
-            *page.meta_mut() = self.0.meta.try_read().unwrap().clone();
-            page.meta_mut().checksum = page.meta().sum64();
-            w.write_all(&page.buf()).map_err(|e| format!("{}", e))?;
-            written += page.size();
-        }
-
-        // second page
-        {
-            page.id = 1;
-            page.meta_mut().txid -= 1;
-            page.meta_mut().checksum = page.meta().sum64();
-            w.write_all(&page.buf()).map_err(|e| format!("{}", e))?;
-            written += page.size();
-        }
-
-        file.seek(SeekFrom::Start(page_size as u64 * 2))
-            .map_err(|e| format!("{}", e))?;
-
-        let size = self.size() as u64 - (page_size as u64 * 2);
-        written += copy(&mut Read::by_ref(&mut file.get_mut()).take(size), &mut w)
-            .map_err(|e| format!("{}", e))? as usize;
-
-        Ok(written as i64)
+    pub fn write_to<W: Write>(&self, w: W) -> Result<i64, Error> {
+        Ok(self.backup().write_to(w)? as i64)
     }
 
     /// Copies the entire database to file at the given path.
     /// A reader transaction is maintained during the copy so it is safe to continue
     /// using the database while a copy is in progress.
     pub fn copy_to(&self, path: &str, mode: OpenOptions) -> Result<(), Error> {
-        let file = mode.open(path).map_err(|e| e.to_string())?;
+        let file = mode.open(path)?;
         self.write_to(file)?;
         Ok(())
     }
@@ -320,57 +397,43 @@ impl Tx {
         let mut db = self.db()?;
 
         {
-            let start_time = SystemTime::now();
+            let start_time = Instant::now();
             self.0.root.try_write().unwrap().rebalance();
             let mut stats = self.0.stats.lock();
             if stats.rebalance > 0 {
-                stats.rebalance_time += SystemTime::now().duration_since(start_time)?;
+                stats.rebalance_time += start_time.elapsed();
             };
         }
 
         {
             // spill
-            let start_time = SystemTime::now();
+            let start_time = Instant::now();
             {
                 let mut root = self.0.root.try_write().unwrap();
                 root.spill()?;
             }
-            self.0.stats.try_lock().unwrap().spill_time =
-                SystemTime::now().duration_since(start_time)?;
+            self.0.stats.try_lock().unwrap().spill_time = start_time.elapsed();
         }
 
         // Free the old root bucket.
         self.0.meta.try_write().unwrap().root.root = self.0.root.try_read().unwrap().bucket.root;
 
-        let (txid, tx_pgid, freelist_pgid) = {
-            let meta = self.0.meta.try_read().unwrap();
-            (meta.txid as usize, meta.pgid, meta.freelist)
-        };
-
-        let (freelist_size, page_size) = {
-            // Free the freelist and allocate new pages for it. This will overestimate
-            // the size of the freelist but not underestimate the size (which would be bad).
-            let page = db.page(freelist_pgid);
-            let mut freelist = db.0.freelist.try_write().unwrap();
-            freelist.free(txid as u64, &page)?;
-
-            let freelist_size = freelist.size();
-            let page_size = db.page_size() as usize;
-
-            (freelist_size, page_size)
-        };
+        let tx_pgid = self.0.meta.try_read().unwrap().pgid;
+        let page_size = db.page_size();
+        let shrink = db.0.auto_shrink || self.0.shrink.load(Ordering::Acquire);
 
         {
-            let page = self.allocate((freelist_size / page_size) as u64 + 1);
-            if let Err(e) = page {
+            // Drop free pages at the end of the file before the freelist
+            // is allocated, so it isn't written past them.
+            if shrink {
+                let pgid = db.0.freelist.try_write().unwrap().trim(tx_pgid);
+                self.set_pgid(pgid)?;
+            }
+
+            if let Err(e) = self.commit_freelist(&db) {
                 self.rollback()?;
                 return Err(e);
             }
-            let page = page?;
-            let page = unsafe { &mut *page };
-
-            db.0.freelist.try_write().unwrap().write(page);
-            self.0.meta.try_write().unwrap().freelist = page.id;
 
             // If the high water mark has moved up then attempt to grow the database.
             if self.pgid() > tx_pgid as u64 {
@@ -381,7 +444,7 @@ impl Tx {
             }
 
             // Write dirty pages to disk.
-            let write_start_time = SystemTime::now();
+            let write_start_time = Instant::now();
             if let Err(e) = self.write() {
                 self.rollback()?;
                 return Err(e);
@@ -408,8 +471,15 @@ impl Tx {
                 return Err(e);
             };
 
-            self.0.stats.try_lock().unwrap().write_time +=
-                SystemTime::now().duration_since(write_start_time)?;
+            self.0.stats.try_lock().unwrap().write_time += write_start_time.elapsed();
+        };
+
+        // Truncate the file while no other writer can extend it. Transaction is
+        // already committed, so the error is reported only after finalizing it.
+        let truncated = if shrink {
+            db.truncate(self.pgid())
+        } else {
+            Ok(())
         };
 
         // Finalize the transaction.
@@ -421,6 +491,31 @@ impl Tx {
             }
         }
 
+        truncated
+    }
+
+    /// Frees the old freelist page and writes the freelist to a new one,
+    /// or marks meta as having no freelist if database is opened with no_freelist_sync.
+    fn commit_freelist(&mut self, db: &DB) -> Result<(), Error> {
+        let meta = self.0.meta.try_read().unwrap().clone();
+
+        // Free the freelist and allocate new pages for it. This will overestimate
+        // the size of the freelist but not underestimate the size (which would be bad).
+        if meta.has_freelist() {
+            let page = db.page(meta.freelist);
+            db.0.freelist.try_write().unwrap().free(meta.txid, &page)?;
+        }
+        if db.0.no_freelist_sync {
+            self.0.meta.try_write().unwrap().freelist = PGID_NO_FREELIST;
+            return Ok(());
+        }
+
+        let freelist_size = db.0.freelist.try_read().unwrap().size();
+        let page = self.allocate((freelist_size / db.page_size()) as u64 + 1)?;
+        let page = unsafe { &mut *page };
+
+        db.0.freelist.try_write().unwrap().write(page);
+        self.0.meta.try_write().unwrap().freelist = page.id;
         Ok(())
     }
 
@@ -450,9 +545,13 @@ impl Tx {
             let txid = self.id();
             let mut freelist = db.0.freelist.write();
             freelist.rollback(txid);
-            let freelist_id = db.meta()?.freelist;
-            let freelist_page = db.page(freelist_id);
-            freelist.reload(&freelist_page);
+            let meta = db.meta()?;
+            if meta.has_freelist() {
+                let freelist_page = db.page(meta.freelist);
+                freelist.reload(&freelist_page);
+            } else {
+                freelist.reload_free(&db.free_pages(&meta));
+            }
         };
         self.close()?;
         Ok(())
@@ -518,15 +617,17 @@ impl Tx {
         let mut reachable = HashMap::new();
         reachable.insert(0, true);
         reachable.insert(1, true);
-        let freelist_pgid = self
+        let meta = self
             .0
             .meta
             .try_read_for(Duration::from_secs(10))
             .unwrap()
-            .freelist;
-        let freelist_overflow = unsafe { &*self.page(freelist_pgid).unwrap() }.overflow;
-        for i in 0..=freelist_overflow {
-            reachable.insert(freelist_pgid + u64::from(i), true);
+            .clone();
+        if meta.has_freelist() {
+            let freelist_overflow = unsafe { &*self.page(meta.freelist).unwrap() }.overflow;
+            for i in 0..=freelist_overflow {
+                reachable.insert(meta.freelist + u64::from(i), true);
+            }
         }
 
         self.check_bucket(
@@ -585,14 +686,23 @@ impl Tx {
 
         b.tx().unwrap().for_each_page(b.bucket.root, 0, handler);
 
-        b.for_each(Box::new(|k, _v| -> Result<(), String> {
-            let child = b.bucket(k);
-            if let Some(child) = child {
-                self.check_bucket(child, reachable, freed, ch);
+        b.for_each_stored_bucket(|_, child| {
+            let child = match child {
+                Ok(child) => child,
+                Err(e) => return ch.send(e.to_string()).unwrap(),
             };
-            Ok(())
-        }))
-        .unwrap();
+            self.check_bucket(&child, reachable, freed, ch);
+            if child.holds_indexes {
+                child.for_each_stored_bucket(|name, index| match index {
+                    Ok(index) => {
+                        for e in b.check_index(name, &index) {
+                            ch.send(e).unwrap();
+                        }
+                    }
+                    Err(e) => ch.send(e.to_string()).unwrap(),
+                });
+            }
+        });
     }
 
     /// Returns a contiguous block of memory starting at a given page.
@@ -639,13 +749,14 @@ impl Tx {
             let offset = *id as u64 * page_size as u64;
 
             let buf = unsafe { std::slice::from_raw_parts(p.as_ptr(), size) };
-            let cursor = IOCursor::new(buf);
 
-            db.write_at(offset, cursor)?;
+            db.write_at(offset, buf)?;
         }
 
-        if !db.0.no_sync || IGNORE_NOSYNC {
+        if db.sync_mode() == SyncMode::Full {
             db.sync()?;
+        } else {
+            db.flush()?;
         }
 
         {
@@ -671,13 +782,19 @@ impl Tx {
     pub(crate) fn write_meta(&mut self) -> Result<(), Error> {
         let mut db = self.db()?;
 
-        let mut buf = vec![0u8; db.page_size() as usize];
+        let page_size = db.page_size();
+        let mut buf = vec![0u8; page_size];
         let mut page = Page::from_buf_mut(&mut buf);
         self.0.meta.try_write().unwrap().write(&mut page)?;
 
-        db.write_at(0, IOCursor::new(buf))?;
+        // Meta pages are written in turns, so torn write of one
+        // leaves the other one valid.
+        let offset = page.id * page_size as u64;
+        db.write_at(offset, &buf)?;
 
-        if !db.0.no_sync || IGNORE_NOSYNC {
+        if db.sync_mode() == SyncMode::None {
+            db.flush()?;
+        } else {
             db.sync()?;
         }
 
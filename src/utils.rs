@@ -93,9 +93,18 @@ pub fn clamp<T: PartialOrd>(n: T, min: T, max: T) -> T {
     n
 }
 
+/// returns the smallest key greater than all keys starting with prefix,
+/// None if there is no such key
+pub fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let len = prefix.iter().rposition(|b| *b != u8::MAX)? + 1;
+    let mut end = prefix[..len].to_vec();
+    end[len - 1] += 1;
+    Some(end)
+}
+
 #[cfg(test)]
 pub mod tests {
-    use super::{find_contiguous, slice_to_u8_slice, to_u8_slice};
+    use super::{find_contiguous, prefix_end, slice_to_u8_slice, to_u8_slice};
 
     #[test]
     fn test_find_contiguous() {
@@ -130,4 +139,12 @@ pub mod tests {
             slice_to_u8_slice(sl)
         });
     }
+
+    #[test]
+    fn test_prefix_end() {
+        assert_eq!(prefix_end(b"user/"), Some(b"user0".to_vec()));
+        assert_eq!(prefix_end(&[1, 255, 255]), Some(vec![2]));
+        assert_eq!(prefix_end(&[255, 255]), None);
+        assert_eq!(prefix_end(&[]), None);
+    }
 }